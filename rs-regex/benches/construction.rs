@@ -0,0 +1,48 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rs_regex::nfa::Construction;
+use rs_regex::parser::Parser;
+use rs_regex::regex::Regex;
+use rs_regex::scanner::Scanner;
+
+const REGEXES: [&str; 3] = [
+    "aa(b|cc)*a",
+    "(0|(-|()*)(1|2|3|4|5|6|7|8|9)(0|1|2|3|4|5|6|7|8|9)*)",
+    "(a|b|c|d|e|f|g|h|i|j|k|l|m|n|o|p|q|r|s|t|u|v|w|x|y|z)*",
+];
+
+const CONSTRUCTIONS: [Construction; 2] = [Construction::Thompson, Construction::Glushkov];
+
+fn compile(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compile");
+    for (i, regex) in REGEXES.iter().enumerate() {
+        for construction in CONSTRUCTIONS {
+            let id = BenchmarkId::new(format!("{:?}", construction), i);
+            group.bench_with_input(id, regex, |b, regex| {
+                b.iter(|| {
+                    let scanner = Scanner::new(regex.to_string());
+                    let mut parser = Parser::new(scanner);
+                    parser.expr_with(construction).unwrap()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn matches(c: &mut Criterion) {
+    let input = "-1234567890".repeat(10);
+    let mut group = c.benchmark_group("matches");
+    for (i, regex) in REGEXES.iter().enumerate() {
+        for construction in CONSTRUCTIONS {
+            let compiled = Regex::with_construction(regex.to_string(), construction).unwrap();
+            let id = BenchmarkId::new(format!("{:?}", construction), i);
+            group.bench_with_input(id, &input, |b, input| {
+                b.iter(|| compiled.matches(input.to_string()).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, compile, matches);
+criterion_main!(benches);
@@ -1,21 +1,346 @@
-use crate::nfa_fragment::NFAFragment;
+use crate::classes::Alphabet;
+use crate::derivative::DerivativeDFA;
+use crate::dfa::DfaLimits;
+use crate::look::Look;
+use crate::nfa_fragment::{NFAFragment, StateArena};
+use crate::utf8;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
 
+/// A pattern over bytes, or over symbols of any ordered type when it is
+/// built with `Node::symbol` and `Node::symbols`
 #[derive(Debug)]
-pub enum Node {
-    Character { character: u8 },
-    Union { left: Box<Node>, right: Box<Node> },
-    Concat { left: Box<Node>, right: Box<Node> },
-    Star { operand: Box<Node> },
+pub enum Node<S = u8> {
+    Empty,
+    Nothing,
+    Character {
+        character: S,
+    },
+    Class {
+        ranges: Vec<(S, S)>,
+    },
+    Look {
+        look: Look,
+    },
+    Union {
+        left: Box<Node<S>>,
+        right: Box<Node<S>>,
+    },
+    Concat {
+        left: Box<Node<S>>,
+        right: Box<Node<S>>,
+    },
+    /// '*', or '*?' when not greedy. Greediness only matters for the
+    /// leftmost-first matches, the language is the same.
+    Star {
+        operand: Box<Node<S>>,
+        greedy: bool,
+    },
+    /// '+', or '+?' when not greedy. The same as the operand followed by its
+    /// star, without a second copy of the operand.
+    Plus {
+        operand: Box<Node<S>>,
+        greedy: bool,
+    },
+    /// A group in parentheses, numbered from 1 in the order of the '('
+    Capture {
+        index: usize,
+        operand: Box<Node<S>>,
+    },
+    /// `\1`, the same bytes as the latest match of the group
+    Backreference {
+        index: usize,
+    },
+    /// `(?=...)`, or `(?!...)` when negated
+    Lookahead {
+        negated: bool,
+        operand: Box<Node<S>>,
+    },
+    /// `(?<=...)`, or `(?<!...)` when negated
+    Lookbehind {
+        negated: bool,
+        operand: Box<Node<S>>,
+    },
+    And {
+        left: Box<Node<S>>,
+        right: Box<Node<S>>,
+    },
+    Not {
+        operand: Box<Node<S>>,
+    },
+}
+
+/// The operator of a node or the leaf itself, without the operands. Two
+/// trees are compared and hashed by the labels of their nodes in pre-order,
+/// which determine the tree since each label has a fixed number of operands.
+#[derive(PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Label<'a, S> {
+    Empty,
+    Nothing,
+    Character(&'a S),
+    Class(&'a [(S, S)]),
+    Look(Look),
+    Union,
+    Concat,
+    Star(bool),
+    Plus(bool),
+    Capture(usize),
+    Backreference(usize),
+    Lookahead(bool),
+    Lookbehind(bool),
+    And,
+    Not,
+}
+
+impl<S> Node<S> {
+    /// Moves the node out, leaving the empty string in its place
+    pub fn take(&mut self) -> Node<S> {
+        std::mem::replace(self, Node::Empty)
+    }
+
+    /// The operands of the node
+    pub fn children(&self) -> Vec<&Node<S>> {
+        match self {
+            Node::Union { left, right }
+            | Node::Concat { left, right }
+            | Node::And { left, right } => vec![left, right],
+            Node::Star { operand, .. }
+            | Node::Plus { operand, .. }
+            | Node::Not { operand }
+            | Node::Capture { operand, .. }
+            | Node::Lookahead { operand, .. }
+            | Node::Lookbehind { operand, .. } => vec![operand],
+            Node::Empty
+            | Node::Nothing
+            | Node::Character { .. }
+            | Node::Class { .. }
+            | Node::Look { .. }
+            | Node::Backreference { .. } => Vec::new(),
+        }
+    }
+
+    /// Moves the operands that have operands of their own onto the stack,
+    /// so that the node is dropped without recursing
+    fn take_operands(&mut self, nodes: &mut Vec<Node<S>>) {
+        let mut take = |operand: &mut Node<S>| {
+            if matches!(
+                operand,
+                Node::Union { .. }
+                    | Node::Concat { .. }
+                    | Node::And { .. }
+                    | Node::Star { .. }
+                    | Node::Plus { .. }
+                    | Node::Not { .. }
+                    | Node::Capture { .. }
+                    | Node::Lookahead { .. }
+                    | Node::Lookbehind { .. }
+            ) {
+                nodes.push(operand.take());
+            }
+        };
+        match self {
+            Node::Union { left, right }
+            | Node::Concat { left, right }
+            | Node::And { left, right } => {
+                take(left);
+                take(right);
+            }
+            Node::Star { operand, .. }
+            | Node::Plus { operand, .. }
+            | Node::Not { operand }
+            | Node::Capture { operand, .. }
+            | Node::Lookahead { operand, .. }
+            | Node::Lookbehind { operand, .. } => take(operand),
+            _ => {}
+        }
+    }
+
+    fn label(&self) -> Label<'_, S> {
+        match self {
+            Node::Empty => Label::Empty,
+            Node::Nothing => Label::Nothing,
+            Node::Character { character } => Label::Character(character),
+            Node::Class { ranges } => Label::Class(ranges),
+            Node::Look { look } => Label::Look(*look),
+            Node::Union { .. } => Label::Union,
+            Node::Concat { .. } => Label::Concat,
+            Node::Star { greedy, .. } => Label::Star(*greedy),
+            Node::Plus { greedy, .. } => Label::Plus(*greedy),
+            Node::Capture { index, .. } => Label::Capture(*index),
+            Node::Backreference { index } => Label::Backreference(*index),
+            Node::Lookahead { negated, .. } => Label::Lookahead(*negated),
+            Node::Lookbehind { negated, .. } => Label::Lookbehind(*negated),
+            Node::And { .. } => Label::And,
+            Node::Not { .. } => Label::Not,
+        }
+    }
+}
+
+/// The derived implementations recurse once per level, and a long pattern
+/// such as 'aaa...' is as deep as it is long. The trees are cloned,
+/// compared, hashed and dropped with explicit stacks instead.
+impl<S> Drop for Node<S> {
+    fn drop(&mut self) {
+        let mut nodes = Vec::new();
+        self.take_operands(&mut nodes);
+        while let Some(mut node) = nodes.pop() {
+            node.take_operands(&mut nodes);
+        }
+    }
+}
+
+impl<S: Clone> Clone for Node<S> {
+    fn clone(&self) -> Self {
+        /// A node is built once its operands are cloned
+        enum Step<'a, S> {
+            Clone(&'a Node<S>),
+            Build(&'a Node<S>),
+        }
+
+        let mut steps = vec![Step::Clone(self)];
+        let mut cloned: Vec<Node<S>> = Vec::new();
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Clone(node) => {
+                    steps.push(Step::Build(node));
+                    steps.extend(node.children().into_iter().rev().map(Step::Clone));
+                }
+                Step::Build(node) => {
+                    let operands = cloned.split_off(cloned.len() - node.children().len());
+                    let mut operands = operands.into_iter().map(Box::new);
+                    let mut operand = || operands.next().unwrap();
+                    cloned.push(match node {
+                        Node::Empty => Node::Empty,
+                        Node::Nothing => Node::Nothing,
+                        Node::Character { character } => Node::Character {
+                            character: character.clone(),
+                        },
+                        Node::Class { ranges } => Node::Class {
+                            ranges: ranges.clone(),
+                        },
+                        Node::Look { look } => Node::Look { look: *look },
+                        Node::Union { .. } => Node::Union {
+                            left: operand(),
+                            right: operand(),
+                        },
+                        Node::Concat { .. } => Node::Concat {
+                            left: operand(),
+                            right: operand(),
+                        },
+                        Node::Star { greedy, .. } => Node::Star {
+                            operand: operand(),
+                            greedy: *greedy,
+                        },
+                        Node::Plus { greedy, .. } => Node::Plus {
+                            operand: operand(),
+                            greedy: *greedy,
+                        },
+                        Node::Capture { index, .. } => Node::Capture {
+                            index: *index,
+                            operand: operand(),
+                        },
+                        Node::Backreference { index } => Node::Backreference { index: *index },
+                        Node::Lookahead { negated, .. } => Node::Lookahead {
+                            negated: *negated,
+                            operand: operand(),
+                        },
+                        Node::Lookbehind { negated, .. } => Node::Lookbehind {
+                            negated: *negated,
+                            operand: operand(),
+                        },
+                        Node::And { .. } => Node::And {
+                            left: operand(),
+                            right: operand(),
+                        },
+                        Node::Not { .. } => Node::Not { operand: operand() },
+                    });
+                }
+            }
+        }
+        cloned.pop().unwrap()
+    }
+}
+
+impl<S: PartialEq> PartialEq for Node<S> {
+    fn eq(&self, other: &Self) -> bool {
+        let mut pairs = vec![(self, other)];
+        while let Some((left, right)) = pairs.pop() {
+            if left.label() != right.label() {
+                return false;
+            }
+            pairs.extend(left.children().into_iter().zip(right.children()));
+        }
+        true
+    }
+}
+
+impl<S: Eq> Eq for Node<S> {}
+
+impl<S: Ord> Ord for Node<S> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let mut pairs = vec![(self, other)];
+        while let Some((left, right)) = pairs.pop() {
+            match left.label().cmp(&right.label()) {
+                Ordering::Equal => {
+                    pairs.extend(left.children().into_iter().zip(right.children()).rev())
+                }
+                ordering => return ordering,
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl<S: Ord> PartialOrd for Node<S> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<S: Hash> Hash for Node<S> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut nodes = vec![self];
+        while let Some(node) = nodes.pop() {
+            node.label().hash(state);
+            nodes.extend(node.children().into_iter().rev());
+        }
+    }
 }
 
 /// Abstract syntax tree (AST) nodes
-impl Node {
-    pub fn character(character: u8) -> Self {
-        Node::Character { character }
+impl<S: Ord + Clone> Node<S> {
+    /// The empty string (epsilon)
+    pub fn empty() -> Self {
+        Node::Empty
+    }
+
+    /// The empty language, matches nothing
+    pub fn nothing() -> Self {
+        Node::Nothing
+    }
+
+    /// A single symbol, the same as a character over bytes
+    pub fn symbol(symbol: S) -> Self {
+        Node::Character { character: symbol }
+    }
+
+    /// A set of symbols, given as inclusive ranges. The ranges are sorted
+    /// and the overlapping ones are merged.
+    pub fn symbols(mut ranges: Vec<(S, S)>) -> Self {
+        ranges.sort();
+        let mut merged: Vec<(S, S)> = Vec::new();
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some((_, last)) if start <= *last => *last = last.clone().max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        Node::Class { ranges: merged }
     }
 
     /// '|' operator in regex
-    pub fn union(left: Node, right: Node) -> Self {
+    pub fn union(left: Node<S>, right: Node<S>) -> Self {
         Node::Union {
             left: Box::new(left),
             right: Box::new(right),
@@ -23,7 +348,7 @@ impl Node {
     }
 
     /// contatenation in regex, such as 'aa'
-    pub fn concat(left: Node, right: Node) -> Self {
+    pub fn concat(left: Node<S>, right: Node<S>) -> Self {
         Node::Concat {
             left: Box::new(left),
             right: Box::new(right),
@@ -31,81 +356,413 @@ impl Node {
     }
 
     /// '*' operator in regex
-    pub fn star(operand: Node) -> Self {
+    pub fn star(operand: Node<S>) -> Self {
         Node::Star {
             operand: Box::new(operand),
+            greedy: true,
         }
     }
 
-    /// Recursively converts the AST into NFA fragments
-    pub fn to_fragment(self, counter: &mut Counter) -> NFAFragment {
-        match self {
-            Node::Character { character } => {
-                let lhs = counter.new_state();
-                let rhs = counter.new_state();
-                let mut fragment = NFAFragment::new(lhs, vec![rhs].into_iter().collect());
-                fragment.create_transition(lhs, Some(character), rhs);
-                fragment
-            }
+    /// '*?' operator in regex, repeats the operand as few times as possible
+    pub fn lazy_star(operand: Node<S>) -> Self {
+        Node::Star {
+            operand: Box::new(operand),
+            greedy: false,
+        }
+    }
 
-            Node::Union { left, right } => {
-                let lhs = left.to_fragment(counter);
-                let rhs = right.to_fragment(counter);
-                let mut fragment = lhs.union_operator(&rhs);
+    /// '+' operator in regex, one or more times
+    pub fn plus(operand: Node<S>) -> Self {
+        Node::Plus {
+            operand: Box::new(operand),
+            greedy: true,
+        }
+    }
 
-                let start = counter.new_state();
-                fragment.create_transition(start, None, lhs.start_state);
-                fragment.create_transition(start, None, rhs.start_state);
+    /// '+?' operator in regex, repeats the operand as few times as possible
+    pub fn lazy_plus(operand: Node<S>) -> Self {
+        Node::Plus {
+            operand: Box::new(operand),
+            greedy: false,
+        }
+    }
 
-                fragment.start_state = start;
-                fragment.accept_states = &lhs.accept_states | &rhs.accept_states;
-                fragment
+    /// A numbered group, can be referred to with a backreference
+    pub fn capture(index: usize, operand: Node<S>) -> Self {
+        Node::Capture {
+            index,
+            operand: Box::new(operand),
+        }
+    }
+
+    /// Intersection of two languages
+    pub fn intersection(left: Node<S>, right: Node<S>) -> Self {
+        Node::And {
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    /// Complement of a language
+    pub fn complement(operand: Node<S>) -> Self {
+        Node::Not {
+            operand: Box::new(operand),
+        }
+    }
+
+    /// Checks if the node or any node below it satisfies the predicate. The
+    /// tree is walked without recursion, so it can be arbitrarily deep.
+    pub fn contains(&self, predicate: &dyn Fn(&Node<S>) -> bool) -> bool {
+        let mut nodes = vec![self];
+        while let Some(node) = nodes.pop() {
+            if predicate(node) {
+                return true;
             }
+            nodes.extend(node.children());
+        }
+        false
+    }
 
-            Node::Concat { left, right } => {
-                let lhs = left.to_fragment(counter);
-                let rhs = right.to_fragment(counter);
-                let mut fragment = lhs.union_operator(&rhs);
+    /// Checks if the node contains assertions such as '^' or '\b'
+    pub fn has_looks(&self) -> bool {
+        self.contains(&|node| matches!(node, Node::Look { .. }))
+    }
 
-                for state in lhs.accept_states {
-                    fragment.create_transition(state, None, rhs.start_state);
-                }
+    /// Checks if the node contains backreferences, which makes it
+    /// non-regular
+    pub fn has_backreferences(&self) -> bool {
+        self.contains(&|node| matches!(node, Node::Backreference { .. }))
+    }
+
+    /// Checks if the node contains lookaheads or lookbehinds
+    pub fn has_lookarounds(&self) -> bool {
+        self.contains(&|node| matches!(node, Node::Lookahead { .. } | Node::Lookbehind { .. }))
+    }
 
-                fragment.start_state = lhs.start_state;
-                fragment.accept_states = rhs.accept_states;
-                fragment
+    /// The largest group index in the node
+    pub fn group_count(&self) -> usize {
+        let mut count = 0;
+        let mut nodes = vec![self];
+        while let Some(node) = nodes.pop() {
+            if let Node::Capture { index, .. } = node {
+                count = count.max(*index);
             }
+            nodes.extend(node.children());
+        }
+        count
+    }
 
-            Node::Star { operand } => {
-                let lhs = operand.to_fragment(counter);
-                let mut fragment = lhs.create_skeleton();
-                let start = counter.new_state();
-                fragment.start_state = start;
+    /// The same pattern over bytes, with every symbol replaced by the number
+    /// of its interval in the alphabet of the pattern. The automata are
+    /// built from the encoded pattern. Assertions, backreferences and
+    /// lookarounds are about bytes, so they are not supported over other
+    /// symbols.
+    pub fn encode(self) -> Result<(Node, Alphabet<S>), String> {
+        /// An operator waits on the stack until its operands are encoded
+        enum Step<S> {
+            Encode(Node<S>),
+            Union,
+            Concat,
+            Star(bool),
+            Plus(bool),
+            Capture(usize),
+            And,
+            Not,
+        }
 
-                for state in lhs.accept_states.iter() {
-                    fragment.create_transition(*state, None, lhs.start_state);
+        let mut ranges = Vec::new();
+        let mut nodes = vec![&self];
+        while let Some(node) = nodes.pop() {
+            match node {
+                Node::Character { character } => {
+                    ranges.push((character.clone(), character.clone()))
                 }
+                Node::Class { ranges: class } => ranges.extend(class.iter().cloned()),
+                Node::Look { .. }
+                | Node::Backreference { .. }
+                | Node::Lookahead { .. }
+                | Node::Lookbehind { .. } => {
+                    return Err("Assertions, backreferences and lookarounds need bytes".to_string())
+                }
+                _ => {}
+            }
+            nodes.extend(node.children());
+        }
+        let alphabet = Alphabet::new(&ranges)?;
 
-                fragment.create_transition(start, None, lhs.start_state);
-                fragment.accept_states = &lhs.accept_states | &vec![start].into_iter().collect();
-                fragment
+        let mut steps = vec![Step::Encode(self)];
+        let mut encoded: Vec<Node> = Vec::new();
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Encode(mut node) => match &mut node {
+                    Node::Empty => encoded.push(Node::Empty),
+                    Node::Nothing => encoded.push(Node::Nothing),
+                    Node::Character { character } => {
+                        encoded.push(Node::character(alphabet.get(character)))
+                    }
+                    Node::Class { ranges } => encoded.push(Node::class(
+                        ranges
+                            .iter()
+                            .map(|(start, end)| (alphabet.get(start), alphabet.get(end)))
+                            .collect(),
+                    )),
+                    Node::Union { left, right } => steps.extend([
+                        Step::Union,
+                        Step::Encode(right.take()),
+                        Step::Encode(left.take()),
+                    ]),
+                    Node::Concat { left, right } => steps.extend([
+                        Step::Concat,
+                        Step::Encode(right.take()),
+                        Step::Encode(left.take()),
+                    ]),
+                    Node::And { left, right } => steps.extend([
+                        Step::And,
+                        Step::Encode(right.take()),
+                        Step::Encode(left.take()),
+                    ]),
+                    Node::Star { operand, greedy } => {
+                        steps.extend([Step::Star(*greedy), Step::Encode(operand.take())])
+                    }
+                    Node::Plus { operand, greedy } => {
+                        steps.extend([Step::Plus(*greedy), Step::Encode(operand.take())])
+                    }
+                    Node::Capture { index, operand } => {
+                        steps.extend([Step::Capture(*index), Step::Encode(operand.take())])
+                    }
+                    Node::Not { operand } => {
+                        steps.extend([Step::Not, Step::Encode(operand.take())])
+                    }
+                    Node::Look { .. }
+                    | Node::Backreference { .. }
+                    | Node::Lookahead { .. }
+                    | Node::Lookbehind { .. } => unreachable!("rejected above"),
+                },
+                Step::Union | Step::Concat | Step::And => {
+                    let right = encoded.pop().unwrap();
+                    let left = encoded.pop().unwrap();
+                    encoded.push(match step {
+                        Step::Union => Node::union(left, right),
+                        Step::Concat => Node::concat(left, right),
+                        _ => Node::intersection(left, right),
+                    });
+                }
+                Step::Star(greedy) => {
+                    let operand = Box::new(encoded.pop().unwrap());
+                    encoded.push(Node::Star { operand, greedy });
+                }
+                Step::Plus(greedy) => {
+                    let operand = Box::new(encoded.pop().unwrap());
+                    encoded.push(Node::Plus { operand, greedy });
+                }
+                Step::Capture(index) => {
+                    let operand = encoded.pop().unwrap();
+                    encoded.push(Node::capture(index, operand));
+                }
+                Step::Not => {
+                    let operand = encoded.pop().unwrap();
+                    encoded.push(Node::complement(operand));
+                }
             }
         }
+        Ok((encoded.pop().unwrap(), alphabet))
     }
 }
 
-/// Used for labeling the states
-pub struct Counter {
-    state_count: i32,
-}
+/// The nodes that only make sense over bytes
+impl Node {
+    pub fn character(character: u8) -> Self {
+        Node::Character { character }
+    }
+
+    /// A set of characters, given as inclusive ranges. The ranges are sorted
+    /// and merged so that equal sets have equal nodes.
+    pub fn class(mut ranges: Vec<(u8, u8)>) -> Self {
+        ranges.sort_unstable();
+        let mut merged: Vec<(u8, u8)> = Vec::new();
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some((_, last)) if start as u16 <= *last as u16 + 1 => *last = (*last).max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        Node::Class { ranges: merged }
+    }
+
+    /// An assertion such as '^' or '\b', matches the empty string
+    pub fn look(look: Look) -> Self {
+        Node::Look { look }
+    }
+
+    /// '.' in regex, the UTF-8 encoding of any character except a newline
+    /// unless `dot_all` is set
+    pub fn any(dot_all: bool) -> Self {
+        let newline = '\n' as u32;
+        if dot_all {
+            utf8::class(&[(0, char::MAX as u32)])
+        } else {
+            utf8::class(&[(0, newline - 1), (newline + 1, char::MAX as u32)])
+        }
+    }
 
-impl Counter {
-    pub fn new() -> Self {
-        Counter { state_count: 1 }
+    /// '\1' in regex, matches what the group matched. The language is not
+    /// regular, so only the backtracking engine supports it.
+    pub fn backreference(index: usize) -> Self {
+        Node::Backreference { index }
     }
 
-    pub fn new_state(&mut self) -> i32 {
-        self.state_count += 1;
-        self.state_count
+    /// Matches the empty string if the input after it starts with a match of
+    /// the operand, or does not when negated
+    pub fn lookahead(negated: bool, operand: Node) -> Self {
+        Node::Lookahead {
+            negated,
+            operand: Box::new(operand),
+        }
+    }
+
+    /// Matches the empty string if the input before it ends with a match of
+    /// the operand, or does not when negated
+    pub fn lookbehind(negated: bool, operand: Node) -> Self {
+        Node::Lookbehind {
+            negated,
+            operand: Box::new(operand),
+        }
+    }
+
+    /// Converts the AST into NFA fragments. The tree is walked with an
+    /// explicit stack instead of recursion, since a long regex such as
+    /// 'aaa...' is as deep as it is long. The operands are converted before
+    /// the operators, and the nodes are taken apart on the way, so the tree
+    /// is also dropped without recursion.
+    pub fn to_fragment(self, arena: &mut StateArena) -> NFAFragment {
+        self.try_to_fragment(arena, DfaLimits::unlimited())
+            .unwrap_or_else(|reason| panic!("{}", reason))
+    }
+
+    /// Same as `to_fragment`, but fails if the DFA of an intersection or a
+    /// complement grows over the limits, or if one of them has assertions
+    /// inside it
+    pub fn try_to_fragment(
+        self,
+        arena: &mut StateArena,
+        limits: DfaLimits,
+    ) -> Result<NFAFragment, String> {
+        /// An operator waits on the stack until its operands are converted
+        enum Step {
+            Convert(Node),
+            Union,
+            Concat,
+            Star,
+            Plus,
+        }
+
+        let mut steps = vec![Step::Convert(self)];
+        let mut fragments: Vec<NFAFragment> = Vec::new();
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Convert(mut node) => match &mut node {
+                    Node::Empty => {
+                        let state = arena.new_state();
+                        let fragment = NFAFragment::new(state, vec![state].into_iter().collect());
+                        fragments.push(fragment);
+                    }
+
+                    Node::Nothing => {
+                        fragments.push(NFAFragment::new(arena.new_state(), HashSet::new()))
+                    }
+
+                    Node::Character { character } => {
+                        let lhs = arena.new_state();
+                        let rhs = arena.new_state();
+                        let accept_states = vec![rhs].into_iter().collect();
+                        let fragment = NFAFragment::new(lhs, accept_states);
+                        arena.create_transition(lhs, Some(*character), rhs);
+                        fragments.push(fragment);
+                    }
+
+                    Node::Class { ranges } => {
+                        let lhs = arena.new_state();
+                        let rhs = arena.new_state();
+                        let accept_states = vec![rhs].into_iter().collect();
+                        let fragment = NFAFragment::new(lhs, accept_states);
+                        for &mut (start, end) in ranges {
+                            for character in start..=end {
+                                arena.create_transition(lhs, Some(character), rhs);
+                            }
+                        }
+                        fragments.push(fragment);
+                    }
+
+                    Node::Look { look } => {
+                        let lhs = arena.new_state();
+                        let rhs = arena.new_state();
+                        let accept_states = vec![rhs].into_iter().collect();
+                        let fragment = NFAFragment::new(lhs, accept_states);
+                        arena.create_look(lhs, *look, rhs);
+                        fragments.push(fragment);
+                    }
+
+                    // The groups only matter to the backtracking engine
+                    Node::Capture { operand, .. } => steps.push(Step::Convert(operand.take())),
+
+                    Node::Backreference { .. } => {
+                        unreachable!("backreferences are matched by backtracking")
+                    }
+
+                    Node::Lookahead { .. } | Node::Lookbehind { .. } => {
+                        unreachable!("lookarounds are rewritten into intersections before")
+                    }
+
+                    Node::Union { left, right } => {
+                        steps.push(Step::Union);
+                        steps.push(Step::Convert(right.take()));
+                        steps.push(Step::Convert(left.take()));
+                    }
+
+                    Node::Concat { left, right } => {
+                        steps.push(Step::Concat);
+                        steps.push(Step::Convert(right.take()));
+                        steps.push(Step::Convert(left.take()));
+                    }
+
+                    Node::Plus { operand, .. } => {
+                        steps.push(Step::Plus);
+                        steps.push(Step::Convert(operand.take()));
+                    }
+
+                    Node::Star { operand, .. } => {
+                        steps.push(Step::Star);
+                        steps.push(Step::Convert(operand.take()));
+                    }
+
+                    // Thompson's construction has no rules for intersection and
+                    // complement, so these are determinized with derivatives instead.
+                    // Derivatives do not support assertions, so those fail.
+                    Node::And { .. } | Node::Not { .. } => {
+                        let dfa = DerivativeDFA::try_new(node.take(), limits)?;
+                        fragments.push(dfa.to_fragment(arena))
+                    }
+                },
+                Step::Union => {
+                    let rhs = fragments.pop().unwrap();
+                    let lhs = fragments.pop().unwrap();
+                    fragments.push(lhs.union(rhs, arena));
+                }
+                Step::Concat => {
+                    let rhs = fragments.pop().unwrap();
+                    let lhs = fragments.pop().unwrap();
+                    fragments.push(lhs.concat(rhs, arena));
+                }
+                Step::Star => {
+                    let lhs = fragments.pop().unwrap();
+                    fragments.push(lhs.star(arena));
+                }
+                Step::Plus => {
+                    let lhs = fragments.pop().unwrap();
+                    fragments.push(lhs.plus(arena));
+                }
+            }
+        }
+        Ok(fragments.pop().unwrap())
     }
 }
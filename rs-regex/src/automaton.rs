@@ -0,0 +1,353 @@
+use crate::dfa::DFA;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+/// The interface shared by the finite automata, so that running, analysing
+/// and combining them can be written once. An automaton reads the whole
+/// input one byte at a time, and a state is whatever tells where it is: an
+/// index for a DFA and a set of states for an NFA.
+/// https://en.wikipedia.org/wiki/Finite-state_machine
+pub trait Automaton {
+    type State: Clone + Eq + Hash;
+
+    /// The state before the first byte of the input
+    fn start_state(&self) -> Self::State;
+
+    /// The state after reading the byte
+    fn step(&self, state: &Self::State, byte: u8) -> Self::State;
+
+    /// Checks if the input read so far matches when it ends here
+    fn is_accepting(&self, state: &Self::State) -> bool;
+
+    /// Checks if no input can lead from the state to an accepting state.
+    /// Returning false is always safe, the search just goes on longer.
+    fn is_dead(&self, state: &Self::State) -> bool;
+}
+
+/// The runtime for recognizing the input strings
+pub struct Recognizer<'a, A: Automaton = DFA> {
+    automaton: &'a A,
+    pub(crate) current_state: A::State,
+}
+
+impl<'a, A: Automaton> Recognizer<'a, A> {
+    pub fn new(automaton: &'a A) -> Self {
+        Recognizer {
+            automaton,
+            current_state: automaton.start_state(),
+        }
+    }
+
+    pub(crate) fn make_transition(&mut self, character: u8) {
+        self.current_state = self.automaton.step(&self.current_state, character)
+    }
+
+    /// Check if the current state is an accept state
+    pub(crate) fn is_accept_state(&self) -> bool {
+        self.automaton.is_accepting(&self.current_state)
+    }
+
+    /// Make transitions along the automaton given by the input string and
+    /// check if we end up in an accept state
+    pub fn accepts(&mut self, input: &[u8]) -> bool {
+        for &symbol in input {
+            self.make_transition(symbol);
+        }
+        self.is_accept_state()
+    }
+}
+
+/// How the product of two automata combines their answers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Both automata accept
+    Intersection,
+    /// Either automaton accepts
+    Union,
+    /// The left automaton accepts and the right one does not
+    Difference,
+}
+
+/// Runs two automata side by side on the same input
+/// https://en.wikipedia.org/wiki/Product_construction
+pub struct Product<'a, L, R> {
+    left: &'a L,
+    right: &'a R,
+    operation: Operation,
+}
+
+impl<'a, L: Automaton, R: Automaton> Product<'a, L, R> {
+    pub fn new(left: &'a L, right: &'a R, operation: Operation) -> Self {
+        Product {
+            left,
+            right,
+            operation,
+        }
+    }
+}
+
+impl<L: Automaton, R: Automaton> Automaton for Product<'_, L, R> {
+    type State = (L::State, R::State);
+
+    fn start_state(&self) -> Self::State {
+        (self.left.start_state(), self.right.start_state())
+    }
+
+    fn step(&self, (left, right): &Self::State, byte: u8) -> Self::State {
+        (self.left.step(left, byte), self.right.step(right, byte))
+    }
+
+    fn is_accepting(&self, (left, right): &Self::State) -> bool {
+        let left = self.left.is_accepting(left);
+        let right = self.right.is_accepting(right);
+        match self.operation {
+            Operation::Intersection => left && right,
+            Operation::Union => left || right,
+            Operation::Difference => left && !right,
+        }
+    }
+
+    fn is_dead(&self, (left, right): &Self::State) -> bool {
+        let left = self.left.is_dead(left);
+        let right = self.right.is_dead(right);
+        match self.operation {
+            Operation::Intersection => left || right,
+            Operation::Union => left && right,
+            Operation::Difference => left,
+        }
+    }
+}
+
+/// The states reachable from the start state numbered in breadth-first
+/// order, with their transitions on every byte. The start state is number 0,
+/// other dead states and the transitions into them are left out.
+struct Graph<S> {
+    states: Vec<S>,
+    transitions: Vec<Vec<(u8, usize)>>,
+}
+
+impl<S: Clone + Eq + Hash> Graph<S> {
+    fn explore<A: Automaton<State = S>>(automaton: &A) -> Self {
+        let mut graph = Graph {
+            states: vec![automaton.start_state()],
+            transitions: Vec::new(),
+        };
+        let mut ids = HashMap::new();
+        ids.insert(automaton.start_state(), 0);
+        while graph.transitions.len() < graph.states.len() {
+            let state = graph.states[graph.transitions.len()].clone();
+            let mut transitions = Vec::new();
+            if !automaton.is_dead(&state) {
+                for byte in 0..=u8::MAX {
+                    let next = automaton.step(&state, byte);
+                    if automaton.is_dead(&next) {
+                        continue;
+                    }
+                    let id = *ids.entry(next.clone()).or_insert_with(|| {
+                        graph.states.push(next);
+                        graph.states.len() - 1
+                    });
+                    transitions.push((byte, id));
+                }
+            }
+            graph.transitions.push(transitions);
+        }
+        graph
+    }
+}
+
+/// Finds the shortest input the automaton accepts, the first one in byte
+/// order if there are many
+pub fn shortest_accepted<A: Automaton>(automaton: &A) -> Option<Vec<u8>> {
+    let start = automaton.start_state();
+    let mut parents: HashMap<A::State, Option<(A::State, u8)>> = HashMap::new();
+    parents.insert(start.clone(), None);
+    let mut queue = VecDeque::from(vec![start]);
+    while let Some(state) = queue.pop_front() {
+        if automaton.is_accepting(&state) {
+            let mut input = Vec::new();
+            let mut current = state;
+            while let Some((parent, byte)) = parents[&current].clone() {
+                input.push(byte);
+                current = parent;
+            }
+            input.reverse();
+            return Some(input);
+        }
+        if automaton.is_dead(&state) {
+            continue;
+        }
+        for byte in 0..=u8::MAX {
+            let next = automaton.step(&state, byte);
+            if !parents.contains_key(&next) {
+                parents.insert(next.clone(), Some((state.clone(), byte)));
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
+
+/// Checks if the automaton accepts no input at all
+pub fn is_empty<A: Automaton>(automaton: &A) -> bool {
+    shortest_accepted(automaton).is_none()
+}
+
+/// Checks if the automata accept exactly the same inputs
+pub fn equivalent<L: Automaton, R: Automaton>(left: &L, right: &R) -> bool {
+    is_empty(&Product::new(left, right, Operation::Difference))
+        && is_empty(&Product::new(right, left, Operation::Difference))
+}
+
+/// Writes the reachable states of the automaton in the DOT language of
+/// Graphviz. Accepting states are drawn with double circles, dead states
+/// and the transitions into them are left out.
+/// https://graphviz.org/doc/info/lang.html
+pub fn to_dot<A: Automaton>(automaton: &A) -> String {
+    let graph = Graph::explore(automaton);
+    let mut dot = String::from("digraph automaton {\n    rankdir=LR;\n");
+    dot.push_str("    start [shape=point];\n");
+    if automaton.is_dead(&graph.states[0]) {
+        dot.push_str("}\n");
+        return dot;
+    }
+    dot.push_str("    start -> 0;\n");
+    for (id, state) in graph.states.iter().enumerate() {
+        let shape = if automaton.is_accepting(state) {
+            "doublecircle"
+        } else {
+            "circle"
+        };
+        dot.push_str(&format!("    {} [shape={}];\n", id, shape));
+    }
+    for (from, transitions) in graph.transitions.iter().enumerate() {
+        let mut targets: Vec<(usize, Vec<u8>)> = Vec::new();
+        for &(byte, to) in transitions.iter() {
+            match targets.iter_mut().find(|(target, _)| *target == to) {
+                Some((_, bytes)) => bytes.push(byte),
+                None => targets.push((to, vec![byte])),
+            }
+        }
+        for (to, bytes) in targets {
+            let label = label(&bytes).replace('\\', "\\\\").replace('"', "\\\"");
+            dot.push_str(&format!("    {} -> {} [label=\"{}\"];\n", from, to, label));
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Writes the sorted bytes as ranges, such as `a-z_`
+fn label(bytes: &[u8]) -> String {
+    let mut label = String::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let mut j = i;
+        while j + 1 < bytes.len() && bytes[j + 1] == bytes[j] + 1 {
+            j += 1;
+        }
+        label.push_str(&escape(bytes[i]));
+        if j > i {
+            label.push('-');
+            label.push_str(&escape(bytes[j]));
+        }
+        i = j + 1;
+    }
+    label
+}
+
+fn escape(byte: u8) -> String {
+    if byte.is_ascii_graphic() || byte == b' ' {
+        (byte as char).to_string()
+    } else {
+        format!("\\x{:02X}", byte)
+    }
+}
+
+//grcov-excl-start
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nfa::NFA;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use crate::simulation::Simulation;
+
+    fn nfa(regex: &str) -> NFA {
+        let scanner = Scanner::new(regex.to_string());
+        let mut parser = Parser::new(scanner);
+        parser.expr().unwrap()
+    }
+
+    #[test]
+    fn recognizer_is_generic() {
+        let nfa = nfa("(a|b)*abb");
+        let simulation = Simulation::new(&nfa);
+        let dfa = nfa.to_dfa();
+        for input in ["abb", "babb", "ab", "abba", ""] {
+            let expected = input.ends_with("abb");
+            assert_eq!(Recognizer::new(&nfa).accepts(input.as_bytes()), expected);
+            assert_eq!(
+                Recognizer::new(&simulation).accepts(input.as_bytes()),
+                expected
+            );
+            assert_eq!(dfa.recognizer().accepts(input.as_bytes()), expected);
+        }
+    }
+
+    #[test]
+    fn assertions() {
+        let nfa = nfa("\\bab\\b|^b$");
+        for (input, expected) in [("ab", true), ("b", true), ("abc", false), ("bb", false)] {
+            assert_eq!(Recognizer::new(&nfa).accepts(input.as_bytes()), expected);
+            let simulation = Simulation::new(&nfa);
+            assert_eq!(
+                Recognizer::new(&simulation).accepts(input.as_bytes()),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn product() {
+        let left = nfa("(a|b)*a").to_dfa();
+        let right = nfa("a(a|b)*").to_dfa();
+        let both = Product::new(&left, &right, Operation::Intersection);
+        assert!(Recognizer::new(&both).accepts(b"aba"));
+        assert!(!Recognizer::new(&both).accepts(b"ab"));
+        let either = Product::new(&left, &right, Operation::Union);
+        assert!(Recognizer::new(&either).accepts(b"ab"));
+        assert!(!Recognizer::new(&either).accepts(b"b"));
+        let difference = Product::new(&left, &right, Operation::Difference);
+        assert_eq!(shortest_accepted(&difference), Some(b"ba".to_vec()));
+    }
+
+    #[test]
+    fn analysis() {
+        let nfa = nfa("(ab|a)(bc|c)");
+        assert_eq!(shortest_accepted(&nfa), Some(b"ac".to_vec()));
+        assert!(equivalent(&nfa, &nfa.to_dfa().minimize()));
+        assert!(!equivalent(&nfa, &super::tests::nfa("abc|ac")));
+        let nothing = Product::new(&nfa, &nfa, Operation::Difference);
+        assert!(is_empty(&nothing));
+    }
+
+    #[test]
+    fn dot() {
+        let dot = to_dot(&nfa("a(b|c|d)").to_dfa().minimize());
+        assert_eq!(
+            dot,
+            "digraph automaton {\n    rankdir=LR;\n    start [shape=point];\n    start -> 0;\n    \
+             0 [shape=circle];\n    1 [shape=circle];\n    2 [shape=doublecircle];\n    \
+             0 -> 1 [label=\"a\"];\n    1 -> 2 [label=\"b-d\"];\n}\n"
+        );
+    }
+
+    #[test]
+    fn dot_labels() {
+        assert_eq!(label(b"\n#$%09"), "\\x0A#-%09");
+    }
+}
+
+//grcov-excl-stop
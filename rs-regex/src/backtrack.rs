@@ -0,0 +1,378 @@
+use crate::ast::Node;
+use crate::look::Context;
+use crate::program::{compile, Instruction};
+use crate::regex::MatchKind;
+
+/// The default for the most steps a single search may take
+pub const DEFAULT_STEP_BUDGET: usize = 1_000_000;
+
+/// A matcher that tries the alternatives one by one, going back when one of
+/// them fails
+/// https://en.wikipedia.org/wiki/Backtracking
+///
+/// Unlike the automata, it can match backreferences, but it may take
+/// exponential time. Every search has a budget of steps, and running out of
+/// it is an error instead of a hang. The regex is compiled into a program,
+/// and the alternatives left to try are kept on a stack on the heap, so the
+/// search does not recurse however long the input is.
+pub struct Backtracker {
+    program: Vec<Instruction>,
+    group_count: usize,
+    star_count: usize,
+    budget: usize,
+}
+
+/// What the search goes back to when an alternative fails: another
+/// alternative to try, or the state to restore
+enum Frame {
+    Try { pc: usize, at: usize },
+    Open(usize, usize),
+    Capture(usize, Option<(usize, usize)>),
+    Mark(usize, usize),
+}
+
+/// The state of a single search
+struct Search<'a> {
+    program: &'a [Instruction],
+    input: &'a [u8],
+    /// Where the groups that are being matched started
+    opened: Vec<usize>,
+    /// The start and the end of the latest match of every group
+    captures: Vec<Option<(usize, usize)>>,
+    /// Where the current iteration of every star started
+    marks: Vec<usize>,
+    steps: usize,
+    budget: usize,
+}
+
+impl Backtracker {
+    /// Intersection and complement are not supported
+    pub fn new(node: Node, budget: usize) -> Result<Self, String> {
+        if node.has_boolean_operators() {
+            return Err(
+                "The backtracking engine does not support intersection or complement".to_string(),
+            );
+        }
+        let program = compile(&node);
+        Ok(Backtracker {
+            program: program.instructions,
+            group_count: node.group_count(),
+            star_count: program.star_count,
+            budget,
+        })
+    }
+
+    /// Checks if the whole input matches
+    pub fn matches(&self, input: &[u8]) -> Result<bool, String> {
+        let mut search = self.search(input);
+        search.run(0, 0, &mut |end| end == input.len())
+    }
+
+    /// Finds the leftmost match, and of those either the longest one like
+    /// the DFA or the first one in the order the alternatives are tried.
+    /// The budget is shared by all of the start positions.
+    pub fn find(&self, input: &[u8], kind: MatchKind) -> Result<Option<(usize, usize)>, String> {
+        let mut search = self.search(input);
+        for start in 0..=input.len() {
+            let mut found = None;
+            search.run(0, start, &mut |end| {
+                found = found.max(Some(end));
+                kind == MatchKind::LeftmostFirst || end == input.len()
+            })?;
+            if let Some(end) = found {
+                return Ok(Some((start, end)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn search<'a>(&'a self, input: &'a [u8]) -> Search<'a> {
+        Search {
+            program: &self.program,
+            input,
+            opened: vec![0; self.group_count + 1],
+            captures: vec![None; self.group_count + 1],
+            marks: vec![0; self.star_count],
+            steps: 0,
+            budget: self.budget,
+        }
+    }
+}
+
+impl<'a> Search<'a> {
+    /// Runs the program from the instruction at the position and calls
+    /// `accept` with every position a match ends at, until it returns true.
+    /// Only the lookarounds run a nested search, so the depth of the calls
+    /// is at most how deep the lookarounds are nested in the regex.
+    fn run(
+        &mut self,
+        pc: usize,
+        at: usize,
+        accept: &mut dyn FnMut(usize) -> bool,
+    ) -> Result<bool, String> {
+        let program = self.program;
+        let mut stack = vec![Frame::Try { pc, at }];
+        while let Some(frame) = stack.pop() {
+            let (mut pc, mut at) = match frame {
+                Frame::Try { pc, at } => (pc, at),
+                Frame::Open(index, start) => {
+                    self.opened[index] = start;
+                    continue;
+                }
+                Frame::Capture(index, capture) => {
+                    self.captures[index] = capture;
+                    continue;
+                }
+                Frame::Mark(star, start) => {
+                    self.marks[star] = start;
+                    continue;
+                }
+            };
+            // Follows the instructions until the alternative fails
+            loop {
+                self.steps += 1;
+                if self.steps > self.budget {
+                    return Err(format!("Backtracking gave up after {} steps", self.budget));
+                }
+                match &program[pc] {
+                    Instruction::Bytes(ranges) => match self.input.get(at) {
+                        Some(&byte) if ranges.iter().any(|&(lo, hi)| lo <= byte && byte <= hi) => {
+                            pc += 1;
+                            at += 1;
+                        }
+                        _ => break,
+                    },
+                    Instruction::Split(first, second) => {
+                        stack.push(Frame::Try { pc: *second, at });
+                        pc = *first;
+                    }
+                    Instruction::Jump(target) => pc = *target,
+                    Instruction::Look(look) => {
+                        let behind = Context::of(at.checked_sub(1).map(|i| self.input[i]));
+                        let ahead = Context::of(self.input.get(at).cloned());
+                        if !look.holds(behind, ahead) {
+                            break;
+                        }
+                        pc += 1;
+                    }
+                    Instruction::Fail => break,
+                    Instruction::Match => {
+                        if accept(at) {
+                            return Ok(true);
+                        }
+                        break;
+                    }
+                    Instruction::Open(index) => {
+                        stack.push(Frame::Open(*index, self.opened[*index]));
+                        self.opened[*index] = at;
+                        pc += 1;
+                    }
+                    Instruction::Close(index) => {
+                        stack.push(Frame::Capture(*index, self.captures[*index]));
+                        self.captures[*index] = Some((self.opened[*index], at));
+                        pc += 1;
+                    }
+                    Instruction::Backreference(index) => match self.captures[*index] {
+                        Some((start, end))
+                            if self.input[at..].starts_with(&self.input[start..end]) =>
+                        {
+                            pc += 1;
+                            at += end - start;
+                        }
+                        _ => break,
+                    },
+                    Instruction::Mark(star) => {
+                        stack.push(Frame::Mark(*star, self.marks[*star]));
+                        self.marks[*star] = at;
+                        pc += 1;
+                    }
+                    Instruction::Repeat { star, start } => {
+                        pc = if at == self.marks[*star] {
+                            pc + 1
+                        } else {
+                            *start
+                        };
+                    }
+                    Instruction::Lookahead { negated, end, .. } => {
+                        if self.lookahead(pc + 1, at)? == *negated {
+                            break;
+                        }
+                        pc = *end;
+                    }
+                    Instruction::Lookbehind { negated, end, .. } => {
+                        if self.lookbehind(pc + 1, at)? == *negated {
+                            break;
+                        }
+                        pc = *end;
+                    }
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// The groups inside a lookaround are not kept after it
+    fn lookahead(&mut self, pc: usize, at: usize) -> Result<bool, String> {
+        let captures = self.captures.clone();
+        let found = self.run(pc, at, &mut |_| true)?;
+        self.captures = captures;
+        Ok(found)
+    }
+
+    fn lookbehind(&mut self, pc: usize, at: usize) -> Result<bool, String> {
+        let captures = self.captures.clone();
+        let mut found = false;
+        for start in (0..=at).rev() {
+            found = self.run(pc, start, &mut |end| end == at)?;
+            if found {
+                break;
+            }
+        }
+        self.captures = captures;
+        Ok(found)
+    }
+}
+
+//grcov-excl-start
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn backtracker(regex: &str) -> Backtracker {
+        let scanner = Scanner::new(regex.to_string());
+        let mut parser = Parser::new(scanner);
+        Backtracker::new(parser.parse().unwrap(), DEFAULT_STEP_BUDGET).unwrap()
+    }
+
+    #[test]
+    fn same_as_dfa() {
+        let regex = backtracker("aa(b|cc)*a");
+        for (input, expected) in [
+            ("aaa", true),
+            ("aacca", true),
+            ("aabaa", false),
+            ("", false),
+        ] {
+            assert_eq!(regex.matches(input.as_bytes()).unwrap(), expected);
+        }
+        assert!(backtracker("(a*)*b").matches(b"aab").unwrap());
+        assert!(backtracker("()*").matches(b"").unwrap());
+    }
+
+    #[test]
+    fn backreferences() {
+        let regex = backtracker("(a|b)*c\\1");
+        assert!(regex.matches(b"abcb").unwrap());
+        assert!(!regex.matches(b"abca").unwrap());
+        // A group that has not matched does not match anything
+        assert!(!regex.matches(b"c").unwrap());
+
+        let regex = backtracker("((a|b)*)=\\1");
+        assert!(regex.matches(b"abba=abba").unwrap());
+        assert!(regex.matches(b"=").unwrap());
+        assert!(!regex.matches(b"ab=ba").unwrap());
+    }
+
+    #[test]
+    fn find() {
+        let longest = MatchKind::LeftmostLongest;
+        let regex = backtracker("(a|b)\\1");
+        assert_eq!(regex.find(b"abba", longest).unwrap(), Some((1, 3)));
+        assert_eq!(regex.find(b"abab", longest).unwrap(), None);
+        assert_eq!(
+            backtracker("\\bx*").find(b"yy x", longest).unwrap(),
+            Some((0, 0))
+        );
+        assert_eq!(backtracker("\\bxx*").find(b"yy", longest).unwrap(), None);
+    }
+
+    #[test]
+    fn leftmost_first() {
+        let first = MatchKind::LeftmostFirst;
+        assert_eq!(
+            backtracker("a|ab").find(b"ab", first).unwrap(),
+            Some((0, 1))
+        );
+        assert_eq!(
+            backtracker("a*?b").find(b"aab", first).unwrap(),
+            Some((0, 3))
+        );
+        assert_eq!(
+            backtracker("(a+?)\\1").find(b"aaaa", first).unwrap(),
+            Some((0, 2))
+        );
+        assert_eq!(
+            backtracker("(a|b)+?(?=b)").find(b"aab", first).unwrap(),
+            Some((0, 2))
+        );
+        assert!(backtracker("a*?").matches(b"aaa").unwrap());
+    }
+
+    #[test]
+    fn lookarounds() {
+        let regex = backtracker("a(?=b)b*");
+        assert!(regex.matches(b"abb").unwrap());
+        assert!(!regex.matches(b"a").unwrap());
+        assert_eq!(
+            backtracker("a(?=b)")
+                .find(b"aab", MatchKind::LeftmostLongest)
+                .unwrap(),
+            Some((1, 2))
+        );
+        assert_eq!(
+            backtracker("a(?!b)")
+                .find(b"aba", MatchKind::LeftmostLongest)
+                .unwrap(),
+            Some((2, 3))
+        );
+        assert_eq!(
+            backtracker("(?<=ab)c")
+                .find(b"cabc", MatchKind::LeftmostLongest)
+                .unwrap(),
+            Some((3, 4))
+        );
+        assert_eq!(
+            backtracker("(?<!a)c")
+                .find(b"acbc", MatchKind::LeftmostLongest)
+                .unwrap(),
+            Some((3, 4))
+        );
+        assert!(backtracker("(a|b)*(?<=a)").matches(b"bba").unwrap());
+        assert!(!backtracker("(a|b)*(?<=a)").matches(b"bab").unwrap());
+    }
+
+    #[test]
+    fn step_budget() {
+        let scanner = Scanner::new("(a*)*b".to_string());
+        let node = Parser::new(scanner).parse().unwrap();
+        let regex = Backtracker::new(node, 10_000).unwrap();
+        assert!(regex.matches(&[b'a'; 30]).is_err());
+    }
+
+    #[test]
+    fn long_input() {
+        let input = "ab".repeat(10_000) + "=b";
+        assert!(backtracker("(a|b)*=\\1").matches(input.as_bytes()).unwrap());
+        let input = "ab".repeat(10_000) + "=a";
+        assert!(!backtracker("(a|b)*=\\1").matches(input.as_bytes()).unwrap());
+        let input = "ab".repeat(50_000);
+        assert!(backtracker("(a|b)*").matches(input.as_bytes()).unwrap());
+        assert_eq!(
+            backtracker("(?:ab)*?(?=b*$)")
+                .find(input.as_bytes(), MatchKind::LeftmostFirst)
+                .unwrap(),
+            Some((0, 100_000))
+        );
+    }
+
+    #[test]
+    fn boolean_operators() {
+        let node = Node::complement(Node::character(b'a'));
+        assert!(Backtracker::new(node, DEFAULT_STEP_BUDGET).is_err());
+    }
+}
+
+//grcov-excl-stop
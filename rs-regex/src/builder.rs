@@ -0,0 +1,355 @@
+use crate::ast::Node;
+use crate::look::Look;
+use crate::utf8;
+use std::fmt::{self, Display, Formatter};
+
+/// Composes a pattern in code by chaining, e.g.
+/// `Pattern::literal("ab").or(Pattern::range('0', '9').plus()).build()` is
+/// the same node as the pattern `ab|[0-9]+`. The groups are numbered in
+/// the order of their opening parentheses when the pattern is built, like
+/// the parser numbers them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern<S = u8> {
+    node: Node<S>,
+}
+
+impl<S: Ord + Clone> Pattern<S> {
+    /// The empty string
+    pub fn empty() -> Self {
+        Pattern { node: Node::Empty }
+    }
+
+    /// The empty language, matches nothing
+    pub fn nothing() -> Self {
+        Pattern {
+            node: Node::Nothing,
+        }
+    }
+
+    pub fn symbol(symbol: S) -> Self {
+        Pattern {
+            node: Node::symbol(symbol),
+        }
+    }
+
+    /// Any of the symbols in the inclusive ranges
+    pub fn symbols(ranges: Vec<(S, S)>) -> Self {
+        Pattern {
+            node: Node::symbols(ranges),
+        }
+    }
+
+    /// This pattern followed by the next one
+    pub fn then(self, next: Pattern<S>) -> Self {
+        Pattern {
+            node: Node::concat(self.node, next.node),
+        }
+    }
+
+    /// Either this pattern or the other one
+    pub fn or(self, other: Pattern<S>) -> Self {
+        Pattern {
+            node: Node::union(self.node, other.node),
+        }
+    }
+
+    /// Both this pattern and the other one
+    pub fn and(self, other: Pattern<S>) -> Self {
+        Pattern {
+            node: Node::intersection(self.node, other.node),
+        }
+    }
+
+    /// Everything that this pattern does not match
+    pub fn complement(self) -> Self {
+        Pattern {
+            node: Node::complement(self.node),
+        }
+    }
+
+    /// '*', zero or more times
+    pub fn star(self) -> Self {
+        Pattern {
+            node: Node::star(self.node),
+        }
+    }
+
+    /// '+', one or more times
+    pub fn plus(self) -> Self {
+        Pattern {
+            node: Node::plus(self.node),
+        }
+    }
+
+    /// '?', zero or one times
+    pub fn optional(self) -> Self {
+        Pattern {
+            node: Node::union(self.node, Node::Empty),
+        }
+    }
+
+    /// At least `min` times, and at most `max` times unless it is `None`.
+    /// The pattern is copied once for every time it is written out, the
+    /// last of them ends in '+' when there is no maximum. Panics if `max`
+    /// is less than `min`.
+    pub fn repeat(self, min: usize, max: Option<usize>) -> Self {
+        if let Some(max) = max {
+            assert!(
+                min <= max,
+                "repeat: the maximum {} is less than the minimum {}",
+                max,
+                min
+            );
+        }
+        let count = max.unwrap_or(min.max(1));
+        let mut copies: Vec<Node<S>> = (1..count).map(|_| self.node.clone()).collect();
+        if count > 0 {
+            copies.push(self.node);
+        }
+        let mut node = match max {
+            None if min > 0 => Node::plus(copies.pop().unwrap()),
+            None => Node::star(copies.pop().unwrap()),
+            Some(max) => (min..max).fold(Node::Empty, |rest, _| {
+                let copy = copies.pop().unwrap();
+                let more = match rest {
+                    Node::Empty => copy,
+                    rest => Node::concat(copy, rest),
+                };
+                Node::union(more, Node::Empty)
+            }),
+        };
+        for copy in copies {
+            node = match node {
+                Node::Empty => copy,
+                node => Node::concat(copy, node),
+            };
+        }
+        Pattern { node }
+    }
+
+    /// Makes the quantifier of the pattern lazy, so that it repeats as few
+    /// times as possible, e.g. '*?' instead of '*'
+    pub fn lazy(mut self) -> Self {
+        match &mut self.node {
+            Node::Star { greedy, .. } | Node::Plus { greedy, .. } => *greedy = false,
+            Node::Union { left, right } if **right == Node::Empty => std::mem::swap(left, right),
+            _ => {}
+        }
+        self
+    }
+
+    /// A capturing group
+    pub fn group(self) -> Self {
+        Pattern {
+            node: Node::capture(0, self.node),
+        }
+    }
+
+    /// The node of the pattern, with the groups numbered from 1 in the
+    /// order of their opening parentheses
+    pub fn build(self) -> Node<S> {
+        let mut node = self.node;
+        let mut count = 0;
+        let mut nodes = vec![&mut node];
+        while let Some(node) = nodes.pop() {
+            match node {
+                Node::Capture { index, operand } => {
+                    count += 1;
+                    *index = count;
+                    nodes.push(operand);
+                }
+                Node::Union { left, right }
+                | Node::Concat { left, right }
+                | Node::And { left, right } => {
+                    nodes.push(right);
+                    nodes.push(left);
+                }
+                Node::Star { operand, .. }
+                | Node::Plus { operand, .. }
+                | Node::Not { operand }
+                | Node::Lookahead { operand, .. }
+                | Node::Lookbehind { operand, .. } => nodes.push(operand),
+                _ => {}
+            }
+        }
+        node
+    }
+}
+
+impl Pattern {
+    /// The characters of the text in a sequence, the same as the parser
+    /// makes of them
+    pub fn literal(text: &str) -> Self {
+        let node = text
+            .chars()
+            .map(|character| {
+                if character.is_ascii() {
+                    Node::character(character as u8)
+                } else {
+                    utf8::character(character)
+                }
+            })
+            .rev()
+            .reduce(|right, left| Node::concat(left, right))
+            .unwrap_or(Node::Empty);
+        Pattern { node }
+    }
+
+    /// Any character from `start` to `end`. Panics if `start` comes after
+    /// `end`.
+    pub fn range(start: char, end: char) -> Self {
+        assert!(
+            start <= end,
+            "range: the start {:?} comes after the end {:?}",
+            start,
+            end
+        );
+        let node = if end.is_ascii() {
+            Node::class(vec![(start as u8, end as u8)])
+        } else {
+            utf8::class(&[(start as u32, end as u32)])
+        };
+        Pattern { node }
+    }
+
+    /// '.', any character except a newline
+    pub fn any() -> Self {
+        Pattern {
+            node: Node::any(false),
+        }
+    }
+
+    /// An assertion such as '^' or '\b'
+    pub fn look(look: Look) -> Self {
+        Pattern {
+            node: Node::look(look),
+        }
+    }
+
+    /// `(?=...)`, or `(?!...)` when negated
+    pub fn followed_by(self, negated: bool) -> Self {
+        Pattern {
+            node: Node::lookahead(negated, self.node),
+        }
+    }
+
+    /// `(?<=...)`, or `(?<!...)` when negated
+    pub fn preceded_by(self, negated: bool) -> Self {
+        Pattern {
+            node: Node::lookbehind(negated, self.node),
+        }
+    }
+}
+
+impl<S> From<Node<S>> for Pattern<S> {
+    fn from(node: Node<S>) -> Self {
+        Pattern { node }
+    }
+}
+
+impl<S: Ord + Clone> From<Pattern<S>> for Node<S> {
+    fn from(pattern: Pattern<S>) -> Self {
+        pattern.build()
+    }
+}
+
+/// The pattern in the syntax of the parser, see the `Display` of `Node`
+impl Display for Pattern {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.node.fmt(f)
+    }
+}
+
+//grcov-excl-start
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn parse(regex: &str) -> Node {
+        Parser::new(Scanner::new(regex.to_string()))
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn same_as_parsed() {
+        let patterns = [
+            (
+                Pattern::literal("ab").or(Pattern::range('0', '9').plus()),
+                "ab|[0-9]+",
+            ),
+            (
+                Pattern::literal("a")
+                    .group()
+                    .then(Pattern::any().star().lazy().group()),
+                "(a)(.*?)",
+            ),
+            (
+                Pattern::look(Look::Start).then(Pattern::literal("ä").optional().lazy()),
+                "^(?:ä)??",
+            ),
+            (Pattern::literal("a").repeat(2, Some(4)), "aa(?:aa?)?"),
+            (Pattern::literal("abc"), "abc"),
+            (Pattern::literal("a").repeat(1, None), "a+"),
+            (Pattern::literal("a").repeat(3, None), "aaa+"),
+            (Pattern::literal("a").plus().lazy(), "a+?"),
+            (Pattern::literal("a").repeat(0, Some(0)), "(?)"),
+            (
+                Pattern::literal("a")
+                    .followed_by(true)
+                    .then(Pattern::literal("b").preceded_by(false)),
+                "(?!a)(?<=b)",
+            ),
+            (Pattern::literal(""), "(?)"),
+        ];
+        for (pattern, regex) in patterns {
+            let printed = pattern.to_string();
+            assert_eq!(pattern.build(), parse(regex), "regex {}", regex);
+            assert_eq!(parse(&printed), parse(regex), "regex {}", regex);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "the maximum 1 is less than the minimum 2")]
+    fn repeat_max_less_than_min() {
+        Pattern::literal("a").repeat(2, Some(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "the start 'b' comes after the end 'a'")]
+    fn inverted_range() {
+        Pattern::range('b', 'a');
+    }
+
+    #[test]
+    fn groups_are_numbered() {
+        let inner = Pattern::literal("b").group();
+        let node = inner.clone().then(inner.group()).group().build();
+        assert_eq!(node, parse("((b)((b)))"));
+    }
+
+    #[test]
+    fn symbols() {
+        let pattern = Pattern::symbol(10u32)
+            .then(Pattern::symbols(vec![(20, 29)]).star())
+            .and(Pattern::symbol(10).then(Pattern::symbol(25)).complement());
+        let nfa = crate::nfa::Construction::Thompson
+            .to_symbol_nfa(pattern.build())
+            .unwrap();
+        assert!(nfa.accepts(&[10, 20, 29]));
+        assert!(!nfa.accepts(&[10, 25]));
+        assert!(!nfa.accepts(&[10, 30]));
+
+        // Intersections have no assertions inside
+        let pattern = Pattern::look(Look::Start)
+            .then(Pattern::literal("a"))
+            .and(Pattern::literal("a"));
+        let nfa = crate::nfa::Construction::Thompson.to_symbol_nfa(pattern.build());
+        assert!(nfa.is_err());
+    }
+}
+
+//grcov-excl-stop
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+
+/// A partition of the bytes into equivalence classes. The bytes in a class
+/// are never told apart by the automaton, so the DFA table only needs a
+/// column for every class instead of every byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteClasses {
+    classes: [u8; 256],
+    count: usize,
+}
+
+impl Default for ByteClasses {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ByteClasses {
+    /// All of the bytes in a single class
+    pub fn new() -> Self {
+        ByteClasses {
+            classes: [0; 256],
+            count: 1,
+        }
+    }
+
+    /// Every byte in a class of its own
+    pub fn singletons() -> Self {
+        let mut classes = [0; 256];
+        for (byte, class) in classes.iter_mut().enumerate() {
+            *class = byte as u8;
+        }
+        ByteClasses {
+            classes,
+            count: 256,
+        }
+    }
+
+    /// Splits the classes so that the bytes in the set are not in the same
+    /// class with the bytes outside of it. The classes stay numbered in the
+    /// order of their smallest byte.
+    pub fn split(&mut self, set: &[bool; 256]) {
+        let mut ids = HashMap::<(u8, bool), u8>::new();
+        for (byte, class) in self.classes.iter_mut().enumerate() {
+            let next_id = ids.len() as u8;
+            *class = *ids.entry((*class, set[byte])).or_insert(next_id);
+        }
+        self.count = ids.len();
+    }
+
+    pub fn get(&self, byte: u8) -> u8 {
+        self.classes[byte as usize]
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// The class of every byte
+    pub fn as_array(&self) -> &[u8; 256] {
+        &self.classes
+    }
+
+    /// The smallest byte of every class, in the order of the classes
+    pub fn representatives(&self) -> Vec<u8> {
+        let mut representatives = Vec::with_capacity(self.count);
+        for byte in 0..=255u8 {
+            if self.get(byte) as usize == representatives.len() {
+                representatives.push(byte);
+            }
+        }
+        representatives
+    }
+}
+
+/// A partition of the symbols of any ordered type into intervals, for the
+/// automata over other symbols than bytes. The ranges in a pattern cut the
+/// symbols into intervals whose symbols are never told apart, and the
+/// automaton reads the number of the interval instead of the symbol. The
+/// numbers are bytes, so there can be at most 256 intervals.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alphabet<S> {
+    /// The boundaries of the intervals in order
+    cuts: Vec<Cut<S>>,
+}
+
+/// A boundary between two intervals, right before or right after a symbol
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Cut<S> {
+    Before(S),
+    After(S),
+}
+
+impl<S: Ord> Cut<S> {
+    fn symbol(&self) -> &S {
+        match self {
+            Cut::Before(symbol) | Cut::After(symbol) => symbol,
+        }
+    }
+
+    /// Checks if the boundary is before the symbol
+    fn is_before(&self, symbol: &S) -> bool {
+        match self {
+            Cut::Before(cut) => cut <= symbol,
+            Cut::After(cut) => cut < symbol,
+        }
+    }
+}
+
+impl Alphabet<u8> {
+    /// Every byte in an interval of its own, numbered by the byte
+    pub fn bytes() -> Self {
+        Alphabet {
+            cuts: (0..u8::MAX).map(Cut::After).collect(),
+        }
+    }
+}
+
+impl<S: Ord + Clone> Alphabet<S> {
+    /// Cuts the symbols at the ends of the inclusive ranges
+    pub fn new(ranges: &[(S, S)]) -> Result<Self, String> {
+        let mut cuts: Vec<Cut<S>> = ranges
+            .iter()
+            .flat_map(|(start, end)| [Cut::Before(start.clone()), Cut::After(end.clone())])
+            .collect();
+        // By the symbol first, the cut before a symbol comes before the cut
+        // after it
+        cuts.sort_by(|a, b| a.symbol().cmp(b.symbol()).then(a.cmp(b)));
+        cuts.dedup();
+        if cuts.len() > u8::MAX as usize {
+            return Err(format!(
+                "The ranges split the symbols into more than {} classes",
+                u8::MAX as usize + 1
+            ));
+        }
+        Ok(Alphabet { cuts })
+    }
+
+    /// The number of the interval of the symbol
+    pub fn get(&self, symbol: &S) -> u8 {
+        self.cuts.partition_point(|cut| cut.is_before(symbol)) as u8
+    }
+
+    pub fn count(&self) -> usize {
+        self.cuts.len() + 1
+    }
+}
+
+//grcov-excl-start
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(bytes: &[u8]) -> [bool; 256] {
+        let mut set = [false; 256];
+        for &byte in bytes {
+            set[byte as usize] = true;
+        }
+        set
+    }
+
+    #[test]
+    fn split() {
+        let mut classes = ByteClasses::new();
+        classes.split(&set(b"abc"));
+        classes.split(&set(b"cd"));
+        assert_eq!(classes.count(), 4);
+        assert_eq!(classes.get(0), 0);
+        assert_eq!(classes.get(b'a'), classes.get(b'b'));
+        assert_ne!(classes.get(b'b'), classes.get(b'c'));
+        assert_eq!(classes.get(b'z'), 0);
+        assert_eq!(classes.representatives(), vec![0, b'a', b'c', b'd']);
+    }
+
+    #[test]
+    fn alphabet() {
+        let alphabet = Alphabet::new(&[(10, 20), (15, 15), (30, 40)]).unwrap();
+        assert_eq!(alphabet.count(), 7);
+        let classes: Vec<u8> = [0, 10, 14, 15, 16, 20, 21, 30, 40, 41]
+            .iter()
+            .map(|symbol| alphabet.get(symbol))
+            .collect();
+        assert_eq!(classes, vec![0, 1, 1, 2, 3, 3, 4, 5, 5, 6]);
+        assert_eq!(Alphabet::bytes().get(&200), 200);
+        assert_eq!(Alphabet::bytes().count(), 256);
+        let words: Vec<(String, String)> =
+            (0..200).map(|i| (i.to_string(), i.to_string())).collect();
+        assert!(Alphabet::new(&words).is_err());
+    }
+
+    #[test]
+    fn singletons() {
+        let classes = ByteClasses::singletons();
+        assert_eq!(classes.count(), 256);
+        assert_eq!(classes.get(200), 200);
+        assert_eq!(classes.representatives().len(), 256);
+    }
+}
+
+//grcov-excl-stop
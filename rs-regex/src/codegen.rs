@@ -0,0 +1,249 @@
+use crate::dfa::DFA;
+use std::fmt::Write;
+
+/// The shape of the generated matcher
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// A loop over a `match` on the state and the byte
+    Match,
+    /// Static transition tables indexed by the state and the alphabet class
+    Table,
+}
+
+/// Generates the source code of a standalone Rust function
+/// `pub fn matches(input: &[u8]) -> bool` that recognizes the same language
+/// as the DFA. The DFA is minimized first. The code has no dependencies, so
+/// it can be written into a file from a build script and included with
+/// `include!`.
+pub fn generate(dfa: &DFA, style: Style) -> String {
+    let dfa = dfa.minimize();
+    match style {
+        Style::Match => generate_match(&dfa),
+        Style::Table => generate_table(&dfa),
+    }
+}
+
+fn generate_match(dfa: &DFA) -> String {
+    let dead = (0..dfa.state_count()).find(|&state| dfa.is_dead_state(state));
+    let mut code = String::new();
+    writeln!(code, "pub fn matches(input: &[u8]) -> bool {{").unwrap();
+    writeln!(code, "    let mut state = {}usize;", dfa.start_state).unwrap();
+    writeln!(code, "    for &byte in input {{").unwrap();
+    writeln!(code, "        state = match (state, byte) {{").unwrap();
+    for state in 0..dfa.state_count() {
+        if Some(state) == dead {
+            continue;
+        }
+        for (start, end, to) in byte_ranges(dfa, state) {
+            if Some(to) == dead {
+                continue;
+            }
+            if start == end {
+                writeln!(code, "            ({}, {}) => {},", state, start, to).unwrap();
+            } else {
+                writeln!(
+                    code,
+                    "            ({}, {}..={}) => {},",
+                    state, start, end, to
+                )
+                .unwrap();
+            }
+        }
+    }
+    match dead {
+        Some(_) => writeln!(code, "            _ => return false,").unwrap(),
+        None => writeln!(code, "            _ => unreachable!(),").unwrap(),
+    }
+    writeln!(code, "        }};").unwrap();
+    writeln!(code, "    }}").unwrap();
+    let accept = accept_pattern(dfa);
+    writeln!(code, "    matches!(state, {})", accept).unwrap();
+    writeln!(code, "}}").unwrap();
+    code
+}
+
+fn generate_table(dfa: &DFA) -> String {
+    let state_count = dfa.state_count();
+    let class_count = dfa.class_count();
+    let state_type = match state_count {
+        0..=0x100 => "u8",
+        0x101..=0x10000 => "u16",
+        _ => "u32",
+    };
+
+    let mut code = String::new();
+    writeln!(code, "pub fn matches(input: &[u8]) -> bool {{").unwrap();
+    writeln!(code, "    static CLASSES: [u8; 256] = {:?};", dfa.classes()).unwrap();
+    writeln!(
+        code,
+        "    static TRANSITIONS: [[{}; {}]; {}] = [",
+        state_type, class_count, state_count
+    )
+    .unwrap();
+    for state in 0..state_count {
+        let row: Vec<usize> = (0..class_count)
+            .map(|class| dfa.get_class_transition(state, class))
+            .collect();
+        writeln!(code, "        {:?},", row).unwrap();
+    }
+    writeln!(code, "    ];").unwrap();
+    let accept: Vec<bool> = (0..state_count)
+        .map(|state| dfa.accept_states.contains(&state))
+        .collect();
+    writeln!(
+        code,
+        "    static ACCEPT: [bool; {}] = {:?};",
+        state_count, accept
+    )
+    .unwrap();
+    writeln!(code, "    let mut state = {}usize;", dfa.start_state).unwrap();
+    writeln!(code, "    for &byte in input {{").unwrap();
+    writeln!(
+        code,
+        "        state = TRANSITIONS[state][CLASSES[byte as usize] as usize] as usize;"
+    )
+    .unwrap();
+    if let Some(dead) = (0..state_count).find(|&state| dfa.is_dead_state(state)) {
+        writeln!(code, "        if state == {} {{", dead).unwrap();
+        writeln!(code, "            return false;").unwrap();
+        writeln!(code, "        }}").unwrap();
+    }
+    writeln!(code, "    }}").unwrap();
+    writeln!(code, "    ACCEPT[state]").unwrap();
+    writeln!(code, "}}").unwrap();
+    code
+}
+
+/// Groups the consecutive bytes that lead to the same state
+fn byte_ranges(dfa: &DFA, state: usize) -> Vec<(u8, u8, usize)> {
+    let mut ranges: Vec<(u8, u8, usize)> = Vec::new();
+    for byte in 0..=255u8 {
+        let to = dfa.get_transition(state, byte);
+        match ranges.last_mut() {
+            Some((_, end, previous)) if *previous == to => *end = byte,
+            _ => ranges.push((byte, byte, to)),
+        }
+    }
+    ranges
+}
+
+/// The accept states as a pattern for `matches!`
+fn accept_pattern(dfa: &DFA) -> String {
+    let mut accept: Vec<usize> = dfa.accept_states.iter().cloned().collect();
+    accept.sort_unstable();
+    if accept.is_empty() {
+        return "_ if false".to_string();
+    }
+    accept
+        .iter()
+        .map(|state| state.to_string())
+        .collect::<Vec<String>>()
+        .join(" | ")
+}
+
+//grcov-excl-start
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Node;
+    use crate::derivative::DerivativeDFA;
+    use crate::regex::Regex;
+    use std::env;
+    use std::fs;
+    use std::process::Command;
+
+    fn dfa(regex: &str) -> DFA {
+        Regex::new(regex.to_string())
+            .unwrap()
+            .dfa()
+            .unwrap()
+            .minimize()
+    }
+
+    #[test]
+    fn match_style() {
+        let code = generate(&dfa("ab*"), Style::Match);
+        assert!(code.starts_with("pub fn matches(input: &[u8]) -> bool {"));
+        // The dead state is found first in the breadth-first numbering
+        assert!(code.contains("(0, 97) => 2,"));
+        assert!(code.contains("(2, 98) => 2,"));
+        assert!(!code.contains("(1, "));
+        assert!(code.contains("_ => return false,"));
+        assert!(code.contains("matches!(state, 2)"));
+    }
+
+    #[test]
+    fn table_style() {
+        let code = generate(&dfa("ab*"), Style::Table);
+        assert!(code.contains("static TRANSITIONS: [[u8; 3]; 3]"));
+        assert!(code.contains("static ACCEPT: [bool; 3] = [false, false, true];"));
+        assert!(code.contains("if state == 1 {"));
+    }
+
+    #[test]
+    fn byte_ranges() {
+        let ranges = super::byte_ranges(&dfa("(a|b|c|x)y"), 0);
+        assert_eq!(ranges.len(), 5);
+        assert_eq!(ranges[1], (b'a', b'c', 2));
+        assert_eq!(ranges[3], (b'x', b'x', 2));
+    }
+
+    #[test]
+    fn accept_pattern() {
+        let nothing = DerivativeDFA::new(Node::Nothing).to_dfa();
+        assert_eq!(super::accept_pattern(&nothing), "_ if false");
+        assert_eq!(super::accept_pattern(&dfa("a|b*")), "0 | 2 | 3");
+    }
+
+    /// Compiles the generated functions with rustc and runs them against the
+    /// DFA they were generated from
+    #[test]
+    fn generated_code_compiles() {
+        let regex = "(0|(-|()*)(1|2|3|4|5|6|7|8|9)(0|1|2|3|4|5|6|7|8|9)*)";
+        let inputs = [
+            "1",
+            "0",
+            "-1",
+            "9999999",
+            "-123123123",
+            "-0",
+            "0.1",
+            "",
+            "01",
+            "007",
+        ];
+        let dfa = dfa(regex);
+        let dir = env::temp_dir().join(format!("rs-regex-codegen-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        for (name, style) in [("matcher", Style::Match), ("table", Style::Table)] {
+            let mut program = generate(&dfa, style);
+            program.push_str("\nfn main() {\n");
+            for input in inputs {
+                let expected = dfa.recognizer().accepts(input.as_bytes());
+                program.push_str(&format!(
+                    "    assert_eq!(matches(b\"{}\"), {});\n",
+                    input, expected
+                ));
+            }
+            program.push_str("}\n");
+
+            let source = dir.join(format!("{}.rs", name));
+            let binary = dir.join(name);
+            fs::write(&source, program).unwrap();
+            let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+            let compiled = Command::new(rustc)
+                .arg(&source)
+                .arg("-o")
+                .arg(&binary)
+                .status()
+                .unwrap();
+            assert!(compiled.success());
+            assert!(Command::new(&binary).status().unwrap().success());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+//grcov-excl-stop
@@ -0,0 +1,686 @@
+use crate::ast::Node;
+use crate::dfa::{DfaLimits, DFA};
+use crate::nfa_fragment::{NFAFragment, StateArena};
+use std::collections::HashMap;
+
+/// Brzozowski derivatives of regular expressions
+/// https://en.wikipedia.org/wiki/Brzozowski_derivative
+///
+/// The derivatives do not know the bytes around a position, so assertions
+/// are not supported. Neither are backreferences and lookarounds, and these
+/// functions panic on them, while `DerivativeDFA::try_new` fails. Groups are
+/// ignored.
+impl Node {
+    /// Checks if the language of the node contains the empty string
+    pub fn nullable(&self) -> bool {
+        let mut terms = Terms::default();
+        let id = terms.supported(self);
+        terms.nullable[id]
+    }
+
+    /// The derivative of the node with respect to a symbol. Its language
+    /// consists of the rest of the words that start with the symbol.
+    pub fn derivative(&self, character: u8) -> Node {
+        let mut terms = Terms::default();
+        let id = terms.supported(self);
+        let derivative = terms.derivative(id, character);
+        terms.extract(derivative)
+    }
+
+    /// Rebuilds the node bottom-up with the smart constructors
+    pub fn normalize(self) -> Node {
+        let mut terms = Terms::default();
+        let id = terms.supported(&self);
+        terms.extract(id)
+    }
+}
+
+/// A node in the normal form that the smart constructors keep, with the
+/// operands given by the ids of their terms. Equal terms are stored once,
+/// so the derivatives are compared by their ids.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Term {
+    Empty,
+    Nothing,
+    Character(u8),
+    Class(Vec<(u8, u8)>),
+    /// At least two operands, sorted and without duplicates or unions
+    Union(Vec<usize>),
+    /// At least two operands, sorted and without duplicates or intersections
+    And(Vec<usize>),
+    /// Nested to the right, so the left operand is not a concatenation
+    Concat(usize, usize),
+    Star(usize),
+    Not(usize),
+}
+
+/// The terms of a regex and of its derivatives. An operand is stored before
+/// the terms that use it, so the terms are built and derived bottom-up with
+/// explicit stacks and a long regex such as 'aaa...' does not recurse.
+#[derive(Default)]
+struct Terms {
+    terms: Vec<Term>,
+    ids: HashMap<Term, usize>,
+    nullable: Vec<bool>,
+    derivatives: HashMap<(usize, u8), usize>,
+}
+
+impl Terms {
+    /// The id of the term, stored if it is new
+    fn get(&mut self, term: Term) -> usize {
+        if let Some(&id) = self.ids.get(&term) {
+            return id;
+        }
+        let nullable = match &term {
+            Term::Empty | Term::Star(_) => true,
+            Term::Nothing | Term::Character(_) | Term::Class(_) => false,
+            Term::Union(ids) => ids.iter().any(|&id| self.nullable[id]),
+            Term::And(ids) => ids.iter().all(|&id| self.nullable[id]),
+            Term::Concat(left, right) => self.nullable[*left] && self.nullable[*right],
+            Term::Not(operand) => !self.nullable[*operand],
+        };
+        let id = self.terms.len();
+        self.terms.push(term.clone());
+        self.ids.insert(term, id);
+        self.nullable.push(nullable);
+        id
+    }
+
+    fn operands(&self, id: usize) -> Vec<usize> {
+        match &self.terms[id] {
+            Term::Union(ids) | Term::And(ids) => ids.clone(),
+            Term::Concat(left, right) => vec![*left, *right],
+            Term::Star(operand) | Term::Not(operand) => vec![*operand],
+            Term::Empty | Term::Nothing | Term::Character(_) | Term::Class(_) => Vec::new(),
+        }
+    }
+
+    /// The term of the node. Fails on assertions, backreferences and
+    /// lookarounds, which have no derivatives.
+    fn intern(&mut self, node: &Node) -> Result<usize, String> {
+        /// A node is built once its operands are interned
+        enum Step<'a> {
+            Intern(&'a Node),
+            Build(&'a Node),
+        }
+
+        let mut steps = vec![Step::Intern(node)];
+        let mut interned = Vec::new();
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Intern(node) => match node {
+                    Node::Capture { operand, .. } => steps.push(Step::Intern(operand)),
+                    Node::Look { .. }
+                    | Node::Backreference { .. }
+                    | Node::Lookahead { .. }
+                    | Node::Lookbehind { .. } => {
+                        return Err("Intersections and complements do not support assertions, backreferences or lookarounds".to_string());
+                    }
+                    _ => {
+                        steps.push(Step::Build(node));
+                        steps.extend(node.children().into_iter().rev().map(Step::Intern));
+                    }
+                },
+                Step::Build(node) => {
+                    let id = match node {
+                        Node::Empty => self.get(Term::Empty),
+                        Node::Nothing => self.get(Term::Nothing),
+                        Node::Character { character } => self.get(Term::Character(*character)),
+                        Node::Class { ranges } if ranges.is_empty() => self.get(Term::Nothing),
+                        Node::Class { ranges } => self.get(Term::Class(ranges.clone())),
+                        Node::Star { .. } => {
+                            let operand = interned.pop().unwrap();
+                            self.star(operand)
+                        }
+                        Node::Plus { .. } => {
+                            let operand = interned.pop().unwrap();
+                            let star = self.star(operand);
+                            self.concat(operand, star)
+                        }
+                        Node::Not { .. } => {
+                            let operand = interned.pop().unwrap();
+                            self.not(operand)
+                        }
+                        _ => {
+                            let right = interned.pop().unwrap();
+                            let left = interned.pop().unwrap();
+                            match node {
+                                Node::Union { .. } => self.union(vec![left, right]),
+                                Node::Concat { .. } => self.concat(left, right),
+                                _ => self.and(vec![left, right]),
+                            }
+                        }
+                    };
+                    interned.push(id);
+                }
+            }
+        }
+        Ok(interned.pop().unwrap())
+    }
+
+    /// The term of a node that is known to be supported
+    fn supported(&mut self, node: &Node) -> usize {
+        self.intern(node)
+            .unwrap_or_else(|reason| panic!("{}", reason))
+    }
+
+    /// The node of the term, with the operands of the unions and the
+    /// intersections in the order of the nodes
+    fn extract(&self, id: usize) -> Node {
+        /// A node is built once its operands are extracted
+        enum Step {
+            Extract(usize),
+            Build(usize),
+        }
+
+        let mut steps = vec![Step::Extract(id)];
+        let mut extracted: Vec<Node> = Vec::new();
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Extract(id) => {
+                    steps.push(Step::Build(id));
+                    steps.extend(self.operands(id).into_iter().rev().map(Step::Extract));
+                }
+                Step::Build(id) => {
+                    let count = self.operands(id).len();
+                    let mut operands = extracted.split_off(extracted.len() - count);
+                    let node = match &self.terms[id] {
+                        Term::Empty => Node::Empty,
+                        Term::Nothing => Node::Nothing,
+                        Term::Character(character) => Node::character(*character),
+                        Term::Class(ranges) => Node::Class {
+                            ranges: ranges.clone(),
+                        },
+                        Term::Union(_) | Term::And(_) => {
+                            let is_and = matches!(self.terms[id], Term::And(_));
+                            operands.sort();
+                            let operator = if is_and {
+                                Node::intersection
+                            } else {
+                                Node::union
+                            };
+                            operands
+                                .into_iter()
+                                .rev()
+                                .reduce(|right, left| operator(left, right))
+                                .unwrap()
+                        }
+                        Term::Concat(..) => {
+                            let right = operands.pop().unwrap();
+                            Node::concat(operands.pop().unwrap(), right)
+                        }
+                        Term::Star(_) => Node::star(operands.pop().unwrap()),
+                        Term::Not(_) => Node::complement(operands.pop().unwrap()),
+                    };
+                    extracted.push(node);
+                }
+            }
+        }
+        extracted.pop().unwrap()
+    }
+
+    fn everything(&mut self) -> usize {
+        let nothing = self.get(Term::Nothing);
+        self.get(Term::Not(nothing))
+    }
+
+    /// The smart constructors on terms. The identities that they keep are
+    /// listed on the public functions of the same names, which run these on
+    /// nodes.
+    fn union(&mut self, operands: Vec<usize>) -> usize {
+        self.flatten(operands, false)
+    }
+
+    fn and(&mut self, operands: Vec<usize>) -> usize {
+        self.flatten(operands, true)
+    }
+
+    /// Flattens nested terms of the same operator into a sorted list without
+    /// duplicates, so that associativity, commutativity and idempotence hold
+    fn flatten(&mut self, operands: Vec<usize>, is_and: bool) -> usize {
+        let nothing = self.get(Term::Nothing);
+        let everything = self.everything();
+        let (absorbing, identity) = if is_and {
+            (nothing, everything)
+        } else {
+            (everything, nothing)
+        };
+        let mut flat = Vec::new();
+        for id in operands {
+            match &self.terms[id] {
+                Term::Union(ids) if !is_and => flat.extend(ids),
+                Term::And(ids) if is_and => flat.extend(ids),
+                _ => flat.push(id),
+            }
+        }
+        if flat.contains(&absorbing) {
+            return absorbing;
+        }
+        flat.retain(|&id| id != identity);
+        flat.sort_unstable();
+        flat.dedup();
+        match flat.len() {
+            0 => identity,
+            1 => flat[0],
+            _ if is_and => self.get(Term::And(flat)),
+            _ => self.get(Term::Union(flat)),
+        }
+    }
+
+    fn concat(&mut self, left: usize, right: usize) -> usize {
+        // The left operand is nested to the right
+        let mut parts = Vec::new();
+        let mut rest = left;
+        while let Term::Concat(first, second) = self.terms[rest] {
+            parts.push(first);
+            rest = second;
+        }
+        parts.push(rest);
+        parts
+            .into_iter()
+            .rev()
+            .fold(right, |concat, part| self.concat_part(part, concat))
+    }
+
+    fn concat_part(&mut self, left: usize, right: usize) -> usize {
+        match (&self.terms[left], &self.terms[right]) {
+            (Term::Nothing, _) | (_, Term::Nothing) => self.get(Term::Nothing),
+            (Term::Empty, _) => right,
+            (_, Term::Empty) => left,
+            _ => self.get(Term::Concat(left, right)),
+        }
+    }
+
+    fn star(&mut self, operand: usize) -> usize {
+        match self.terms[operand] {
+            Term::Empty | Term::Nothing => self.get(Term::Empty),
+            Term::Star(_) => operand,
+            _ => self.get(Term::Star(operand)),
+        }
+    }
+
+    fn not(&mut self, operand: usize) -> usize {
+        match self.terms[operand] {
+            Term::Not(operand) => operand,
+            _ => self.get(Term::Not(operand)),
+        }
+    }
+
+    /// The derivative of the term with respect to a byte. The derivatives of
+    /// the operands that it needs are computed first and remembered.
+    fn derivative(&mut self, id: usize, byte: u8) -> usize {
+        let mut steps = vec![(id, false)];
+        while let Some((id, ready)) = steps.pop() {
+            if self.derivatives.contains_key(&(id, byte)) {
+                continue;
+            }
+            if !ready {
+                steps.push((id, true));
+                let needed = match self.terms[id] {
+                    Term::Concat(left, right) if self.nullable[left] => vec![left, right],
+                    Term::Concat(left, _) => vec![left],
+                    _ => self.operands(id),
+                };
+                steps.extend(needed.into_iter().map(|id| (id, false)));
+                continue;
+            }
+            let derivatives = &self.derivatives;
+            let derivative_of = |id| derivatives[&(id, byte)];
+            let derivative = match self.terms[id].clone() {
+                Term::Empty | Term::Nothing => self.get(Term::Nothing),
+                Term::Character(character) if character == byte => self.get(Term::Empty),
+                Term::Class(ranges)
+                    if ranges
+                        .iter()
+                        .any(|&(start, end)| start <= byte && byte <= end) =>
+                {
+                    self.get(Term::Empty)
+                }
+                Term::Character(_) | Term::Class(_) => self.get(Term::Nothing),
+                Term::Union(ids) => {
+                    let derivatives = ids.iter().map(|&id| derivative_of(id)).collect();
+                    self.union(derivatives)
+                }
+                Term::And(ids) => {
+                    let derivatives = ids.iter().map(|&id| derivative_of(id)).collect();
+                    self.and(derivatives)
+                }
+                Term::Concat(left, right) => {
+                    let second = self.nullable[left].then(|| derivative_of(right));
+                    let first = derivative_of(left);
+                    let first = self.concat(first, right);
+                    match second {
+                        Some(second) => self.union(vec![first, second]),
+                        None => first,
+                    }
+                }
+                Term::Star(operand) => {
+                    let derivative = derivative_of(operand);
+                    self.concat(derivative, id)
+                }
+                Term::Not(operand) => {
+                    let derivative = derivative_of(operand);
+                    self.not(derivative)
+                }
+            };
+            self.derivatives.insert((id, byte), derivative);
+        }
+        self.derivatives[&(id, byte)]
+    }
+
+    /// The bytes split into ranges that every character and class of the
+    /// terms either contains whole or not at all. The bytes of a range have
+    /// the same derivatives.
+    fn byte_ranges(&self) -> Vec<(u8, u8)> {
+        let mut starts = [false; 257];
+        starts[0] = true;
+        for term in &self.terms {
+            let ranges = match term {
+                Term::Character(character) => vec![(*character, *character)],
+                Term::Class(ranges) => ranges.clone(),
+                _ => continue,
+            };
+            for (start, end) in ranges {
+                starts[start as usize] = true;
+                starts[end as usize + 1] = true;
+            }
+        }
+        let mut ranges = Vec::new();
+        for (byte, &start) in starts[..256].iter().enumerate() {
+            if start {
+                ranges.push((byte as u8, byte as u8));
+            } else {
+                ranges.last_mut().unwrap().1 = byte as u8;
+            }
+        }
+        ranges
+    }
+}
+
+/// Runs one of the smart constructors on nodes
+fn construct(operands: &[Node], constructor: fn(&mut Terms, Vec<usize>) -> usize) -> Node {
+    let mut terms = Terms::default();
+    let ids = operands.iter().map(|node| terms.supported(node)).collect();
+    let id = constructor(&mut terms, ids);
+    terms.extract(id)
+}
+
+/// Smart constructors that keep the derivatives in a normal form. Without them
+/// the derivatives would keep growing and the DFA construction would never
+/// terminate.
+///
+/// '|' operator, with r|r = r, r|0 = r and r|~0 = ~0
+pub fn union(left: Node, right: Node) -> Node {
+    construct(&[left, right], Terms::union)
+}
+
+/// '&' operator, with r&r = r, r&0 = 0 and r&~0 = r
+pub fn and(left: Node, right: Node) -> Node {
+    construct(&[left, right], Terms::and)
+}
+
+/// Concatenation, with 0r = r0 = 0, er = re = r and (rs)t = r(st)
+pub fn concat(left: Node, right: Node) -> Node {
+    construct(&[left, right], |terms, ids| terms.concat(ids[0], ids[1]))
+}
+
+/// '*' operator, with (r*)* = r* and 0* = e* = e. A lazy star becomes
+/// greedy, since the language is the same.
+pub fn star(operand: Node) -> Node {
+    construct(&[operand], |terms, ids| terms.star(ids[0]))
+}
+
+/// Complement, with ~~r = r
+pub fn not(operand: Node) -> Node {
+    construct(&[operand], |terms, ids| terms.not(ids[0]))
+}
+
+/// DFA whose states are the distinct derivatives of the regex
+pub struct DerivativeDFA {
+    terms: Terms,
+    /// The term of each state
+    states: Vec<usize>,
+    /// The ranges of bytes with the same derivatives
+    ranges: Vec<(u8, u8)>,
+    /// The next state for each range of bytes
+    transitions: Vec<Vec<usize>>,
+}
+
+impl DerivativeDFA {
+    /// Panics if the node has assertions, backreferences or lookarounds
+    pub fn new(node: Node) -> Self {
+        DerivativeDFA::try_new(node, DfaLimits::unlimited())
+            .unwrap_or_else(|reason| panic!("{}", reason))
+    }
+
+    /// Same as `new`, but fails as soon as the DFA grows over the limits, or
+    /// if the node has assertions, backreferences or lookarounds.
+    /// The memory counts the terms, which are stored twice, and a row of
+    /// the table for every state.
+    pub fn try_new(node: Node, limits: DfaLimits) -> Result<Self, String> {
+        let mut terms = Terms::default();
+        let start = terms.intern(&node)?;
+        let ranges = terms.byte_ranges();
+        let mut states = vec![start];
+        let mut ids: HashMap<usize, usize> = vec![(start, 0)].into_iter().collect();
+        let mut transitions = Vec::new();
+        // The states are visited in the order of their ids
+        while transitions.len() < states.len() {
+            let term = states[transitions.len()];
+            let mut row = Vec::new();
+            for &(first, _) in &ranges {
+                let derivative = terms.derivative(term, first);
+                let next = states.len();
+                let to = *ids.entry(derivative).or_insert_with(|| {
+                    states.push(derivative);
+                    next
+                });
+                row.push(to);
+                let memory = 2 * terms.terms.len() * size_of::<Term>()
+                    + states.len() * ranges.len() * size_of::<usize>();
+                limits.check(states.len(), memory)?;
+            }
+            transitions.push(row);
+        }
+
+        Ok(DerivativeDFA {
+            terms,
+            states,
+            ranges,
+            transitions,
+        })
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.states.len()
+    }
+
+    fn is_accept_state(&self, state: usize) -> bool {
+        self.terms.nullable[self.states[state]]
+    }
+
+    pub fn to_dfa(self) -> DFA {
+        let accept = (0..self.states.len())
+            .filter(|&state| self.is_accept_state(state))
+            .collect();
+        let transitions = self
+            .transitions
+            .iter()
+            .map(|row| {
+                let mut bytes = [0; 256];
+                for (&(first, last), &to) in self.ranges.iter().zip(row) {
+                    bytes[first as usize..=last as usize].fill(to);
+                }
+                bytes
+            })
+            .collect();
+        DFA::new(0, accept, transitions)
+    }
+
+    /// Converts into an NFA fragment, leaving out the transitions into the
+    /// empty language
+    pub fn to_fragment(self, arena: &mut StateArena) -> NFAFragment {
+        let labels: Vec<i32> = self.states.iter().map(|_| arena.new_state()).collect();
+        let accept = (0..self.states.len())
+            .filter(|&state| self.is_accept_state(state))
+            .map(|state| labels[state])
+            .collect();
+        for (from, row) in self.transitions.iter().enumerate() {
+            for (&(first, last), &to) in self.ranges.iter().zip(row) {
+                if self.terms.terms[self.states[to]] != Term::Nothing {
+                    for character in first..=last {
+                        arena.create_transition(labels[from], Some(character), labels[to]);
+                    }
+                }
+            }
+        }
+        NFAFragment::new(labels[0], accept)
+    }
+}
+
+//grcov-excl-start
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nfa::Construction;
+    use crate::parser::Parser;
+    use crate::regex::Regex;
+    use crate::scanner::Scanner;
+
+    fn parse(regex: &str) -> Node {
+        let scanner = Scanner::new(regex.to_string());
+        let mut parser = Parser::new(scanner);
+        parser.parse().unwrap()
+    }
+
+    /// All of the strings over the alphabet up to the given length
+    fn strings(alphabet: &[char], max_len: usize) -> Vec<String> {
+        let mut result = vec!["".to_string()];
+        let mut previous = vec!["".to_string()];
+        for _ in 0..max_len {
+            let mut next = Vec::new();
+            for s in previous {
+                for c in alphabet {
+                    next.push(format!("{}{}", s, c));
+                }
+            }
+            result.extend(next.iter().cloned());
+            previous = next;
+        }
+        result
+    }
+
+    #[test]
+    fn nullable() {
+        assert!(parse("a*").nullable());
+        assert!(parse("a*b*").nullable());
+        assert!(parse("a|b*").nullable());
+        assert!(!parse("ab*").nullable());
+        assert!(!Node::complement(parse("a*")).nullable());
+    }
+
+    #[test]
+    fn derivative() {
+        let node = parse("ab").normalize();
+        assert_eq!(node.derivative(b'a'), Node::character(b'b'));
+        assert_eq!(node.derivative(b'b'), Node::Nothing);
+        assert_eq!(node.derivative(b'a').derivative(b'b'), Node::Empty);
+    }
+
+    #[test]
+    fn smart_constructors() {
+        let a = Node::character(b'a');
+        let b = Node::character(b'b');
+        assert_eq!(union(a.clone(), a.clone()), a);
+        assert_eq!(union(a.clone(), Node::Nothing), a);
+        assert_eq!(union(a.clone(), b.clone()), union(b.clone(), a.clone()));
+        assert_eq!(concat(Node::Empty, a.clone()), a);
+        assert_eq!(concat(a.clone(), Node::Nothing), Node::Nothing);
+        assert_eq!(star(star(a.clone())), star(a.clone()));
+        assert_eq!(and(a.clone(), not(Node::Nothing)), a);
+        assert_eq!(not(not(b.clone())), b);
+    }
+
+    #[test]
+    fn assertions_are_rejected() {
+        let node = Node::intersection(parse("^a"), parse("a"));
+        assert!(DerivativeDFA::try_new(node.clone(), DfaLimits::unlimited()).is_err());
+        for construction in [Construction::Thompson, Construction::Glushkov] {
+            let nfa = construction.try_to_nfa(node.clone(), DfaLimits::unlimited());
+            assert!(nfa.is_err());
+        }
+        let node = Node::complement(parse("(a)\\1"));
+        assert!(DerivativeDFA::try_new(node, DfaLimits::unlimited()).is_err());
+    }
+
+    #[test]
+    fn finite_state_count() {
+        let dfa = DerivativeDFA::new(parse("(a|b)*abb"));
+        assert_eq!(dfa.state_count(), 5);
+    }
+
+    #[test]
+    fn same_as_thompson() {
+        let regexes = vec![
+            "aa(b|cc)*a",
+            "(a|b)*abb",
+            "(a*b*)*c",
+            "a(b|c)*|c*",
+            "((a|b)(a|b))*",
+            "(0|(-|()*)(1|2|3|4|5|6|7|8|9)(0|1|2|3|4|5|6|7|8|9)*)",
+        ];
+        let inputs = strings(&['a', 'b', 'c', '0', '-', '1'], 4);
+        for regex in regexes {
+            let thompson = Regex::new(regex.to_string()).unwrap();
+            let derivatives = Regex::with_derivatives(regex.to_string()).unwrap();
+            for input in inputs.iter() {
+                assert_eq!(
+                    thompson.matches(input.to_string()).unwrap(),
+                    derivatives.matches(input.to_string()).unwrap(),
+                    "regex {} input {}",
+                    regex,
+                    input
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn intersection_and_complement() {
+        // Strings over {a, b} with an even length that do not contain "bb"
+        let even = parse("((a|b)(a|b))*");
+        let bb = parse("(a|b)*bb(a|b)*");
+        let node = Node::intersection(even, Node::complement(bb));
+
+        let derivative_dfa = DerivativeDFA::new(node.clone()).to_dfa();
+        let thompson_dfa = Construction::Thompson.to_nfa(node).to_dfa();
+
+        for input in strings(&['a', 'b'], 6) {
+            let expected = input.len() % 2 == 0 && !input.contains("bb");
+            assert_eq!(
+                derivative_dfa.recognizer().accepts(input.as_bytes()),
+                expected
+            );
+            assert_eq!(
+                thompson_dfa.recognizer().accepts(input.as_bytes()),
+                expected
+            );
+        }
+        let everything = DerivativeDFA::new(Node::complement(Node::Nothing)).to_dfa();
+        assert!(everything.recognizer().accepts(b"anything"));
+    }
+
+    #[test]
+    fn long_regex() {
+        let literal = "ab".repeat(15_000);
+        let regex = Regex::with_derivatives(literal.clone() + "(?!c)").unwrap();
+        assert!(regex.matches(literal.clone()).unwrap());
+        assert!(!regex.matches(literal.clone() + "a").unwrap());
+        let node = Node::complement(parse(&literal));
+        assert_eq!(DerivativeDFA::new(node.clone()).state_count(), 30_002);
+        assert!(node.derivative(b'a').derivative(b'b').nullable());
+    }
+}
+
+//grcov-excl-stop
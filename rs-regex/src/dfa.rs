@@ -1,72 +1,542 @@
-use std::collections::HashSet;
+use crate::automaton::Automaton;
+pub use crate::automaton::Recognizer;
+use crate::classes::{Alphabet, ByteClasses};
+use crate::look::Context;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Identifies the binary format of a serialized DFA
+const MAGIC: &[u8; 8] = b"RSREGDFA";
+/// Bumped whenever the binary format changes
+const VERSION: u16 = 2;
+/// Magic, version, class count, state count and the start states
+const HEADER_LEN: usize = 8 + 2 + 2 + 4 + 4 * 4;
+
+/// The most states a DFA may have unless `RegexBuilder` sets another limit
+pub const DEFAULT_DFA_STATE_LIMIT: usize = 100_000;
+/// The most memory building a DFA may use unless `RegexBuilder` sets another
+/// limit, 64 MiB
+pub const DEFAULT_DFA_SIZE_LIMIT: usize = 64 << 20;
+
+/// How big a DFA may get while it is built. A regex such as
+/// `(a|b)*a(a|b)(a|b)...` needs exponentially many states, and building its
+/// DFA fails instead of using up the time and the memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DfaLimits {
+    pub states: usize,
+    /// An estimate of the bytes used by the table and the state sets
+    pub memory: usize,
+}
+
+impl Default for DfaLimits {
+    fn default() -> Self {
+        DfaLimits {
+            states: DEFAULT_DFA_STATE_LIMIT,
+            memory: DEFAULT_DFA_SIZE_LIMIT,
+        }
+    }
+}
+
+impl DfaLimits {
+    pub fn unlimited() -> Self {
+        DfaLimits {
+            states: usize::MAX,
+            memory: usize::MAX,
+        }
+    }
+
+    pub(crate) fn check(&self, states: usize, memory: usize) -> Result<(), String> {
+        if states > self.states {
+            return Err(format!("The DFA needs more than {} states", self.states));
+        }
+        if memory > self.memory {
+            return Err(format!("The DFA needs more than {} bytes", self.memory));
+        }
+        Ok(())
+    }
+}
 
 /// Deterministic finite automaton
 /// https://en.wikipedia.org/wiki/Deterministic_finite_automaton
-pub struct DFA {
-    pub start_state: HashSet<i32>,
-    pub accept_states: HashSet<i32>,
-    pub transitions: Box<dyn Fn(HashSet<i32>, u8) -> HashSet<i32>>,
+///
+/// The transitions are stored in a table with a row for every state and a
+/// column for every alphabet class. Bytes that behave the same way share a
+/// class.
+///
+/// `start_state` and `accept_states` are for matching the whole input. For
+/// searching inside the input, the DFA also knows where to start after any
+/// byte and which states match before any byte, which is what the
+/// assertions such as '^' and '\b' need.
+pub struct DFA<S = u8> {
+    pub start_state: usize,
+    pub accept_states: HashSet<usize>,
+    /// The start state for every context of the byte before the start
+    starts: [usize; 4],
+    /// A bit for every context of the next byte, before which the state
+    /// matches. The bit of `Context::Edge` is set for the accept states.
+    accepts_before: Vec<u8>,
+    classes: [u8; 256],
+    class_count: usize,
+    transitions: Vec<usize>,
+    /// The intervals of the symbols, whose numbers the transitions read
+    alphabet: Alphabet<S>,
 }
 
 impl DFA {
-    pub fn new(
-        start_state: HashSet<i32>,
-        accept_states: HashSet<i32>,
-        transitions: Box<dyn Fn(HashSet<i32>, u8) -> HashSet<i32>>,
+    /// Creates a DFA with a transition for every byte from every state
+    pub fn new(start_state: usize, accept_states: HashSet<usize>, rows: Vec<[usize; 256]>) -> Self {
+        let accepts_before = (0..rows.len())
+            .map(|state| {
+                if accept_states.contains(&state) {
+                    ALL_CONTEXTS
+                } else {
+                    0
+                }
+            })
+            .collect();
+        DFA::with_looks([start_state; 4], accepts_before, rows)
+    }
+
+    /// Creates a DFA whose start and accept states depend on the bytes around
+    /// the match, see `start_state_after` and `accepts_before`
+    pub fn with_looks(
+        starts: [usize; 4],
+        accepts_before: Vec<u8>,
+        rows: Vec<[usize; 256]>,
+    ) -> Self {
+        let transitions = rows.into_iter().flatten().collect();
+        DFA::with_classes(
+            starts,
+            accepts_before,
+            ByteClasses::singletons(),
+            transitions,
+        )
+    }
+
+    /// Creates a DFA from a table with a row for every state and a column
+    /// for every byte class
+    pub fn with_classes(
+        starts: [usize; 4],
+        accepts_before: Vec<u8>,
+        classes: ByteClasses,
+        transitions: Vec<usize>,
     ) -> Self {
         DFA {
-            start_state,
-            accept_states,
+            start_state: starts[Context::Edge.index()],
+            accept_states: accept_states(&accepts_before),
+            starts,
+            accepts_before,
+            classes: *classes.as_array(),
+            class_count: classes.count(),
             transitions,
+            alphabet: Alphabet::bytes(),
+        }
+    }
+}
+
+impl<S: Ord + Clone> DFA<S> {
+    /// The same automaton, reading the symbols of the alphabet
+    pub(crate) fn with_alphabet<T>(self, alphabet: Alphabet<T>) -> DFA<T> {
+        DFA {
+            start_state: self.start_state,
+            accept_states: self.accept_states,
+            starts: self.starts,
+            accepts_before: self.accepts_before,
+            classes: self.classes,
+            class_count: self.class_count,
+            transitions: self.transitions,
+            alphabet,
         }
     }
 
-    pub fn recognizer(&self) -> Recognizer {
+    /// Checks if the DFA accepts the whole input
+    pub fn accepts(&self, input: &[S]) -> bool {
+        let input: Vec<u8> = input
+            .iter()
+            .map(|symbol| self.alphabet.get(symbol))
+            .collect();
+        self.recognizer().accepts(&input)
+    }
+
+    pub fn recognizer(&self) -> Recognizer<'_, DFA<S>> {
         Recognizer::new(self)
     }
 
-    pub fn get_transition(&self, state: HashSet<i32>, character: u8) -> HashSet<i32> {
-        (self.transitions)(state, character)
+    pub fn state_count(&self) -> usize {
+        self.transitions.len() / self.class_count
+    }
+
+    /// The state to start from when the match starts after the byte, or at
+    /// the start of the input when there is no byte
+    pub fn start_state_after(&self, previous: Option<u8>) -> usize {
+        self.starts[Context::of(previous).index()]
+    }
+
+    /// Checks if the state matches when the next byte follows it, or when the
+    /// input ends if there is no byte
+    pub fn accepts_before(&self, state: usize, next: Option<u8>) -> bool {
+        self.accepts_before[state] & (1 << Context::of(next).index()) != 0
+    }
+
+    pub fn get_transition(&self, state: usize, character: u8) -> usize {
+        self.transitions[state * self.class_count + self.classes[character as usize] as usize]
+    }
+
+    /// The alphabet class of every byte
+    pub fn classes(&self) -> &[u8; 256] {
+        &self.classes
+    }
+
+    pub fn class_count(&self) -> usize {
+        self.class_count
+    }
+
+    /// The destination state from a given state and an alphabet class
+    pub fn get_class_transition(&self, state: usize, class: usize) -> usize {
+        self.transitions[state * self.class_count + class]
     }
-}
 
-/// The runtime for recognizing the input strings
-pub struct Recognizer<'a> {
-    dfa: &'a DFA,
-    current_state: HashSet<i32>,
+    /// A dead state is a state that rejects and that can never be left
+    pub fn is_dead_state(&self, state: usize) -> bool {
+        self.accepts_before[state] == 0
+            && (0..self.class_count).all(|class| self.get_class_transition(state, class) == state)
+    }
+
+    /// Minimizes the DFA with Moore's algorithm
+    /// https://en.wikipedia.org/wiki/DFA_minimization
+    ///
+    /// The states are first split into blocks by when they accept, and the
+    /// blocks are refined until all of the states in a block have their
+    /// transitions into the same blocks. The states of the result are
+    /// numbered in breadth-first order starting from the start states.
+    pub fn minimize(&self) -> DFA<S> {
+        let states = self.reachable_states();
+        let mut blocks: HashMap<usize, usize> = states
+            .iter()
+            .map(|&state| (state, self.accepts_before[state] as usize))
+            .collect();
+        let mut block_count = 0;
+        loop {
+            let mut signatures = HashMap::<Vec<usize>, usize>::new();
+            let mut refined = HashMap::new();
+            for &state in states.iter() {
+                let mut signature = vec![blocks[&state]];
+                for class in 0..self.class_count {
+                    signature.push(blocks[&self.get_class_transition(state, class)]);
+                }
+                let next_id = signatures.len();
+                refined.insert(state, *signatures.entry(signature).or_insert(next_id));
+            }
+            blocks = refined;
+            if signatures.len() == block_count {
+                break;
+            }
+            block_count = signatures.len();
+        }
+
+        // Renumbers the blocks in breadth-first order
+        let mut ids = HashMap::new();
+        let mut representatives = Vec::new();
+        let mut not_visited = VecDeque::new();
+        let mut starts = [0; 4];
+        for (i, &start) in self.starts.iter().enumerate() {
+            let next_id = representatives.len();
+            starts[i] = *ids.entry(blocks[&start]).or_insert_with(|| {
+                representatives.push(start);
+                not_visited.push_back(start);
+                next_id
+            });
+        }
+        let mut transitions = Vec::new();
+        while let Some(state) = not_visited.pop_front() {
+            for class in 0..self.class_count {
+                let to = self.get_class_transition(state, class);
+                let next_id = representatives.len();
+                let id = *ids.entry(blocks[&to]).or_insert_with(|| {
+                    representatives.push(to);
+                    not_visited.push_back(to);
+                    next_id
+                });
+                transitions.push(id);
+            }
+        }
+        let accepts_before: Vec<u8> = representatives
+            .iter()
+            .map(|&state| self.accepts_before[state])
+            .collect();
+
+        DFA {
+            start_state: starts[Context::Edge.index()],
+            accept_states: accept_states(&accepts_before),
+            starts,
+            accepts_before,
+            classes: self.classes,
+            class_count: self.class_count,
+            transitions,
+            alphabet: self.alphabet.clone(),
+        }
+    }
+
+    /// The states that can be reached from the start states
+    fn reachable_states(&self) -> Vec<usize> {
+        let mut seen = HashSet::new();
+        let mut visited: Vec<usize> = self
+            .starts
+            .iter()
+            .cloned()
+            .filter(|&start| seen.insert(start))
+            .collect();
+        let mut i = 0;
+        while i < visited.len() {
+            for class in 0..self.class_count {
+                let to = self.get_class_transition(visited[i], class);
+                if seen.insert(to) {
+                    visited.push(to);
+                }
+            }
+            i += 1;
+        }
+        visited
+    }
 }
 
-impl<'a> Recognizer<'a> {
-    pub fn new(dfa: &'a DFA) -> Self {
-        let state = dfa.start_state.clone();
-        Recognizer {
-            dfa,
-            current_state: state,
+impl DFA {
+    /// The DFA that matches wherever a match of this DFA ends, as if a match
+    /// could start at every position. Its states are sets of the states of
+    /// this DFA, and after every byte the start state for a match starting
+    /// after that byte joins the set. The dead states are left out of the
+    /// sets, since they never match.
+    pub fn unanchored(&self) -> DFA {
+        self.try_unanchored(DfaLimits::unlimited())
+            .expect("unlimited DFA")
+    }
+
+    /// Same as `unanchored`, but fails if the DFA grows over the limits
+    pub fn try_unanchored(&self, limits: DfaLimits) -> Result<DFA, String> {
+        let mut representatives = vec![0u8; self.class_count];
+        for byte in (0..=255u8).rev() {
+            representatives[self.classes[byte as usize] as usize] = byte;
+        }
+        let dead: Vec<bool> = (0..self.state_count())
+            .map(|state| self.is_dead_state(state))
+            .collect();
+        let mut ids: HashMap<Vec<usize>, usize> = HashMap::new();
+        let mut subsets: Vec<Vec<usize>> = Vec::new();
+        let mut memory = 0;
+        let mut id = |mut subset: Vec<usize>, subsets: &mut Vec<Vec<usize>>| {
+            subset.retain(|&state| !dead[state]);
+            subset.sort_unstable();
+            subset.dedup();
+            if let Some(&id) = ids.get(&subset) {
+                return Ok(id);
+            }
+            // The set is stored twice, and the state needs a table row
+            memory += (2 * subset.len() + self.class_count) * size_of::<usize>();
+            limits.check(subsets.len() + 1, memory)?;
+            ids.insert(subset.clone(), subsets.len());
+            subsets.push(subset);
+            Ok::<usize, String>(subsets.len() - 1)
+        };
+
+        let mut starts = [0; 4];
+        for (i, &start) in self.starts.iter().enumerate() {
+            starts[i] = id(vec![start], &mut subsets)?;
+        }
+        let mut transitions = Vec::new();
+        let mut accepts_before = Vec::new();
+        while accepts_before.len() < subsets.len() {
+            let subset = subsets[accepts_before.len()].clone();
+            for (class, &byte) in representatives.iter().enumerate() {
+                let next = subset
+                    .iter()
+                    .map(|&state| self.get_class_transition(state, class))
+                    .chain([self.start_state_after(Some(byte))])
+                    .collect();
+                transitions.push(id(next, &mut subsets)?);
+            }
+            accepts_before.push(
+                subset
+                    .iter()
+                    .fold(0, |accepts, &state| accepts | self.accepts_before[state]),
+            );
         }
+
+        Ok(DFA {
+            start_state: starts[Context::Edge.index()],
+            accept_states: accept_states(&accepts_before),
+            starts,
+            accepts_before,
+            classes: self.classes,
+            class_count: self.class_count,
+            transitions,
+            alphabet: self.alphabet.clone(),
+        })
     }
 
-    fn make_transition(&mut self, character: u8) {
-        let state = self.current_state.clone();
-        self.current_state = self.dfa.get_transition(state, character)
+    /// Serializes the DFA into a versioned binary format. All of the numbers
+    /// are little-endian.
+    ///
+    /// | field        | size                           |
+    /// | ------------ | ------------------------------ |
+    /// | magic        | 8 bytes, "RSREGDFA"            |
+    /// | version      | u16                            |
+    /// | class count  | u16                            |
+    /// | state count  | u32                            |
+    /// | start states | u32 for every context          |
+    /// | classes      | 256 bytes, class of every byte |
+    /// | transitions  | u32 for every state and class  |
+    /// | accept flags | u8 for every state             |
+    ///
+    /// The start states and the bits of the accept flags are in the order of
+    /// `Context::ALL`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let state_count = self.state_count();
+        let mut bytes =
+            Vec::with_capacity(HEADER_LEN + 256 + self.transitions.len() * 4 + state_count);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.class_count as u16).to_le_bytes());
+        bytes.extend_from_slice(&(state_count as u32).to_le_bytes());
+        for &start in self.starts.iter() {
+            bytes.extend_from_slice(&(start as u32).to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.classes);
+        for &to in self.transitions.iter() {
+            bytes.extend_from_slice(&(to as u32).to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.accepts_before);
+        bytes
     }
 
-    /// Check if the current state is an accept state
-    fn is_accept_state(&self) -> bool {
-        !(&self.dfa.accept_states & &self.current_state).is_empty()
+    /// Deserializes a DFA written by `to_bytes`. The input is validated, so
+    /// it can come from an untrusted source.
+    pub fn from_bytes(bytes: &[u8]) -> Result<DFA, String> {
+        let mut reader = Reader { bytes };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err("Not a compiled DFA".to_string());
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(format!("Unsupported DFA format version {}", version));
+        }
+        let class_count = reader.u16()? as usize;
+        if class_count == 0 || class_count > 256 {
+            return Err(format!("Invalid class count {}", class_count));
+        }
+        let state_count = reader.u32()? as usize;
+        let mut starts = [0; 4];
+        for start in starts.iter_mut() {
+            *start = reader.u32()? as usize;
+            if *start >= state_count {
+                return Err(format!("Start state {} does not exist", start));
+            }
+        }
+
+        let mut classes = [0; 256];
+        classes.copy_from_slice(reader.take(256)?);
+        if classes.iter().any(|&class| class as usize >= class_count) {
+            return Err("Invalid alphabet class".to_string());
+        }
+
+        // Checked before allocating anything based on the header
+        let table_len = state_count
+            .checked_mul(class_count)
+            .filter(|len| {
+                len.checked_mul(4)
+                    .is_some_and(|size| size <= reader.bytes.len())
+            })
+            .ok_or_else(|| "Unexpected end of the DFA".to_string())?;
+        let mut transitions = Vec::with_capacity(table_len);
+        for _ in 0..table_len {
+            let to = reader.u32()? as usize;
+            if to >= state_count {
+                return Err(format!("Transition to state {} does not exist", to));
+            }
+            transitions.push(to);
+        }
+
+        let accepts_before = reader.take(state_count)?.to_vec();
+        if let Some(flags) = accepts_before.iter().find(|&&flags| flags > ALL_CONTEXTS) {
+            return Err(format!("Invalid accept flags {}", flags));
+        }
+        if !reader.bytes.is_empty() {
+            return Err("Trailing bytes after the DFA".to_string());
+        }
+
+        Ok(DFA {
+            start_state: starts[Context::Edge.index()],
+            accept_states: accept_states(&accepts_before),
+            starts,
+            accepts_before,
+            classes,
+            class_count,
+            transitions,
+            alphabet: Alphabet::bytes(),
+        })
     }
+}
+
+/// The accept flags of a state that matches before any byte
+const ALL_CONTEXTS: u8 = 0b1111;
+
+/// The states that accept at the end of the input
+fn accept_states(accepts_before: &[u8]) -> HashSet<usize> {
+    let edge = 1 << Context::Edge.index();
+    (0..accepts_before.len())
+        .filter(|&state| accepts_before[state] & edge != 0)
+        .collect()
+}
 
-    /// Make transitions along the dfa given by the input string and check if
-    /// We end up in an accept state
-    pub fn accepts(&mut self, input: &[u8]) -> bool {
-        for &symbol in input {
-            self.make_transition(symbol);
+/// Reads little-endian numbers from a byte slice, failing on truncated input
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < len {
+            return Err("Unexpected end of the DFA".to_string());
         }
-        self.is_accept_state()
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+}
+
+impl<S: Ord + Clone> Automaton for DFA<S> {
+    type State = usize;
+
+    fn start_state(&self) -> usize {
+        self.start_state
+    }
+
+    fn step(&self, state: &usize, byte: u8) -> usize {
+        self.get_transition(*state, byte)
+    }
+
+    fn is_accepting(&self, state: &usize) -> bool {
+        self.accept_states.contains(state)
+    }
+
+    fn is_dead(&self, state: &usize) -> bool {
+        self.is_dead_state(*state)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
     use crate::parser::Parser;
     use crate::scanner::Scanner;
 
@@ -121,6 +591,110 @@ mod tests {
         let dfa = nfa.unwrap().to_dfa();
         let recognizer = dfa.recognizer();
         let state = dfa.get_transition(recognizer.current_state, 'a' as u8);
-        assert!(state.contains(&mut 3));
+        assert!(!dfa.accept_states.contains(&state));
+        let state = dfa.get_transition(state, b'b');
+        assert!(dfa.accept_states.contains(&state));
+    }
+
+    #[test]
+    fn minimize() {
+        // (a|b)*abb has a well known minimal DFA with 4 states and a dead
+        // state for the rest of the bytes
+        let dfa = compile("(a|b)*abb");
+        let minimal = dfa.minimize();
+        assert!(minimal.state_count() < dfa.state_count());
+        assert_eq!(minimal.state_count(), 5);
+        assert_eq!(minimal.start_state, 0);
+        for input in ["abb", "aabb", "babb", "ab", "abba", "", "abbc"] {
+            assert_eq!(
+                minimal.recognizer().accepts(input.as_bytes()),
+                dfa.recognizer().accepts(input.as_bytes())
+            );
+        }
+    }
+
+    #[test]
+    fn dead_state() {
+        let dfa = compile("ab").minimize();
+        let dead = dfa.get_transition(dfa.start_state, b'b');
+        assert!(dfa.is_dead_state(dead));
+        assert!(!dfa.is_dead_state(dfa.start_state));
+    }
+
+    #[test]
+    fn unanchored() {
+        let dfa = compile("ab").unanchored();
+        assert!(dfa.recognizer().accepts(b"ab"));
+        assert!(dfa.recognizer().accepts(b"xxaab"));
+        assert!(!dfa.recognizer().accepts(b"abx"));
+        assert!(!dfa.recognizer().accepts(b""));
+
+        // A match can start after every byte, so '\b' sees the byte before
+        let dfa = compile("\\bab").unanchored();
+        assert!(dfa.recognizer().accepts(b"x ab"));
+        assert!(!dfa.recognizer().accepts(b"xab"));
+    }
+
+    fn compile(regex: &str) -> DFA {
+        let scanner = Scanner::new(regex.to_string());
+        let mut parser = Parser::new(scanner);
+        parser.expr().unwrap().to_dfa()
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let dfa = compile("aa(b|cc)*a");
+        let loaded = DFA::from_bytes(&dfa.to_bytes()).unwrap();
+        assert_eq!(loaded.state_count(), dfa.state_count());
+        for input in ["aaa", "aaba", "aacca", "aa", "aabaa", "aaccca"] {
+            assert_eq!(
+                loaded.recognizer().accepts(input.as_bytes()),
+                dfa.recognizer().accepts(input.as_bytes())
+            );
+        }
+    }
+
+    #[test]
+    fn bytes_header() {
+        let bytes = compile("ab").to_bytes();
+        assert_eq!(&bytes[0..8], b"RSREGDFA");
+        assert_eq!(&bytes[8..10], &[2, 0]);
+        // 'a', 'b' and the rest of the bytes
+        assert_eq!(&bytes[10..12], &[3, 0]);
+    }
+
+    #[test]
+    fn from_bytes_rejects_invalid_input() {
+        let bytes = compile("ab").to_bytes();
+        assert!(DFA::from_bytes(&[]).is_err());
+        assert!(DFA::from_bytes(b"not a dfa at all").is_err());
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[8] = 1;
+        assert!(DFA::from_bytes(&wrong_version).is_err());
+
+        for len in [10, 20, 100, bytes.len() - 1] {
+            assert!(DFA::from_bytes(&bytes[..len]).is_err());
+        }
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(DFA::from_bytes(&trailing).is_err());
+
+        let mut bad_start = bytes.clone();
+        bad_start[24..28].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(DFA::from_bytes(&bad_start).is_err());
+
+        let mut bad_transition = bytes.clone();
+        bad_transition[HEADER_LEN + 256..HEADER_LEN + 260].copy_from_slice(&1000u32.to_le_bytes());
+        assert!(DFA::from_bytes(&bad_transition).is_err());
+
+        let mut bad_flag = bytes.clone();
+        *bad_flag.last_mut().unwrap() = 16;
+        assert!(DFA::from_bytes(&bad_flag).is_err());
+
+        let mut huge = bytes;
+        huge[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(DFA::from_bytes(&huge).is_err());
     }
 }
@@ -0,0 +1,34 @@
+use crate::regex::Engine;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+/// The reasons why a regex can not be compiled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegexError {
+    /// The regex is not valid
+    Syntax(String),
+    /// The regex is valid, but the engine can not match it
+    Unsupported { engine: Engine, reason: String },
+    /// The automaton would grow over the limits set in `RegexBuilder`
+    TooBig(String),
+}
+
+impl Display for RegexError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            RegexError::Syntax(message) => write!(f, "{}", message),
+            RegexError::Unsupported { engine, reason } => {
+                write!(f, "Not supported by the {} engine: {}", engine, reason)
+            }
+            RegexError::TooBig(reason) => write!(f, "The regex is too big: {}", reason),
+        }
+    }
+}
+
+impl Error for RegexError {}
+
+impl From<String> for RegexError {
+    fn from(message: String) -> Self {
+        RegexError::Syntax(message)
+    }
+}
@@ -0,0 +1,290 @@
+use crate::ast::Node;
+use crate::derivative::DerivativeDFA;
+use crate::dfa::DfaLimits;
+use crate::nfa::{Construction, NFA};
+use crate::nfa_fragment::{NFAFragment, StateArena};
+
+/// Glushkov's construction, also known as the position automaton
+/// https://en.wikipedia.org/wiki/Glushkov%27s_construction_algorithm
+///
+/// Every occurrence of a character in the regex is a position and becomes a
+/// state of the NFA, plus one extra start state. Unlike Thompson's
+/// construction, the result has no epsilon transitions.
+pub struct Glushkov {
+    symbols: Vec<Vec<(u8, u8)>>,
+    follow: Vec<Vec<usize>>,
+}
+
+/// The sets computed for every node of the AST
+struct Positions {
+    nullable: bool,
+    first: Vec<usize>,
+    last: Vec<usize>,
+}
+
+impl Glushkov {
+    fn new() -> Self {
+        Glushkov {
+            symbols: Vec::new(),
+            follow: Vec::new(),
+        }
+    }
+
+    /// Builds the NFA for the node. Intersection and complement have no
+    /// positions, so those are determinized with derivatives, which is
+    /// epsilon free as well. Assertions are epsilon transitions with a
+    /// condition, so regexes with them use Thompson's construction instead.
+    pub fn to_nfa(node: Node) -> NFA {
+        Glushkov::try_to_nfa(node, DfaLimits::unlimited())
+            .unwrap_or_else(|reason| panic!("{}", reason))
+    }
+
+    /// Same as `to_nfa`, but fails if the DFA of the intersections and the
+    /// complements grows over the limits or they have assertions inside
+    pub fn try_to_nfa(node: Node, limits: DfaLimits) -> Result<NFA, String> {
+        if node.has_looks() {
+            return Construction::Thompson.try_to_nfa(node, limits);
+        }
+        if node.has_boolean_operators() {
+            let mut arena = StateArena::new();
+            let fragment = DerivativeDFA::try_new(node, limits)?.to_fragment(&mut arena);
+            return Ok(arena.to_nfa(fragment));
+        }
+
+        let mut glushkov = Glushkov::new();
+        let root = glushkov.positions(&node);
+
+        // State 0 is the start state and position p is the state p + 1
+        let mut accept: Vec<i32> = root.last.iter().map(|&p| p as i32 + 1).collect();
+        if root.nullable {
+            accept.push(0);
+        }
+        let mut arena = StateArena::new();
+        for &to in root.first.iter() {
+            glushkov.create_transitions(&mut arena, 0, to);
+        }
+        for (from, follow) in glushkov.follow.iter().enumerate() {
+            for &to in follow {
+                glushkov.create_transitions(&mut arena, from as i32 + 1, to);
+            }
+        }
+        Ok(arena.to_nfa(NFAFragment::new(0, accept.into_iter().collect())))
+    }
+
+    /// Creates the transitions into a position for all of its characters
+    fn create_transitions(&self, arena: &mut StateArena, from: i32, to: usize) {
+        for &(start, end) in self.symbols[to].iter() {
+            for character in start..=end {
+                arena.create_transition(from, Some(character), to as i32 + 1);
+            }
+        }
+    }
+
+    /// Adds a new position for the characters in the ranges
+    fn position(&mut self, ranges: Vec<(u8, u8)>) -> Positions {
+        let position = self.symbols.len();
+        self.symbols.push(ranges);
+        self.follow.push(Vec::new());
+        Positions {
+            nullable: false,
+            first: vec![position],
+            last: vec![position],
+        }
+    }
+
+    /// Numbers the positions and computes the nullable, first and last sets
+    /// bottom-up, collecting the follow sets on the way. The tree is walked
+    /// with an explicit stack, since a long regex such as 'aaa...' is as
+    /// deep as it is long.
+    fn positions(&mut self, node: &Node) -> Positions {
+        /// An operator waits on the stack until its operands are numbered
+        enum Step<'a> {
+            Visit(&'a Node),
+            Union,
+            Concat,
+            Star,
+            Plus,
+        }
+
+        let mut steps = vec![Step::Visit(node)];
+        let mut sets: Vec<Positions> = Vec::new();
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Visit(node) => match node {
+                    Node::Empty => sets.push(Positions {
+                        nullable: true,
+                        first: Vec::new(),
+                        last: Vec::new(),
+                    }),
+                    Node::Nothing => sets.push(Positions {
+                        nullable: false,
+                        first: Vec::new(),
+                        last: Vec::new(),
+                    }),
+                    Node::Character { character } => {
+                        sets.push(self.position(vec![(*character, *character)]))
+                    }
+                    Node::Class { ranges } => sets.push(self.position(ranges.clone())),
+                    // The left operand is numbered first
+                    Node::Union { left, right } => {
+                        steps.extend([Step::Union, Step::Visit(right), Step::Visit(left)])
+                    }
+                    Node::Concat { left, right } => {
+                        steps.extend([Step::Concat, Step::Visit(right), Step::Visit(left)])
+                    }
+                    Node::Star { operand, .. } => steps.extend([Step::Star, Step::Visit(operand)]),
+                    Node::Plus { operand, .. } => steps.extend([Step::Plus, Step::Visit(operand)]),
+                    Node::And { .. } | Node::Not { .. } => {
+                        unreachable!("boolean operators are determinized before")
+                    }
+                    Node::Look { .. } => unreachable!("assertions use Thompson's construction"),
+                    Node::Capture { operand, .. } => steps.push(Step::Visit(operand)),
+                    Node::Backreference { .. } => {
+                        unreachable!("backreferences are matched by backtracking")
+                    }
+                    Node::Lookahead { .. } | Node::Lookbehind { .. } => {
+                        unreachable!("lookarounds are rewritten into intersections before")
+                    }
+                },
+                Step::Union => {
+                    let rhs = sets.pop().unwrap();
+                    let mut lhs = sets.pop().unwrap();
+                    lhs.first.extend(rhs.first);
+                    lhs.last.extend(rhs.last);
+                    lhs.nullable = lhs.nullable || rhs.nullable;
+                    sets.push(lhs);
+                }
+                Step::Concat => {
+                    let rhs = sets.pop().unwrap();
+                    let lhs = sets.pop().unwrap();
+                    for &p in lhs.last.iter() {
+                        self.follow[p].extend(rhs.first.iter());
+                    }
+                    let mut first = lhs.first;
+                    if lhs.nullable {
+                        first.extend(rhs.first.iter());
+                    }
+                    let mut last = rhs.last;
+                    if rhs.nullable {
+                        last.extend(lhs.last);
+                    }
+                    sets.push(Positions {
+                        nullable: lhs.nullable && rhs.nullable,
+                        first,
+                        last,
+                    });
+                }
+                Step::Star | Step::Plus => {
+                    let inner = sets.pop().unwrap();
+                    for &p in inner.last.iter() {
+                        self.follow[p].extend(inner.first.iter());
+                    }
+                    sets.push(Positions {
+                        nullable: inner.nullable || matches!(step, Step::Star),
+                        ..inner
+                    });
+                }
+            }
+        }
+        sets.pop().unwrap()
+    }
+}
+
+impl Node {
+    /// Checks if the node contains intersections or complements
+    pub fn has_boolean_operators(&self) -> bool {
+        self.contains(&|node| matches!(node, Node::And { .. } | Node::Not { .. }))
+    }
+}
+
+//grcov-excl-start
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nfa::Construction;
+    use crate::parser::Parser;
+    use crate::regex::Regex;
+    use crate::scanner::Scanner;
+
+    fn parse(regex: &str) -> Node {
+        let scanner = Scanner::new(regex.to_string());
+        let mut parser = Parser::new(scanner);
+        parser.parse().unwrap()
+    }
+
+    #[test]
+    fn no_epsilon_transitions() {
+        let nfa = Glushkov::to_nfa(parse("(a|b)*c(a*|b)"));
+        for state in 0..=6 {
+            assert!(nfa.find_transitions(state, None).is_err());
+        }
+    }
+
+    #[test]
+    fn one_state_per_position() {
+        let nfa = Glushkov::to_nfa(parse("a(b|c)*"));
+        assert!(nfa.find_transitions(0, Some(b'a')).unwrap().contains(&1));
+        let from_a = nfa.find_transitions(1, Some(b'b')).unwrap();
+        assert!(from_a.contains(&2));
+        let from_c = nfa.find_transitions(3, Some(b'b')).unwrap();
+        assert!(from_c.contains(&2));
+        assert!(nfa.find_transitions(4, Some(b'b')).is_err());
+    }
+
+    #[test]
+    fn same_as_thompson() {
+        let regexes = vec![
+            "aa(b|cc)*a",
+            "(B|cc|GG)*A",
+            "(a*b*)*c",
+            "a(b|c)*|c*",
+            "((a|b)(a|b))*",
+            "(0|(-|()*)(1|2|3|4|5|6|7|8|9)(0|1|2|3|4|5|6|7|8|9)*)",
+        ];
+        let inputs = vec![
+            "", "a", "aaa", "aaba", "aacca", "aabaa", "BA", "A", "ccGGA", "cccA", "c", "abc",
+            "ababc", "abab", "bbbb", "aba", "0", "-0", "-1", "1234", "01",
+        ];
+        for regex in regexes {
+            let thompson = Regex::new(regex.to_string()).unwrap();
+            let glushkov =
+                Regex::with_construction(regex.to_string(), Construction::Glushkov).unwrap();
+            for input in inputs.iter() {
+                assert_eq!(
+                    thompson.matches(input.to_string()).unwrap(),
+                    glushkov.matches(input.to_string()).unwrap(),
+                    "regex {} input {}",
+                    regex,
+                    input
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn assertions() {
+        let glushkov = Regex::with_construction("\\bab*$".to_string(), Construction::Glushkov);
+        assert!(glushkov.unwrap().matches("abb".to_string()).unwrap());
+    }
+
+    #[test]
+    fn boolean_operators() {
+        let node = Node::intersection(parse("(a|b)*"), Node::complement(parse("(a|b)*bb(a|b)*")));
+        let dfa = Glushkov::to_nfa(node).to_dfa();
+        assert!(dfa.recognizer().accepts(b"abab"));
+        assert!(!dfa.recognizer().accepts(b"abba"));
+    }
+
+    #[test]
+    fn long_regex() {
+        let literal = "ab".repeat(30_000);
+        let nfa = Glushkov::to_nfa(parse(&(literal.clone() + "|c*")));
+        assert_eq!(nfa.state_count(), 60_002);
+        assert!(nfa.accepts(literal.as_bytes()));
+        assert!(nfa.accepts(b"cc"));
+        assert!(!nfa.accepts(&literal.as_bytes()[1..]));
+    }
+}
+
+//grcov-excl-stop
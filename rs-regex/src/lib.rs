@@ -0,0 +1,26 @@
+pub mod ast;
+pub mod automaton;
+pub mod backtrack;
+pub mod builder;
+pub mod classes;
+pub mod codegen;
+pub mod derivative;
+pub mod dfa;
+pub mod error;
+pub mod glushkov;
+pub mod literal;
+pub mod look;
+pub mod lookaround;
+pub mod nfa;
+pub mod nfa_fragment;
+pub mod parser;
+pub mod pike;
+pub mod printer;
+pub mod program;
+pub mod regex;
+pub mod scanner;
+pub mod simplify;
+pub mod simulation;
+pub mod sparse_set;
+pub mod tokens;
+pub mod utf8;
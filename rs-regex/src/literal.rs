@@ -0,0 +1,354 @@
+use crate::ast::Node;
+
+/// Longer literals are cut to this length, which keeps the extraction
+/// linear in the size of the regex
+const MAX_LITERAL: usize = 256;
+
+/// Literals that every match of a node has to contain
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Literals {
+    /// The whole match, if the node matches only this one string
+    pub exact: Option<Vec<u8>>,
+    /// Every match starts with this
+    pub prefix: Vec<u8>,
+    /// Every match ends with this
+    pub suffix: Vec<u8>,
+    /// The longest literal found that every match contains somewhere
+    pub inner: Vec<u8>,
+}
+
+impl Literals {
+    /// Extracts the literals of the node. The tree is walked without
+    /// recursion, since a long literal is a deep tree.
+    pub fn of(node: &Node) -> Literals {
+        enum Step<'a> {
+            Visit(&'a Node),
+            Combine(&'a Node),
+        }
+
+        let mut steps = vec![Step::Visit(node)];
+        let mut results: Vec<Literals> = Vec::new();
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Visit(node) => {
+                    steps.push(Step::Combine(node));
+                    for child in node.children().into_iter().rev() {
+                        steps.push(Step::Visit(child));
+                    }
+                }
+                Step::Combine(node) => {
+                    let literals = match node {
+                        Node::Union { .. } => {
+                            let right = results.pop().unwrap();
+                            let left = results.pop().unwrap();
+                            Literals::union(left, right)
+                        }
+                        Node::Concat { .. } => {
+                            let right = results.pop().unwrap();
+                            let left = results.pop().unwrap();
+                            Literals::concat(left, right)
+                        }
+                        Node::Capture { .. } => results.pop().unwrap(),
+                        // Every match starts and ends with a match of the operand
+                        Node::Plus { .. } => Literals {
+                            exact: None,
+                            ..results.pop().unwrap()
+                        },
+                        // The assertions match the empty string
+                        Node::Empty
+                        | Node::Look { .. }
+                        | Node::Lookahead { .. }
+                        | Node::Lookbehind { .. } => {
+                            results.truncate(results.len() - node.children().len());
+                            Literals::exact(Vec::new())
+                        }
+                        Node::Character { character } => Literals::exact(vec![*character]),
+                        Node::Class { ranges }
+                            if ranges.len() == 1 && ranges[0].0 == ranges[0].1 =>
+                        {
+                            Literals::exact(vec![ranges[0].0])
+                        }
+                        _ => {
+                            results.truncate(results.len() - node.children().len());
+                            Literals::default()
+                        }
+                    };
+                    results.push(literals);
+                }
+            }
+        }
+        results.pop().unwrap()
+    }
+
+    fn exact(bytes: Vec<u8>) -> Literals {
+        if bytes.len() > MAX_LITERAL {
+            let prefix = bytes[..MAX_LITERAL].to_vec();
+            return Literals {
+                exact: None,
+                suffix: bytes[bytes.len() - MAX_LITERAL..].to_vec(),
+                inner: prefix.clone(),
+                prefix,
+            };
+        }
+        Literals {
+            exact: Some(bytes.clone()),
+            prefix: bytes.clone(),
+            suffix: bytes.clone(),
+            inner: bytes,
+        }
+    }
+
+    /// The literals of the left node followed by the right node. The suffix
+    /// of the left and the prefix of the right are next to each other in
+    /// every match.
+    fn concat(left: Literals, right: Literals) -> Literals {
+        if let (Some(left), Some(right)) = (&left.exact, &right.exact) {
+            return Literals::exact([left.as_slice(), right].concat());
+        }
+        let prefix = match &left.exact {
+            Some(exact) => head([exact.as_slice(), &right.prefix].concat()),
+            None => left.prefix,
+        };
+        let suffix = match &right.exact {
+            Some(exact) => tail([left.suffix.as_slice(), exact].concat()),
+            None => right.suffix,
+        };
+        let middle = head([left.suffix.as_slice(), &right.prefix].concat());
+        let inner = [
+            left.inner,
+            right.inner,
+            middle,
+            prefix.clone(),
+            suffix.clone(),
+        ]
+        .into_iter()
+        .max_by_key(Vec::len)
+        .unwrap();
+        Literals {
+            exact: None,
+            prefix,
+            suffix,
+            inner,
+        }
+    }
+
+    /// The literals that both alternatives have
+    fn union(left: Literals, right: Literals) -> Literals {
+        if left.exact.is_some() && left.exact == right.exact {
+            return left;
+        }
+        let common = left
+            .prefix
+            .iter()
+            .zip(right.prefix.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let prefix = left.prefix[..common].to_vec();
+        let common = left
+            .suffix
+            .iter()
+            .rev()
+            .zip(right.suffix.iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let suffix = left.suffix[left.suffix.len() - common..].to_vec();
+        let inner = if prefix.len() >= suffix.len() {
+            prefix.clone()
+        } else {
+            suffix.clone()
+        };
+        Literals {
+            exact: None,
+            prefix,
+            suffix,
+            inner,
+        }
+    }
+}
+
+fn head(mut bytes: Vec<u8>) -> Vec<u8> {
+    bytes.truncate(MAX_LITERAL);
+    bytes
+}
+
+fn tail(bytes: Vec<u8>) -> Vec<u8> {
+    bytes[bytes.len().saturating_sub(MAX_LITERAL)..].to_vec()
+}
+
+/// Substring search with the Boyer-Moore-Horspool algorithm. When a window
+/// does not match, it is shifted by how far the byte at its end is from the
+/// end of the needle, which skips most of the haystack for long needles.
+/// https://en.wikipedia.org/wiki/Boyer%E2%80%93Moore%E2%80%93Horspool_algorithm
+#[derive(Debug, Clone)]
+pub struct Finder {
+    needle: Vec<u8>,
+    shifts: [usize; 256],
+}
+
+impl Finder {
+    pub fn new(needle: Vec<u8>) -> Self {
+        let mut shifts = [needle.len().max(1); 256];
+        for (i, &byte) in needle
+            .iter()
+            .enumerate()
+            .take(needle.len().saturating_sub(1))
+        {
+            shifts[byte as usize] = needle.len() - 1 - i;
+        }
+        Finder { needle, shifts }
+    }
+
+    /// The first position at or after `from` where the needle starts
+    pub fn find(&self, haystack: &[u8], from: usize) -> Option<usize> {
+        let len = self.needle.len();
+        let mut at = from;
+        while at + len <= haystack.len() {
+            if haystack[at..at + len] == self.needle[..] {
+                return Some(at);
+            }
+            at += self.shifts[haystack[at + len - 1] as usize];
+        }
+        None
+    }
+}
+
+/// Skips the parts of the input where a match can not start, before the
+/// automaton is run on them
+#[derive(Debug, Clone)]
+pub struct Prefilter {
+    /// Every match starts with this, may be empty
+    prefix: Finder,
+    /// Every match contains this, the longest of the literals
+    required: Finder,
+}
+
+impl Prefilter {
+    /// None if the regex has no literals to look for
+    pub fn new(node: &Node) -> Option<Prefilter> {
+        let literals = Literals::of(node);
+        let required = [&literals.prefix, &literals.suffix, &literals.inner]
+            .into_iter()
+            .max_by_key(|literal| literal.len())
+            .unwrap()
+            .clone();
+        if required.is_empty() {
+            return None;
+        }
+        Some(Prefilter {
+            prefix: Finder::new(literals.prefix),
+            required: Finder::new(required),
+        })
+    }
+
+    /// The positions where a match can start, in order. A match starts with
+    /// the prefix and contains the required literal after its start.
+    pub fn candidates<'a>(&'a self, haystack: &'a [u8]) -> Candidates<'a> {
+        Candidates {
+            prefilter: self,
+            haystack,
+            from: 0,
+            required: None,
+        }
+    }
+}
+
+/// The iterator returned by `Prefilter::candidates`
+pub struct Candidates<'a> {
+    prefilter: &'a Prefilter,
+    haystack: &'a [u8],
+    from: usize,
+    /// The latest occurrence of the required literal, so that it is not
+    /// searched for again at every candidate before it
+    required: Option<usize>,
+}
+
+impl<'a> Iterator for Candidates<'a> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let candidate = self.prefilter.prefix.find(self.haystack, self.from)?;
+        let required = match self.required {
+            Some(at) if at >= candidate => at,
+            _ => self.prefilter.required.find(self.haystack, candidate)?,
+        };
+        self.required = Some(required);
+        self.from = candidate + 1;
+        Some(candidate)
+    }
+}
+
+//grcov-excl-start
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn literals(regex: &str) -> Literals {
+        let scanner = Scanner::new(regex.to_string());
+        Literals::of(&Parser::new(scanner).parse().unwrap())
+    }
+
+    #[test]
+    fn extraction() {
+        let found = literals("ERROR: (a|b)*");
+        assert_eq!(found.exact, None);
+        assert_eq!(found.prefix, b"ERROR: ");
+        assert!(found.suffix.is_empty());
+
+        let found = literals("(a|b)*\\.log");
+        assert!(found.prefix.is_empty());
+        assert_eq!(found.suffix, b".log");
+
+        let found = literals("x*(foo|bar)=value(a|b)*");
+        assert_eq!(found.inner, b"=value");
+
+        assert_eq!(literals("ab(c)").exact, Some(b"abc".to_vec()));
+        assert_eq!(literals("^abc\\b").exact, Some(b"abc".to_vec()));
+        assert_eq!(literals("abc|abd").prefix, b"ab");
+        assert_eq!(literals("xbc|ybc").suffix, b"bc");
+        assert_eq!(literals("(?i)abc"), Literals::default());
+    }
+
+    #[test]
+    fn long_literals() {
+        let regex = "a".repeat(20_000);
+        let found = literals(&regex);
+        assert_eq!(found.exact, None);
+        assert_eq!(found.prefix.len(), MAX_LITERAL);
+        assert_eq!(found.suffix.len(), MAX_LITERAL);
+    }
+
+    #[test]
+    fn finder() {
+        let finder = Finder::new(b"needle".to_vec());
+        let haystack = b"a needle in a haystack with another needle";
+        assert_eq!(finder.find(haystack, 0), Some(2));
+        assert_eq!(finder.find(haystack, 3), Some(36));
+        assert_eq!(finder.find(haystack, 37), None);
+        assert_eq!(Finder::new(Vec::new()).find(b"ab", 1), Some(1));
+        assert_eq!(Finder::new(b"aab".to_vec()).find(b"aaab", 0), Some(1));
+    }
+
+    #[test]
+    fn prefilter() {
+        let scanner = Scanner::new("(a|b)*c=d".to_string());
+        let node = Parser::new(scanner).parse().unwrap();
+        let prefilter = Prefilter::new(&node).unwrap();
+        let candidates: Vec<usize> = prefilter.candidates(b"abc=dc=").collect();
+        assert_eq!(candidates, vec![0, 1, 2]);
+        assert_eq!(prefilter.candidates(b"abc=").next(), None);
+
+        let scanner = Scanner::new("ERROR:(a|b)*".to_string());
+        let node = Parser::new(scanner).parse().unwrap();
+        let prefilter = Prefilter::new(&node).unwrap();
+        let candidates: Vec<usize> = prefilter.candidates(b"ERROR:a ERROR:b").collect();
+        assert_eq!(candidates, vec![0, 8]);
+
+        let scanner = Scanner::new("(a|b)*".to_string());
+        assert!(Prefilter::new(&Parser::new(scanner).parse().unwrap()).is_none());
+    }
+}
+
+//grcov-excl-stop
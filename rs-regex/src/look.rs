@@ -0,0 +1,140 @@
+/// Assertions about the position between two bytes. They match the empty
+/// string, but only if the bytes around the position are right.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Look {
+    /// `^`, the start of the input
+    Start,
+    /// `$`, the end of the input
+    End,
+    /// `^` in multi-line mode, the start of the input or of a line
+    StartLine,
+    /// `$` in multi-line mode, the end of the input or of a line
+    EndLine,
+    /// `\b`, a word character on exactly one side
+    WordBoundary,
+    /// `\B`, a word character on both sides or on neither side
+    NotWordBoundary,
+}
+
+/// What a byte next to a position means for the assertions. The same type
+/// describes both the byte before and the byte after the position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Context {
+    /// There is no byte, the position is at the start or at the end
+    Edge,
+    NewLine,
+    /// An ASCII word character, `[0-9A-Za-z_]`
+    Word,
+    Other,
+}
+
+impl Context {
+    pub const ALL: [Context; 4] = [
+        Context::Edge,
+        Context::NewLine,
+        Context::Word,
+        Context::Other,
+    ];
+
+    pub fn of(byte: Option<u8>) -> Self {
+        match byte {
+            None => Context::Edge,
+            Some(b'\n') => Context::NewLine,
+            Some(byte) if byte.is_ascii_alphanumeric() || byte == b'_' => Context::Word,
+            Some(_) => Context::Other,
+        }
+    }
+
+    /// The position of the context in `ALL`
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl Look {
+    /// Checks if the assertion holds between the bytes of the contexts
+    pub fn holds(self, behind: Context, ahead: Context) -> bool {
+        let boundary = (behind == Context::Word) != (ahead == Context::Word);
+        match self {
+            Look::Start => behind == Context::Edge,
+            Look::End => ahead == Context::Edge,
+            Look::StartLine => matches!(behind, Context::Edge | Context::NewLine),
+            Look::EndLine => matches!(ahead, Context::Edge | Context::NewLine),
+            Look::WordBoundary => boundary,
+            Look::NotWordBoundary => !boundary,
+        }
+    }
+
+    /// The same assertion when the input is read backwards, the bytes
+    /// behind the position are then ahead of it
+    pub fn reversed(self) -> Self {
+        match self {
+            Look::Start => Look::End,
+            Look::End => Look::Start,
+            Look::StartLine => Look::EndLine,
+            Look::EndLine => Look::StartLine,
+            Look::WordBoundary | Look::NotWordBoundary => self,
+        }
+    }
+}
+
+//grcov-excl-start
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contexts() {
+        assert_eq!(Context::of(None), Context::Edge);
+        assert_eq!(Context::of(Some(b'\n')), Context::NewLine);
+        assert_eq!(Context::of(Some(b'_')), Context::Word);
+        assert_eq!(Context::of(Some(b'7')), Context::Word);
+        assert_eq!(Context::of(Some(b'-')), Context::Other);
+        assert_eq!(Context::of(Some(0xc3)), Context::Other);
+        for (i, context) in Context::ALL.iter().enumerate() {
+            assert_eq!(context.index(), i);
+        }
+    }
+
+    #[test]
+    fn assertions() {
+        use Context::*;
+        assert!(Look::Start.holds(Edge, Word));
+        assert!(!Look::Start.holds(NewLine, Word));
+        assert!(Look::StartLine.holds(NewLine, Word));
+        assert!(Look::End.holds(Other, Edge));
+        assert!(!Look::End.holds(Other, NewLine));
+        assert!(Look::EndLine.holds(Other, NewLine));
+        assert!(Look::WordBoundary.holds(Edge, Word));
+        assert!(Look::WordBoundary.holds(Word, Other));
+        assert!(!Look::WordBoundary.holds(Word, Word));
+        assert!(Look::NotWordBoundary.holds(Other, Edge));
+    }
+
+    #[test]
+    fn reversed() {
+        use Context::*;
+        let looks = [
+            Look::Start,
+            Look::End,
+            Look::StartLine,
+            Look::EndLine,
+            Look::WordBoundary,
+            Look::NotWordBoundary,
+        ];
+        for look in looks {
+            assert_eq!(look.reversed().reversed(), look);
+            for behind in Context::ALL {
+                for ahead in [Edge, NewLine, Word, Other] {
+                    assert_eq!(
+                        look.holds(behind, ahead),
+                        look.reversed().holds(ahead, behind)
+                    );
+                }
+            }
+        }
+    }
+}
+
+//grcov-excl-stop
@@ -0,0 +1,296 @@
+use crate::ast::Node;
+use crate::dfa::{DfaLimits, DFA};
+use crate::nfa::NFA;
+
+/// Rewrites the lookarounds of the node into intersections, so that the
+/// node can be matched with a DFA. A lookahead only depends on what follows
+/// it, so with `R` being the rest of the regex after it
+///
+/// (?=B)R = (B.*) & R and (?!B)R = ~(B.*) & R
+///
+/// and a lookbehind after the start `P` of the regex in the same way
+///
+/// P(?<=B) = P & (.*B) and P(?<!B) = P & ~(.*B)
+///
+/// where `.` is any byte. The rewritten node only matches whole inputs the
+/// same way, since the lookarounds can not see past the ends of it.
+///
+/// The lookbehinds are rewritten first, so the part before a lookbehind is
+/// in an intersection when the lookaheads are rewritten. A lookahead there
+/// can only be rewritten when nothing follows the lookbehind, as in
+/// `(?=a)a(?<=a)`, since the intersection then ends where the input does.
+///
+/// Fails when a lookaround is inside '*', '+', another lookaround or an
+/// intersection, where the rest of the regex is not known.
+pub fn to_intersections(node: Node) -> Result<Node, String> {
+    let node = behind(node, Node::Empty)?;
+    ahead(node, Node::Empty)
+}
+
+fn any_bytes() -> Node {
+    Node::star(Node::class(vec![(0x00, 0xff)]))
+}
+
+fn negate(negated: bool, node: Node) -> Node {
+    if negated {
+        Node::complement(node)
+    } else {
+        node
+    }
+}
+
+fn concat(left: Node, right: Node) -> Node {
+    match (left, right) {
+        (Node::Empty, node) | (node, Node::Empty) => node,
+        (left, right) => Node::concat(left, right),
+    }
+}
+
+/// Checks that the node can be kept as it is
+fn unsupported(node: &Node, has_lookaround: fn(&Node) -> bool) -> Result<(), String> {
+    if has_lookaround(node) {
+        Err("Lookarounds inside '*', '+' or other lookarounds, and lookaheads before a lookbehind that something follows, can not be matched with a DFA".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+/// A rewrite waits on the stack until the rewrites it depends on are done.
+/// The rewrites are chained with an explicit stack, since a long regex such
+/// as 'aaa...' is as deep as it is long.
+enum Step {
+    /// Rewrites the node with the given context on the side of it
+    Rewrite(Node, Node),
+    /// Rewrites the node with the latest result as its context
+    Then(Node),
+    Union,
+    And,
+}
+
+/// Returns `before` followed by the node, with the lookbehinds of the node
+/// rewritten
+fn behind(node: Node, before: Node) -> Result<Node, String> {
+    let has_lookbehind =
+        |node: &Node| node.contains(&|node| matches!(node, Node::Lookbehind { .. }));
+    let mut steps = vec![Step::Rewrite(node, before)];
+    let mut rewritten = Vec::new();
+    while let Some(step) = steps.pop() {
+        match step {
+            Step::Rewrite(mut node, before) => match &mut node {
+                Node::Lookbehind { negated, operand } => {
+                    unsupported(operand, Node::has_lookarounds)?;
+                    let language = negate(*negated, Node::concat(any_bytes(), operand.take()));
+                    rewritten.push(Node::intersection(before, language));
+                }
+                Node::Concat { left, right } => {
+                    steps.extend([Step::Then(right.take()), Step::Rewrite(left.take(), before)])
+                }
+                Node::Union { left, right } => steps.extend([
+                    Step::Union,
+                    Step::Rewrite(right.take(), before.clone()),
+                    Step::Rewrite(left.take(), before),
+                ]),
+                Node::Capture { operand, .. } => steps.push(Step::Rewrite(operand.take(), before)),
+                _ => {
+                    unsupported(&node, has_lookbehind)?;
+                    rewritten.push(concat(before, node));
+                }
+            },
+            Step::Then(node) => {
+                let before = rewritten.pop().unwrap();
+                steps.push(Step::Rewrite(node, before));
+            }
+            Step::Union | Step::And => combine(&step, &mut rewritten),
+        }
+    }
+    Ok(rewritten.pop().unwrap())
+}
+
+/// Replaces the two latest results with their union or intersection
+fn combine(step: &Step, rewritten: &mut Vec<Node>) {
+    let right = rewritten.pop().unwrap();
+    let left = rewritten.pop().unwrap();
+    rewritten.push(match step {
+        Step::And => Node::intersection(left, right),
+        _ => Node::union(left, right),
+    });
+}
+
+/// Returns the node followed by `rest`, with the lookaheads of the node
+/// rewritten
+fn ahead(node: Node, rest: Node) -> Result<Node, String> {
+    let has_lookahead = |node: &Node| node.contains(&|node| matches!(node, Node::Lookahead { .. }));
+    let mut steps = vec![Step::Rewrite(node, rest)];
+    let mut rewritten = Vec::new();
+    while let Some(step) = steps.pop() {
+        match step {
+            Step::Rewrite(mut node, rest) => match &mut node {
+                Node::Lookahead { negated, operand } => {
+                    unsupported(operand, Node::has_lookarounds)?;
+                    let language = negate(*negated, Node::concat(operand.take(), any_bytes()));
+                    rewritten.push(Node::intersection(language, rest));
+                }
+                Node::Concat { left, right } => {
+                    steps.extend([Step::Then(left.take()), Step::Rewrite(right.take(), rest)])
+                }
+                Node::Union { left, right } => steps.extend([
+                    Step::Union,
+                    Step::Rewrite(right.take(), rest.clone()),
+                    Step::Rewrite(left.take(), rest),
+                ]),
+                Node::Capture { operand, .. } => steps.push(Step::Rewrite(operand.take(), rest)),
+                // Made of a lookbehind, and both sides end with the input
+                Node::And { left, right } if rest == Node::Empty => steps.extend([
+                    Step::And,
+                    Step::Rewrite(right.take(), Node::Empty),
+                    Step::Rewrite(left.take(), Node::Empty),
+                ]),
+                _ => {
+                    unsupported(&node, has_lookahead)?;
+                    rewritten.push(concat(node, rest));
+                }
+            },
+            Step::Then(node) => {
+                let rest = rewritten.pop().unwrap();
+                steps.push(Step::Rewrite(node, rest));
+            }
+            Step::Union | Step::And => combine(&step, &mut rewritten),
+        }
+    }
+    Ok(rewritten.pop().unwrap())
+}
+
+/// Finds where the operand of a lookaround matches in an input, at all of
+/// the positions with one pass of a DFA. With these tables the lookarounds
+/// are simple assertions, so the matches inside an input can be searched
+/// in linear time even though the lookarounds see past their ends.
+pub struct Lookaround {
+    /// Unanchored, so that it accepts wherever a match of the operand ends.
+    /// For a lookahead it is built from the reversed operand and reads the
+    /// input backwards, so it accepts wherever a match starts.
+    dfa: DFA,
+    ahead: bool,
+}
+
+impl Lookaround {
+    /// Fails if the DFA would grow over the limits
+    pub fn new(ahead: bool, operand: &NFA, limits: DfaLimits) -> Result<Self, String> {
+        let dfa = if ahead {
+            operand.reverse().try_to_dfa(limits)?
+        } else {
+            operand.try_to_dfa(limits)?
+        };
+        Ok(Lookaround {
+            dfa: dfa.try_unanchored(limits)?,
+            ahead,
+        })
+    }
+
+    /// Whether the operand matches from every position of the input for a
+    /// lookahead, or up to it for a lookbehind
+    pub fn table(&self, input: &[u8]) -> Vec<bool> {
+        let mut table = vec![false; input.len() + 1];
+        let mut state = self.dfa.start_state_after(None);
+        if self.ahead {
+            // Backwards, the byte after a position is the one read before it
+            for (at, holds) in table.iter_mut().enumerate().rev() {
+                let next = at.checked_sub(1).map(|i| input[i]);
+                *holds = self.dfa.accepts_before(state, next);
+                if let Some(byte) = next {
+                    state = self.dfa.get_transition(state, byte);
+                }
+            }
+        } else {
+            for (at, holds) in table.iter_mut().enumerate() {
+                let next = input.get(at).cloned();
+                *holds = self.dfa.accepts_before(state, next);
+                if let Some(byte) = next {
+                    state = self.dfa.get_transition(state, byte);
+                }
+            }
+        }
+        table
+    }
+}
+
+//grcov-excl-start
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::derivative::DerivativeDFA;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn rewrite(regex: &str) -> Result<Node, String> {
+        let scanner = Scanner::new(regex.to_string());
+        to_intersections(Parser::new(scanner).parse().unwrap())
+    }
+
+    fn accepts(node: &Node, input: &str) -> bool {
+        let dfa = DerivativeDFA::new(node.clone()).to_dfa();
+        dfa.recognizer().accepts(input.as_bytes())
+    }
+
+    #[test]
+    fn lookahead() {
+        let node = rewrite("(a|b)(?=a)(a|b)*").unwrap();
+        assert!(!node.has_lookarounds());
+        assert!(accepts(&node, "ba"));
+        assert!(accepts(&node, "aab"));
+        assert!(!accepts(&node, "ab"));
+        assert!(!accepts(&node, "a"));
+
+        let node = rewrite("(?!ab)(a|b)*").unwrap();
+        assert!(accepts(&node, "ba"));
+        assert!(!accepts(&node, "abb"));
+    }
+
+    #[test]
+    fn lookbehind() {
+        let node = rewrite("(a|b)*(?<=ab)c").unwrap();
+        assert!(accepts(&node, "aabc"));
+        assert!(!accepts(&node, "bac"));
+
+        let node = rewrite("(a|b)*(?<!a)").unwrap();
+        assert!(accepts(&node, "ab"));
+        assert!(accepts(&node, ""));
+        assert!(!accepts(&node, "ba"));
+    }
+
+    #[test]
+    fn lookahead_before_lookbehind() {
+        let node = rewrite("(?=a)(a|b)(?<=a)").unwrap();
+        assert!(!node.has_lookarounds());
+        assert!(accepts(&node, "a"));
+        assert!(!accepts(&node, "b"));
+
+        let node = rewrite("(a|b)(?!ab)(a|b)*(?<=a)|c").unwrap();
+        assert!(accepts(&node, "aba"));
+        assert!(accepts(&node, "c"));
+        assert!(!accepts(&node, "aab"));
+        assert!(!accepts(&node, "aaba"));
+    }
+
+    #[test]
+    fn unsupported() {
+        assert!(rewrite("((?=a)a)*").is_err());
+        assert!(rewrite("(?=(?=a)a)").is_err());
+        assert!(rewrite("(?=ab)a(?<=a)b").is_err());
+        assert!(rewrite("((?=a)a(?<=a))*").is_err());
+        assert!(rewrite("((a|b)(?<=a))*").is_err());
+    }
+
+    #[test]
+    fn long_regex() {
+        let literal = "a".repeat(100_000);
+        let node = rewrite(&(literal.clone() + "(?=b)")).unwrap();
+        assert!(!node.has_lookarounds());
+        let node = rewrite(&("(?<!c)".to_string() + &literal)).unwrap();
+        assert!(!node.has_lookarounds());
+        let error = rewrite(&(literal + "(?=b(?=c))")).unwrap_err();
+        assert!(error.contains("inside"));
+    }
+}
+
+//grcov-excl-stop
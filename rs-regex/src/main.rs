@@ -1,17 +1,12 @@
-mod ast;
-mod dfa;
-mod nfa;
-mod nfa_fragment;
-mod parser;
-mod regex;
-mod scanner;
-mod tokens;
-
-use crate::parser::Parser;
-use crate::regex::Regex;
-use crate::scanner::Scanner;
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use rs_regex::codegen::{self, Style};
+use rs_regex::dfa::DFA;
+use rs_regex::nfa::Construction;
+use rs_regex::parser::Parser;
+use rs_regex::regex::{Engine, MatchKind, Regex, RegexBuilder};
+use rs_regex::scanner::Scanner;
 use std::error::Error;
+use std::fs;
 use std::io::{stdin, stdout, Write};
 
 type MyResult<T> = Result<T, Box<dyn Error>>;
@@ -19,21 +14,145 @@ type MyResult<T> = Result<T, Box<dyn Error>>;
 #[derive(Debug)]
 pub struct Config {
     regex: String,
+    /// The regex with the options given as flags
+    builder: RegexBuilder,
     print_tokens: bool,
+    dfa_file: Option<String>,
+    output: Option<String>,
+    codegen: Option<Style>,
+    /// Prints the simplified pattern instead of matching
+    simplify: bool,
 }
 
 fn main() {
+    /// The options for compiling the regex, shared by the subcommands
+    fn options<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+        let flag = |name, short, help| {
+            Arg::with_name(name)
+                .takes_value(false)
+                .short(short)
+                .long(name)
+                .help(help)
+        };
+        let number = |name, help| {
+            Arg::with_name(name)
+                .value_name("NUMBER")
+                .long(name)
+                .help(help)
+        };
+        vec![
+            flag(
+                "case-insensitive",
+                "i",
+                "ASCII letters match both cases, same as (?i)",
+            ),
+            flag("dot-all", "s", "'.' matches also a newline, same as (?s)"),
+            flag(
+                "multi-line",
+                "m",
+                "'^' and '$' match also at the lines, same as (?m)",
+            ),
+            flag(
+                "verbose",
+                "x",
+                "ignores whitespace and '#' comments, same as (?x)",
+            ),
+            Arg::with_name("strict")
+                .takes_value(false)
+                .long("strict")
+                .help("rejects escapes and class ranges that could be read in more than one way"),
+            Arg::with_name("engine")
+                .value_name("ENGINE")
+                .long("engine")
+                .possible_values(&["auto", "dfa", "nfa", "backtrack"])
+                .help("the matching engine"),
+            Arg::with_name("match-kind")
+                .value_name("KIND")
+                .long("match-kind")
+                .possible_values(&["leftmost-longest", "leftmost-first"])
+                .help("which match is found when searching"),
+            Arg::with_name("construction")
+                .value_name("CONSTRUCTION")
+                .long("construction")
+                .possible_values(&["thompson", "glushkov"])
+                .help("the algorithm for building the NFA"),
+            number("nfa-size-limit", "the most states the NFA may have"),
+            number(
+                "dfa-state-limit",
+                "the most states the DFA may have before the NFA is simulated instead",
+            ),
+            number(
+                "dfa-size-limit",
+                "the most bytes building the DFA may use before the NFA is simulated instead",
+            ),
+        ]
+    }
+
+    /// Maps the flags onto the options of the builder. The engine is the
+    /// default one unless it is given.
+    fn builder(regex: &str, matches: &ArgMatches, engine: Engine) -> MyResult<RegexBuilder> {
+        let mut builder = RegexBuilder::new(regex.to_string());
+        builder
+            .case_insensitive(matches.is_present("case-insensitive"))
+            .dot_matches_new_line(matches.is_present("dot-all"))
+            .multi_line(matches.is_present("multi-line"))
+            .verbose(matches.is_present("verbose"))
+            .strict(matches.is_present("strict"));
+        match matches.value_of("engine") {
+            Some("dfa") => builder.engine(Engine::Dfa),
+            Some("nfa") => builder.engine(Engine::Nfa),
+            Some("backtrack") => builder.engine(Engine::Backtrack),
+            Some("auto") => builder.engine(Engine::Auto),
+            _ => builder.engine(engine),
+        };
+        if matches.value_of("match-kind") == Some("leftmost-first") {
+            builder.match_kind(MatchKind::LeftmostFirst);
+        }
+        if matches.value_of("construction") == Some("glushkov") {
+            builder.construction(Construction::Glushkov);
+        }
+        let number = |name| -> MyResult<Option<usize>> {
+            match matches.value_of(name) {
+                Some(value) => {
+                    Ok(Some(value.parse().map_err(|_| {
+                        format!("--{} must be a number, got '{}'", name, value)
+                    })?))
+                }
+                None => Ok(None),
+            }
+        };
+        if let Some(states) = number("nfa-size-limit")? {
+            builder.nfa_size_limit(states);
+        }
+        if let Some(states) = number("dfa-state-limit")? {
+            builder.dfa_state_limit(states);
+        }
+        if let Some(bytes) = number("dfa-size-limit")? {
+            builder.dfa_size_limit(bytes);
+        }
+        Ok(builder)
+    }
+
     pub fn get_args() -> MyResult<Config> {
         let matches = App::new("rs_regex")
             .version("0.1.0")
             .author("thiom")
             .about("Rust regex engine")
+            .setting(AppSettings::SubcommandsNegateReqs)
             .arg(
                 Arg::with_name("regex")
                     .value_name("REGEX")
-                    .required(true)
+                    .required_unless("dfa")
                     .help("Regular expession"),
             )
+            .arg(
+                Arg::with_name("dfa")
+                    .value_name("FILE")
+                    .short("d")
+                    .long("dfa")
+                    .conflicts_with("regex")
+                    .help("loads a compiled DFA instead of a regular expression"),
+            )
             .arg(
                 Arg::with_name("print_tokens")
                     .takes_value(false)
@@ -41,25 +160,156 @@ fn main() {
                     .long("print_tokens")
                     .help("prints tokens"),
             )
+            .args(&options())
+            .subcommand(
+                SubCommand::with_name("compile")
+                    .about("Compiles the regular expression into a DFA file")
+                    .arg(
+                        Arg::with_name("regex")
+                            .value_name("REGEX")
+                            .required(true)
+                            .help("Regular expession"),
+                    )
+                    .arg(
+                        Arg::with_name("output")
+                            .value_name("FILE")
+                            .short("o")
+                            .long("output")
+                            .required(true)
+                            .help("file to write the DFA to"),
+                    )
+                    .args(&options()),
+            )
+            .subcommand(
+                SubCommand::with_name("codegen")
+                    .about("Prints a Rust function that matches the regular expression")
+                    .arg(
+                        Arg::with_name("regex")
+                            .value_name("REGEX")
+                            .required(true)
+                            .help("Regular expession"),
+                    )
+                    .arg(
+                        Arg::with_name("table")
+                            .takes_value(false)
+                            .long("table")
+                            .help("uses static transition tables instead of a match"),
+                    )
+                    .args(&options()),
+            )
+            .subcommand(
+                SubCommand::with_name("simplify")
+                    .about("Prints the simplified pattern that the automata are built from")
+                    .arg(
+                        Arg::with_name("regex")
+                            .value_name("REGEX")
+                            .required(true)
+                            .help("Regular expession"),
+                    )
+                    .args(&options()),
+            )
             .get_matches();
 
+        if let Some(compile) = matches.subcommand_matches("compile") {
+            let regex = compile.value_of_lossy("regex").unwrap().trim().to_string();
+            return Ok(Config {
+                // Too big a DFA is an error instead of falling back to the NFA
+                builder: builder(&regex, compile, Engine::Dfa)?,
+                regex,
+                print_tokens: false,
+                dfa_file: None,
+                output: compile.value_of("output").map(String::from),
+                codegen: None,
+                simplify: false,
+            });
+        }
+
+        if let Some(codegen) = matches.subcommand_matches("codegen") {
+            let regex = codegen.value_of_lossy("regex").unwrap().trim().to_string();
+            return Ok(Config {
+                builder: builder(&regex, codegen, Engine::Dfa)?,
+                regex,
+                print_tokens: false,
+                dfa_file: None,
+                output: None,
+                codegen: Some(if codegen.is_present("table") {
+                    Style::Table
+                } else {
+                    Style::Match
+                }),
+                simplify: false,
+            });
+        }
+
+        if let Some(simplify) = matches.subcommand_matches("simplify") {
+            let regex = simplify.value_of_lossy("regex").unwrap().trim().to_string();
+            return Ok(Config {
+                builder: builder(&regex, simplify, Engine::Auto)?,
+                regex,
+                print_tokens: false,
+                dfa_file: None,
+                output: None,
+                codegen: None,
+                simplify: true,
+            });
+        }
+
+        let regex = matches
+            .value_of_lossy("regex")
+            .unwrap_or_default()
+            .trim()
+            .to_string();
         Ok(Config {
-            regex: matches.value_of_lossy("regex").unwrap().trim().to_string(),
+            builder: builder(&regex, &matches, Engine::Auto)?,
+            regex,
             print_tokens: matches.is_present("print_tokens"),
+            dfa_file: matches.value_of("dfa").map(String::from),
+            output: None,
+            codegen: None,
+            simplify: false,
         })
     }
 
     pub fn run(config: Config) -> MyResult<()> {
+        if let Some(output) = config.output {
+            let regex = config.builder.build()?;
+            fs::write(&output, dfa(&regex)?.to_bytes())?;
+            println!("DFA written to {}", output);
+            let statistics = regex.statistics();
+            println!(
+                "{} states, {} byte classes",
+                statistics.dfa_states, statistics.byte_classes
+            );
+            return Ok(());
+        }
+
+        if let Some(style) = config.codegen {
+            let regex = config.builder.build()?;
+            print!("{}", codegen::generate(dfa(&regex)?, style));
+            return Ok(());
+        }
+
+        if config.simplify {
+            println!("{}", config.builder.parse()?.simplify());
+            return Ok(());
+        }
+
         if config.print_tokens {
             let scanner = Scanner::new(config.regex);
             let mut parser = Parser::new(scanner);
             parser.print_tokens();
         } else {
-            if !config.regex.is_ascii() {
-                println!("Regular expression must contain valid ASCII characters only. Try again");
-                return Ok(());
+            let (regex, description) = match config.dfa_file {
+                Some(file) => (
+                    DFA::from_bytes(&fs::read(&file)?).map(Regex::from_dfa),
+                    format!("Compiled DFA is: {}", file),
+                ),
+                None => (
+                    config.builder.build().map_err(|err| err.to_string()),
+                    format!("Regular expression is: {}", &config.regex),
+                ),
             };
-            match Regex::new(config.regex.to_string()) {
+            match regex {
                 Ok(regex) => {
                     println!("Regex read successfully");
                     let mut empty_strings = 0;
@@ -68,7 +318,7 @@ fn main() {
                         println!(
                             "\nGive a string (two consecutive empty strings will exit the program)"
                         );
-                        println!("Regular expression is: {}", &config.regex);
+                        println!("{}", description);
                         let _ = stdout().flush();
                         stdin()
                             .read_line(&mut input)
@@ -88,10 +338,10 @@ fn main() {
                             break;
                         }
 
-                        if regex.matches(input.to_string()).unwrap() {
-                            println!("ACCEPT");
-                        } else {
-                            println!("REJECT");
+                        match regex.matches(input.to_string()) {
+                            Ok(true) => println!("ACCEPT"),
+                            Ok(false) => println!("REJECT"),
+                            Err(err) => println!("{}", err),
                         }
                     }
                 }
@@ -104,6 +354,12 @@ fn main() {
         Ok(())
     }
 
+    fn dfa(regex: &Regex) -> MyResult<&DFA> {
+        regex
+            .dfa()
+            .ok_or_else(|| "The regex can not be compiled into a DFA".into())
+    }
+
     if let Err(e) = get_args().and_then(run) {
         eprintln!("{}", e);
         std::process::exit(1);
@@ -1,12 +1,78 @@
-use crate::dfa::DFA;
-use std::collections::{HashSet, VecDeque};
+use crate::ast::Node;
+use crate::automaton::{Automaton, Recognizer};
+use crate::classes::{Alphabet, ByteClasses};
+use crate::dfa::{DfaLimits, DFA};
+use crate::glushkov::Glushkov;
+use crate::look::{Context, Look};
+use crate::nfa_fragment::{NFAFragment, StateArena};
+use std::collections::{HashMap, HashSet};
+
+/// The most states an NFA may have unless `RegexBuilder` sets another limit
+pub const DEFAULT_NFA_SIZE_LIMIT: usize = 1_000_000;
+
+/// The algorithm used for converting the AST into NFA
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Construction {
+    /// Thompson's construction, uses epsilon transitions
+    Thompson,
+    /// Glushkov's construction, epsilon free with a state per character
+    Glushkov,
+}
+
+impl Construction {
+    /// Converts the AST into NFA. The node must not contain backreferences,
+    /// or assertions inside intersections and complements.
+    pub fn to_nfa(self, node: Node) -> NFA {
+        self.try_to_nfa(node, DfaLimits::unlimited())
+            .unwrap_or_else(|reason| panic!("{}", reason))
+    }
+
+    /// Same as `to_nfa`, but fails if the DFA that an intersection or a
+    /// complement is determinized into grows over the limits, or if it has
+    /// assertions inside
+    pub fn try_to_nfa(self, node: Node, limits: DfaLimits) -> Result<NFA, String> {
+        match self {
+            Construction::Thompson => {
+                let mut arena = StateArena::new();
+                let fragment = node.try_to_fragment(&mut arena, limits)?;
+                Ok(arena.to_nfa(fragment))
+            }
+            Construction::Glushkov => Glushkov::try_to_nfa(node, limits),
+        }
+    }
+
+    /// Converts a pattern over any ordered symbols into NFA, which reads
+    /// the numbers of their intervals as bytes, see `Node::encode`
+    pub fn to_symbol_nfa<S: Ord + Clone>(self, node: Node<S>) -> Result<NFA<S>, String> {
+        let (node, alphabet) = node.encode()?;
+        let nfa = self.try_to_nfa(node, DfaLimits::unlimited())?;
+        Ok(nfa.with_alphabet(alphabet))
+    }
+}
+
+/// What a transition of the NFA reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Label {
+    Epsilon,
+    /// Any byte of the class
+    Class(usize),
+    /// An epsilon transition that requires the assertion to hold
+    Look(Look),
+}
 
 /// Nondeterministic finite automaton
 /// https://en.wikipedia.org/wiki/Nondeterministic_finite_automaton
-pub struct NFA {
+pub struct NFA<S = u8> {
     start_state: i32,
     accept_states: HashSet<i32>,
     transitions: Box<dyn Fn(i32, Option<u8>) -> Result<HashSet<i32>, String>>,
+    /// Epsilon transitions that can only be taken when the assertion holds
+    looks: HashMap<i32, Vec<(Look, i32)>>,
+    /// The bytes in a class have the same transitions from every state
+    classes: ByteClasses,
+    state_count: usize,
+    /// The intervals of the symbols, whose numbers the transitions read
+    alphabet: Alphabet<S>,
 }
 
 impl NFA {
@@ -14,55 +80,359 @@ impl NFA {
         start_state: i32,
         accept_states: HashSet<i32>,
         transitions: Box<dyn Fn(i32, Option<u8>) -> Result<HashSet<i32>, String>>,
+        looks: HashMap<i32, Vec<(Look, i32)>>,
+        classes: ByteClasses,
+        state_count: usize,
     ) -> Self {
         NFA {
             start_state,
             accept_states,
             transitions,
+            looks,
+            classes,
+            state_count,
+            alphabet: Alphabet::bytes(),
+        }
+    }
+
+    /// The NFA of the reversed language, which accepts the inputs of this
+    /// NFA backwards. The transitions are turned around, the accept states
+    /// lead to the new start state and the start state is the only accept
+    /// state. The assertions look the other way, e.g. '^' becomes '$'.
+    pub fn reverse(&self) -> NFA {
+        let mut arena = StateArena::new();
+        let (largest, visited) = self.reverse_into(&mut arena);
+        let start = largest + 1;
+        for &state in self.accept_states.intersection(&visited) {
+            arena.create_transition(start, None, state);
         }
+        let accept_states = vec![self.start_state].into_iter().collect();
+        arena.to_nfa(NFAFragment::new(start, accept_states))
+    }
+
+    /// Adds the transitions between the states that can be reached from the
+    /// start state into the arena, turned around. Returns the largest label
+    /// of the states and the states.
+    fn reverse_into(&self, arena: &mut StateArena) -> (i32, HashSet<i32>) {
+        let mut members = vec![Vec::new(); self.classes.count()];
+        for byte in 0..=255u8 {
+            members[self.classes.get(byte) as usize].push(byte);
+        }
+        let visited = self.for_each_transition(|from, label, to| match label {
+            Label::Epsilon => arena.create_transition(to, None, from),
+            Label::Class(class) => {
+                for &byte in &members[class] {
+                    arena.create_transition(to, Some(byte), from);
+                }
+            }
+            Label::Look(look) => arena.create_look(to, look.reversed(), from),
+        });
+        let largest = visited.iter().cloned().max().unwrap_or(self.start_state);
+        (largest, visited)
+    }
+}
+
+impl<S: Ord + Clone> NFA<S> {
+    /// The same automaton, reading the symbols of the alphabet
+    fn with_alphabet<T>(self, alphabet: Alphabet<T>) -> NFA<T> {
+        NFA {
+            start_state: self.start_state,
+            accept_states: self.accept_states,
+            transitions: self.transitions,
+            looks: self.looks,
+            classes: self.classes,
+            state_count: self.state_count,
+            alphabet,
+        }
+    }
+
+    /// Checks if the NFA accepts the whole input
+    pub fn accepts(&self, input: &[S]) -> bool {
+        let input: Vec<u8> = input
+            .iter()
+            .map(|symbol| self.alphabet.get(symbol))
+            .collect();
+        Recognizer::new(self).accepts(&input)
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.state_count
     }
 
     /// Finds the destination state from a given state and a symbol
-    fn find_transitions(&self, state: i32, character: Option<u8>) -> Result<HashSet<i32>, String> {
+    pub(crate) fn find_transitions(
+        &self,
+        state: i32,
+        character: Option<u8>,
+    ) -> Result<HashSet<i32>, String> {
         (self.transitions)(state, character)
     }
 
+    /// Calls the function with every transition between the states that can
+    /// be reached from the start state, and returns the states
+    pub(crate) fn for_each_transition(
+        &self,
+        mut transition: impl FnMut(i32, Label, i32),
+    ) -> HashSet<i32> {
+        let representatives = self.classes.representatives();
+        let mut visited: HashSet<i32> = vec![self.start_state].into_iter().collect();
+        let mut not_visited = vec![self.start_state];
+        while let Some(from) = not_visited.pop() {
+            let mut targets = Vec::new();
+            for to in self.find_transitions(from, None).unwrap_or_default() {
+                transition(from, Label::Epsilon, to);
+                targets.push(to);
+            }
+            for (class, to_states) in self.class_transitions(from, &representatives) {
+                for to in to_states {
+                    transition(from, Label::Class(class), to);
+                    targets.push(to);
+                }
+            }
+            for &(look, to) in self.looks.get(&from).into_iter().flatten() {
+                transition(from, Label::Look(look), to);
+                targets.push(to);
+            }
+            for to in targets {
+                if visited.insert(to) {
+                    not_visited.push(to);
+                }
+            }
+        }
+        visited
+    }
+
+    pub(crate) fn start_state(&self) -> i32 {
+        self.start_state
+    }
+
+    pub(crate) fn is_accept_state(&self, state: i32) -> bool {
+        self.accept_states.contains(&state)
+    }
+
+    /// The bytes in a class have the same transitions from every state
+    pub(crate) fn classes(&self) -> ByteClasses {
+        self.classes
+    }
+
     /// Eliminates the epsilon transitions for the dfa conversion
     fn transform_transitions(&self, set: HashSet<i32>) -> HashSet<i32> {
-        let mut not_visited: VecDeque<i32> = set.into_iter().collect();
-        let mut visited = VecDeque::<i32>::new();
-        while !not_visited.is_empty() {
-            let start = not_visited.pop_back().unwrap();
-            visited.push_back(start);
+        let mut not_visited: Vec<i32> = set.iter().cloned().collect();
+        let mut visited = set;
+        while let Some(start) = not_visited.pop() {
             match self.find_transitions(start, None) {
                 Ok(states) => {
                     for state in states {
-                        if !visited.contains(&state) {
-                            not_visited.push_front(state)
+                        if visited.insert(state) {
+                            not_visited.push(state)
                         }
                     }
                 }
                 Err(_) => continue,
             }
         }
-        visited.into_iter().collect()
-    }
-
-    /// Converts the NFA into an equivalent DFA
-    pub fn to_dfa(self) -> DFA {
-        let start = self.transform_transitions(vec![self.start_state].into_iter().collect());
-        let accept = self.accept_states.clone();
-        let transition_set = move |set: HashSet<i32>, character: u8| {
-            let mut result = HashSet::<i32>::new();
-            for state in set {
-                match self.find_transitions(state, Some(character)) {
-                    Ok(set) => result = &result | &set,
-                    Err(_) => continue,
+        visited
+    }
+
+    /// Follows the transitions of the assertions that hold between the
+    /// contexts, and the epsilon transitions after them
+    fn look_closure(&self, subset: &[i32], behind: Context, ahead: Context) -> HashSet<i32> {
+        let mut visited: HashSet<i32> = subset.iter().cloned().collect();
+        if self.looks.is_empty() {
+            return visited;
+        }
+        let mut not_visited = subset.to_vec();
+        while let Some(start) = not_visited.pop() {
+            let looks = self.looks.get(&start).into_iter().flatten();
+            let passed = looks
+                .filter(|(look, _)| look.holds(behind, ahead))
+                .map(|&(_, to)| to);
+            let epsilons = self.find_transitions(start, None).unwrap_or_default();
+            for state in passed.chain(epsilons) {
+                if visited.insert(state) {
+                    not_visited.push(state);
+                }
+            }
+        }
+        visited
+    }
+
+    /// Converts the NFA into an equivalent DFA with the subset construction.
+    /// Every reachable set of NFA states becomes a single DFA state.
+    ///
+    /// The assertions depend on the bytes around a position, so a DFA state
+    /// is a set of NFA states together with the context of the previous
+    /// byte. The assertions are checked when the next byte, or the end of
+    /// the input, is known. That way no byte is read twice and matching
+    /// stays linear.
+    ///
+    /// The bytes in a class have the same transitions, so only one byte of
+    /// every class is followed and the table has a column for every class.
+    pub fn to_dfa(&self) -> DFA<S> {
+        self.try_to_dfa(DfaLimits::unlimited())
+            .expect("unlimited DFA")
+    }
+
+    /// Same as `to_dfa`, but fails as soon as the DFA grows over the limits
+    pub fn try_to_dfa(&self, limits: DfaLimits) -> Result<DFA<S>, String> {
+        let representatives = self.classes.representatives();
+        let class_count = representatives.len();
+        // Without assertions the previous byte makes no difference
+        let has_looks = !self.looks.is_empty();
+        let behind = |context| if has_looks { context } else { Context::Edge };
+
+        let start = self.subset(vec![self.start_state].into_iter().collect());
+        let mut ids: HashMap<(Vec<i32>, Context), usize> = HashMap::new();
+        let mut subsets = Vec::new();
+        let mut starts = [0; 4];
+        for context in Context::ALL {
+            let key = (start.clone(), behind(context));
+            starts[context.index()] = *ids.entry(key.clone()).or_insert_with(|| {
+                subsets.push(key);
+                subsets.len() - 1
+            });
+        }
+        let mut transitions = Vec::new();
+        let mut accepts_before = Vec::new();
+        let mut moves = HashMap::new();
+        // Many classes and states usually lead to the same states, so the
+        // closures are computed only once for every distinct set of targets
+        let mut closures = HashMap::<(Vec<i32>, Context), usize>::new();
+        // The subsets are stored twice, and the closures once
+        let state_memory =
+            |subset: &[i32]| 2 * subset.len() * size_of::<i32>() + class_count * size_of::<usize>();
+        let mut memory: usize = subsets.iter().map(|(subset, _)| state_memory(subset)).sum();
+
+        // The subsets are visited in the order of their ids
+        while accepts_before.len() < subsets.len() {
+            let (subset, previous) = subsets[accepts_before.len()].clone();
+            let mut targets = vec![Vec::<i32>::new(); class_count];
+            let mut accepts = 0;
+            // Without assertions the states are the same in every context
+            let contexts: &[Context] = if has_looks {
+                &Context::ALL
+            } else {
+                &[Context::Edge]
+            };
+            for &context in contexts {
+                let states = self.look_closure(&subset, previous, context);
+                if states
+                    .iter()
+                    .any(|state| self.accept_states.contains(state))
+                {
+                    accepts |= if has_looks {
+                        1 << context.index()
+                    } else {
+                        0b1111
+                    };
+                }
+                // Every byte has exactly one context, which it is read in.
+                // The bytes of a class share it when there are assertions.
+                for state in states {
+                    let moves = moves
+                        .entry(state)
+                        .or_insert_with(|| self.class_transitions(state, &representatives));
+                    for (class, states) in moves.iter() {
+                        if !has_looks || Context::of(Some(representatives[*class])) == context {
+                            targets[*class].extend(states);
+                        }
+                    }
+                }
+            }
+
+            for (class, mut states) in targets.into_iter().enumerate() {
+                states.sort_unstable();
+                states.dedup();
+                let context = behind(Context::of(Some(representatives[class])));
+                if let Some(&id) = closures.get(&(states.clone(), context)) {
+                    transitions.push(id);
+                    continue;
                 }
+                let key = (self.subset(states.iter().cloned().collect()), context);
+                let id = match ids.get(&key) {
+                    Some(&id) => id,
+                    None => {
+                        memory += state_memory(&key.0);
+                        limits.check(subsets.len() + 1, memory)?;
+                        ids.insert(key.clone(), subsets.len());
+                        subsets.push(key);
+                        subsets.len() - 1
+                    }
+                };
+                memory += states.len() * size_of::<i32>();
+                closures.insert((states, context), id);
+                transitions.push(id);
             }
-            self.transform_transitions(result)
+            accepts_before.push(accepts);
+        }
+
+        let dfa = DFA::with_classes(starts, accepts_before, self.classes, transitions);
+        Ok(dfa.with_alphabet(self.alphabet.clone()))
+    }
+
+    /// All of the transitions from a state on the classes, given by their
+    /// representative bytes
+    fn class_transitions(&self, state: i32, representatives: &[u8]) -> Vec<(usize, HashSet<i32>)> {
+        representatives
+            .iter()
+            .enumerate()
+            .filter_map(|(class, &character)| {
+                self.find_transitions(state, Some(character))
+                    .ok()
+                    .map(|states| (class, states))
+            })
+            .collect()
+    }
+
+    /// The epsilon closure of the states as a sorted list, so that equal
+    /// sets have equal keys
+    fn subset(&self, set: HashSet<i32>) -> Vec<i32> {
+        let mut subset: Vec<i32> = self.transform_transitions(set).into_iter().collect();
+        subset.sort_unstable();
+        subset
+    }
+}
+
+/// The NFA runs as a set of states, like in the subset construction. The
+/// assertions are checked one byte late, when the byte after the position is
+/// known, so the state remembers the context of the last byte as well. Over
+/// other symbols than bytes, the bytes are the numbers of their intervals.
+impl<S: Ord + Clone> Automaton for NFA<S> {
+    type State = (Vec<i32>, Context);
+
+    fn start_state(&self) -> Self::State {
+        (
+            self.subset(HashSet::from([self.start_state])),
+            Context::Edge,
+        )
+    }
+
+    fn step(&self, (subset, behind): &Self::State, byte: u8) -> Self::State {
+        let ahead = Context::of(Some(byte));
+        let moved = self
+            .look_closure(subset, *behind, ahead)
+            .into_iter()
+            .flat_map(|state| self.find_transitions(state, Some(byte)).unwrap_or_default())
+            .collect();
+        // Without assertions the context does not matter, and leaving it out
+        // keeps the states that are equal as sets equal
+        let behind = if self.looks.is_empty() {
+            Context::Edge
+        } else {
+            ahead
         };
-        DFA::new(start, accept, Box::new(transition_set))
+        (self.subset(moved), behind)
+    }
+
+    fn is_accepting(&self, (subset, behind): &Self::State) -> bool {
+        let closure = self.look_closure(subset, *behind, Context::Edge);
+        closure
+            .iter()
+            .any(|state| self.accept_states.contains(state))
+    }
+
+    fn is_dead(&self, (subset, _): &Self::State) -> bool {
+        subset.is_empty()
     }
 }
 
@@ -99,7 +469,8 @@ mod tests {
         let nfa = parser.expr();
         assert!(nfa.is_ok());
         let dfa = nfa.unwrap().to_dfa();
-        assert!(dfa.start_state.contains(&mut 2));
+        assert_eq!(dfa.start_state, 0);
+        assert!(!dfa.accept_states.contains(&dfa.start_state));
     }
 
     #[test]
@@ -110,7 +481,9 @@ mod tests {
         let nfa = parser.expr();
         assert!(nfa.is_ok());
         let dfa = nfa.unwrap().to_dfa();
-        assert!(dfa.accept_states.contains(&mut 5));
+        let state = dfa.get_transition(dfa.start_state, b'a');
+        let state = dfa.get_transition(state, b'b');
+        assert!(dfa.accept_states.contains(&state));
     }
 
     #[test]
@@ -134,4 +507,87 @@ mod tests {
         assert!(start_states.contains(&mut 4));
         assert!(start_states.contains(&mut 2));
     }
+
+    #[test]
+    fn long_regex() {
+        // Tens of thousands of literals compile without copying the
+        // transitions at every operator
+        let regex = "ab|".repeat(20_000) + "c";
+        let scanner = Scanner::new(regex);
+        let nfa = Parser::new(scanner).expr().unwrap();
+        assert_eq!(nfa.accept_states.len(), 20_001);
+    }
+
+    #[test]
+    fn reverse() {
+        let nfa = |regex: &str| {
+            let node = Parser::new(Scanner::new(regex.to_string()))
+                .parse()
+                .unwrap();
+            super::Construction::Thompson.to_nfa(node)
+        };
+        let dfa = nfa("a(b|cd)*").reverse().to_dfa();
+        assert!(dfa.recognizer().accepts(b"a"));
+        assert!(dfa.recognizer().accepts(b"dcba"));
+        assert!(!dfa.recognizer().accepts(b"abcd"));
+
+        // The assertions look the other way
+        let dfa = nfa("^ab\\b").reverse().to_dfa();
+        assert!(dfa.recognizer().accepts(b"ba"));
+        let dfa = nfa("(?m)a$\n^b").reverse().to_dfa();
+        assert!(dfa.recognizer().accepts(b"b\na"));
+    }
+
+    #[test]
+    fn symbols() {
+        use super::Construction;
+        use crate::ast::Node;
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+        enum Event {
+            Login,
+            Read,
+            Write,
+            Delete,
+            Logout,
+        }
+        use Event::*;
+
+        // A session that changes something: login, reads and writes with at
+        // least one write or delete, and logout
+        let any = Node::symbols(vec![(Read, Delete)]);
+        let change = Node::symbols(vec![(Write, Delete)]);
+        let pattern = Node::concat(
+            Node::concat(Node::symbol(Login), Node::star(any.clone())),
+            Node::concat(Node::concat(change, Node::star(any)), Node::symbol(Logout)),
+        );
+        for construction in [Construction::Thompson, Construction::Glushkov] {
+            let nfa = construction.to_symbol_nfa(pattern.clone()).unwrap();
+            let dfa = nfa.to_dfa().minimize();
+            for (input, expected) in [
+                (vec![Login, Write, Logout], true),
+                (vec![Login, Read, Delete, Read, Logout], true),
+                (vec![Login, Read, Logout], false),
+                (vec![Login, Write], false),
+                (vec![Write, Logout], false),
+            ] {
+                assert_eq!(nfa.accepts(&input), expected, "{:?}", input);
+                assert_eq!(dfa.accepts(&input), expected, "{:?}", input);
+            }
+        }
+
+        let words = Node::concat(Node::symbol("hello"), Node::symbols(vec![("a", "m")]));
+        let dfa = Construction::Thompson
+            .to_symbol_nfa(words)
+            .unwrap()
+            .to_dfa();
+        assert!(dfa.accepts(&["hello", "it"]));
+        assert!(!dfa.accepts(&["hello", "world"]));
+
+        let look = Node::Look {
+            look: crate::look::Look::Start,
+        };
+        let assertion = Node::concat(look, Node::symbol(1u32));
+        assert!(Construction::Thompson.to_symbol_nfa(assertion).is_err());
+    }
 }
@@ -1,71 +1,221 @@
+use crate::classes::ByteClasses;
+use crate::look::{Context, Look};
 use crate::nfa::NFA;
 use std::collections::{HashMap, HashSet};
 
+/// The states and transitions of an NFA under construction. Every fragment
+/// of the NFA adds its transitions here and only refers to its states by
+/// their labels, so combining fragments does not copy any transitions and
+/// the construction takes linear time.
 #[derive(Debug)]
-pub struct NFAFragment {
-    pub start_state: i32,
-    pub accept_states: HashSet<i32>,
+pub struct StateArena {
+    state_count: i32,
     transitions: HashMap<(i32, Option<u8>), HashSet<i32>>,
+    /// Epsilon transitions that can only be taken when the assertion holds
+    looks: HashMap<i32, Vec<(Look, i32)>>,
 }
 
-/// An intermediate stage for processing the AST
-impl NFAFragment {
-    pub fn new(start_state: i32, accept_states: HashSet<i32>) -> Self {
-        NFAFragment {
-            start_state,
-            accept_states,
+impl Default for StateArena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StateArena {
+    pub fn new() -> Self {
+        StateArena {
+            state_count: 1,
             transitions: HashMap::new(),
+            looks: HashMap::new(),
         }
     }
 
+    /// Labels a new state
+    pub fn new_state(&mut self) -> i32 {
+        self.state_count += 1;
+        self.state_count
+    }
+
     /// Creates a transitions for a given symbol between states: from -> to
     pub fn create_transition(&mut self, from: i32, character: Option<u8>, to: i32) {
-        match self.transitions.get_mut(&(from, character)) {
-            Some(to_states) => {
-                to_states.insert(to);
+        self.transitions
+            .entry((from, character))
+            .or_default()
+            .insert(to);
+    }
+
+    /// Creates an epsilon transition that requires the assertion to hold
+    pub fn create_look(&mut self, from: i32, look: Look, to: i32) {
+        self.looks.entry(from).or_default().push((look, to));
+    }
+
+    /// Converts the arena into NFA that starts and accepts where the fragment
+    /// does
+    pub fn to_nfa(self, fragment: NFAFragment) -> NFA {
+        let classes = self.byte_classes();
+        let state_count = self.state_count(&fragment);
+        let transitions = self.transitions;
+        let t = move |start: i32, character: Option<u8>| match transitions.get(&(start, character))
+        {
+            None => Err("Can't make transitions".to_string()),
+            Some(to_states) => Ok(to_states.clone()),
+        };
+        NFA::new(
+            fragment.start_state,
+            fragment.accept_states,
+            Box::new(t),
+            self.looks,
+            classes,
+            state_count,
+        )
+    }
+
+    /// The number of distinct states in the transitions and the fragment
+    fn state_count(&self, fragment: &NFAFragment) -> usize {
+        let mut states: HashSet<i32> = fragment.accept_states.clone();
+        states.insert(fragment.start_state);
+        for (&(from, _), to_states) in self.transitions.iter() {
+            states.insert(from);
+            states.extend(to_states);
+        }
+        for (&from, looks) in self.looks.iter() {
+            states.insert(from);
+            states.extend(looks.iter().map(|&(_, to)| to));
+        }
+        states.len()
+    }
+
+    /// The bytes that lead from a state into the same states form a set,
+    /// and the classes keep every set apart from the other bytes. With
+    /// assertions, the bytes in a class also need to have the same context.
+    fn byte_classes(&self) -> ByteClasses {
+        let mut sets = HashMap::<(i32, Vec<i32>), Vec<u8>>::new();
+        for (&(from, character), to_states) in self.transitions.iter() {
+            if let Some(byte) = character {
+                let mut targets: Vec<i32> = to_states.iter().cloned().collect();
+                targets.sort_unstable();
+                sets.entry((from, targets)).or_default().push(byte);
             }
-            None => {
-                let mut to_states = HashSet::<i32>::new();
-                to_states.insert(to);
-                self.transitions.insert((from, character), to_states);
+        }
+        let mut distinct: HashSet<Vec<u8>> = HashSet::new();
+        for (_, mut bytes) in sets {
+            bytes.sort_unstable();
+            distinct.insert(bytes);
+        }
+
+        let mut classes = ByteClasses::new();
+        for bytes in distinct {
+            let mut set = [false; 256];
+            for byte in bytes {
+                set[byte as usize] = true;
             }
+            classes.split(&set);
         }
+        if !self.looks.is_empty() {
+            for context in Context::ALL {
+                classes.split(&std::array::from_fn(|byte| {
+                    Context::of(Some(byte as u8)) == context
+                }));
+            }
+        }
+        classes
     }
+}
+
+/// An intermediate stage for processing the AST, a part of the NFA with its
+/// transitions in the arena
+#[derive(Debug)]
+pub struct NFAFragment {
+    pub start_state: i32,
+    pub accept_states: HashSet<i32>,
+}
 
-    /// A skeleton for a new fragment with the context of previous transitions
-    pub fn create_skeleton(&self) -> Self {
+impl NFAFragment {
+    pub fn new(start_state: i32, accept_states: HashSet<i32>) -> Self {
         NFAFragment {
-            start_state: 0,
-            accept_states: HashSet::new(),
-            transitions: self.transitions.clone(),
+            start_state,
+            accept_states,
         }
     }
 
     /// '|' operator in the regex
-    pub fn union_operator(&self, fragment: &NFAFragment) -> Self {
-        let mut new_frag = self.create_skeleton();
-        for (key, to_states) in fragment.create_skeleton().transitions {
-            /*
-            if !new_frag.transitions.contains_key(&key) {
-                new_frag.transitions.insert(key, to_states);
-            }
-            */
-            new_frag.transitions.entry(key).or_insert(to_states);
+    pub fn union(self, fragment: NFAFragment, arena: &mut StateArena) -> Self {
+        let start = arena.new_state();
+        arena.create_transition(start, None, self.start_state);
+        arena.create_transition(start, None, fragment.start_state);
+        NFAFragment::new(start, merge(self.accept_states, fragment.accept_states))
+    }
+
+    /// Concatenation in the regex
+    pub fn concat(self, fragment: NFAFragment, arena: &mut StateArena) -> Self {
+        for state in self.accept_states {
+            arena.create_transition(state, None, fragment.start_state);
         }
-        new_frag
+        NFAFragment::new(self.start_state, fragment.accept_states)
     }
 
-    /// Converts the fragment into NFA
-    pub fn to_nfa(self) -> NFA {
-        let accepts_copy = self.accept_states.clone();
-        let start_copy = self.start_state;
-        let t = move |start: i32, character: Option<u8>| match self
-            .transitions
-            .get(&(start, character))
-        {
-            None => Err("Can't make transitions".to_string()),
-            Some(to_states) => Ok(to_states.clone()),
+    /// '+' operator in the regex, the accept states lead back to the start
+    pub fn plus(self, arena: &mut StateArena) -> Self {
+        for &state in self.accept_states.iter() {
+            arena.create_transition(state, None, self.start_state);
+        }
+        self
+    }
+
+    /// '*' operator in the regex
+    pub fn star(self, arena: &mut StateArena) -> Self {
+        let start = arena.new_state();
+        for &state in self.accept_states.iter() {
+            arena.create_transition(state, None, self.start_state);
+        }
+        arena.create_transition(start, None, self.start_state);
+        let mut accept_states = self.accept_states;
+        accept_states.insert(start);
+        NFAFragment::new(start, accept_states)
+    }
+}
+
+/// The union of the sets, by adding the smaller one into the larger one
+fn merge(left: HashSet<i32>, right: HashSet<i32>) -> HashSet<i32> {
+    let (mut larger, smaller) = if left.len() >= right.len() {
+        (left, right)
+    } else {
+        (right, left)
+    };
+    larger.extend(smaller);
+    larger
+}
+
+//grcov-excl-start
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operators() {
+        let mut arena = StateArena::new();
+        let character = |arena: &mut StateArena, c: u8| {
+            let (from, to) = (arena.new_state(), arena.new_state());
+            arena.create_transition(from, Some(c), to);
+            NFAFragment::new(from, vec![to].into_iter().collect())
         };
-        NFA::new(start_copy, accepts_copy, Box::new(t))
+        let a = character(&mut arena, b'a');
+        let b = character(&mut arena, b'b');
+        let c = character(&mut arena, b'c');
+        // (a|b)*c
+        let fragment = a
+            .union(b, &mut arena)
+            .star(&mut arena)
+            .concat(c, &mut arena);
+        let dfa = arena.to_nfa(fragment).to_dfa();
+        // 'a', 'b', 'c' and the rest
+        assert_eq!(dfa.class_count(), 4);
+        assert!(dfa.recognizer().accepts(b"c"));
+        assert!(dfa.recognizer().accepts(b"abbac"));
+        assert!(!dfa.recognizer().accepts(b"ab"));
+        assert!(!dfa.recognizer().accepts(b"cc"));
     }
 }
+
+//grcov-excl-stop
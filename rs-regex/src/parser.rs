@@ -1,12 +1,40 @@
-use crate::ast::{Counter, Node};
-use crate::nfa::NFA;
+use crate::ast::Node;
+use crate::dfa::DfaLimits;
+use crate::look::Look;
+use crate::nfa::{Construction, NFA};
 use crate::scanner::Scanner;
 use crate::tokens::{Token, TokenType};
+use crate::utf8;
+
+/// Flags that change how the regex is interpreted. They can be given when
+/// building the regex or inline in the regex, e.g. `(?i)` or `(?i:abc)`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flags {
+    /// `i`, ASCII letters match both the upper and the lower case letter
+    pub case_insensitive: bool,
+    /// `s`, '.' matches also a newline
+    pub dot_all: bool,
+    /// `m`, '^' and '$' match also at the start and at the end of a line
+    pub multi_line: bool,
+    /// `x`, whitespace and comments starting with '#' are ignored
+    pub verbose: bool,
+}
+
+/// The default for how deep the groups can be nested
+pub const DEFAULT_NESTING_LIMIT: usize = 250;
 
 #[derive(Debug)]
 pub struct Parser {
     scanner: Scanner,
     current_token: Token,
+    flags: Flags,
+    /// The number of groups opened so far
+    group_count: usize,
+    /// The largest group index referred to with a backreference
+    max_backreference: usize,
+    /// How deep the groups are nested at the current token
+    depth: usize,
+    nesting_limit: usize,
 }
 
 /// Parses the regular expression by asking tokens from the scanner one by one
@@ -20,24 +48,59 @@ pub struct Parser {
 /// <seq>     ->  <subseq> | ''
 /// <subseq>  ->  <star> <subseq> | <star>
 /// <star>    ->  <factor> '*' | <factor>
-/// <factor>  ->  '(' <subexpr> ')' | ASCII_CHAR
+/// <factor>  ->  '(' <subexpr> ')' | '(?' FLAGS ')' | '(?' FLAGS ':' <subexpr> ')'
+///               | '(?' LOOKAROUND <subexpr> ')'
+///               | '\p' PROPERTY | '\P' PROPERTY | '.' | '\x' HEX HEX | LOOK
+///               | '\' DIGIT | '\e' | '\' CLASS_ESCAPE | '[' CLASS ']' | CHAR
+///
+/// Flags set with '(?' FLAGS ')' apply until the end of the enclosing group.
+/// LOOK is one of the assertions '^', '$', '\b' and '\B'. The groups in
+/// '(' ')' are numbered from 1, and '\' DIGIT refers back to one of them.
+/// LOOKAROUND is one of '=', '!', '<=' and '<!'.
+/// '\x' HEX HEX is a single raw byte, even above 0x7f where it is not valid
+/// UTF-8 on its own. CLASS is a list of characters and ranges such as 'a-z',
+/// negated when it starts with '^'.
+/// An empty sequence, as in '()', 'a|' or '|b', and '\e' match the empty
+/// string, and the empty class '[]' matches nothing.
+/// CLASS_ESCAPE is one of 'd', 'w' and 's', or the negated 'D', 'W' and 'S',
+/// which can also be used inside of a CLASS. The other escapes are read into
+/// characters by the scanner, see `Scanner::escape`.
+///
+/// Sequences and alternatives are parsed with loops, and only the groups
+/// recurse. The depth of the groups is limited, so that a regex can not
+/// overflow the stack.
 
 impl Parser {
     pub fn new(scanner: Scanner) -> Self {
+        Parser::with_flags(scanner, Flags::default())
+    }
+
+    pub fn with_flags(mut scanner: Scanner, flags: Flags) -> Self {
+        scanner.verbose = flags.verbose;
         let mut parser = Parser {
             scanner,
-            current_token: Token::new(TokenType::NoOp, 0x00),
+            current_token: Token::new(TokenType::NoOp, '\0'),
+            flags,
+            group_count: 0,
+            max_backreference: 0,
+            depth: 0,
+            nesting_limit: DEFAULT_NESTING_LIMIT,
         };
         parser.advance();
         parser
     }
 
+    /// Sets how deep the groups can be nested before parsing fails
+    pub fn set_nesting_limit(&mut self, limit: usize) {
+        self.nesting_limit = limit;
+    }
+
     pub fn print_tokens(&mut self) {
         let mut tokens = vec![self.current_token.clone()];
         self.current_token = self.scanner.get_next_token();
         loop {
             let token = self.current_token.clone();
-            if let TokenType::EOF = token.type_ {
+            if let TokenType::EOF | TokenType::Error = token.type_ {
                 break;
             }
             tokens.push(token);
@@ -46,10 +109,16 @@ impl Parser {
         for token in tokens {
             println!("{}", token);
         }
+        if let Some(error) = self.scanner.error() {
+            println!("{}", error);
+        }
     }
 
     /// Consumes a token and proceeds to the next one
     pub fn eat(&mut self, token: TokenType) -> Result<(), String> {
+        if self.current_token.type_ == TokenType::Error {
+            return Err(self.scanner.error().unwrap_or_default().to_string());
+        }
         if self.current_token.type_ != token {
             return Err("Parsing error. Check the syntax of the regular expression.".to_string());
         }
@@ -61,81 +130,435 @@ impl Parser {
         self.current_token = self.scanner.get_next_token()
     }
 
+    /// Changes the flags. The token after the current one is already scanned
+    /// with the new flags.
+    fn set_flags(&mut self, flags: Flags) {
+        self.flags = flags;
+        self.scanner.verbose = flags.verbose;
+    }
+
+    /// Checks if the current token can start a factor
+    fn at_factor(&self) -> bool {
+        matches!(
+            self.current_token.type_,
+            TokenType::LeftParen
+                | TokenType::LeftBracket
+                | TokenType::FlagGroup
+                | TokenType::Property
+                | TokenType::Dot
+                | TokenType::ClassEscape
+                | TokenType::Empty
+                | TokenType::Look
+                | TokenType::Backreference
+                | TokenType::Char
+                | TokenType::Byte
+        )
+    }
+
     /// Corresponds to the production:
-    /// <factor> -> '(' <subexpr> ')' | ASCII_CHAR
+    /// <factor> -> '(' <subexpr> ')' | '(?' FLAGS ')' | '(?' FLAGS ':' <subexpr> ')'
+    ///             | '(?' LOOKAROUND <subexpr> ')'
+    ///             | '\p' PROPERTY | '\P' PROPERTY | '.' | '\x' HEX HEX | LOOK
+    ///             | '\' DIGIT | '\e' | '\' CLASS_ESCAPE | '[' CLASS ']' | CHAR
     fn factor(&mut self) -> Result<Node, String> {
         match self.current_token.type_ {
             TokenType::LeftParen => {
                 self.eat(TokenType::LeftParen)?;
-                let node = self.subexpr()?;
-                self.eat(TokenType::RightParen)?;
+                self.group_count += 1;
+                let index = self.group_count;
+                let node = self.group(self.flags)?;
+                Ok(Node::capture(index, node))
+            }
+            TokenType::Backreference => {
+                let index = self.current_token.value.to_digit(10).unwrap() as usize;
+                self.max_backreference = self.max_backreference.max(index);
+                self.eat(TokenType::Backreference)?;
+                Ok(Node::backreference(index))
+            }
+            TokenType::LeftBracket => {
+                let node = self.class()?;
+                self.advance();
+                Ok(node)
+            }
+            TokenType::FlagGroup => self.flag_group(),
+            TokenType::Property => self.property(),
+            TokenType::Dot => {
+                let node = Node::any(self.flags.dot_all);
+                self.eat(TokenType::Dot)?;
+                Ok(node)
+            }
+            TokenType::Empty => {
+                self.eat(TokenType::Empty)?;
+                Ok(Node::Empty)
+            }
+            TokenType::ClassEscape => {
+                let (negated, ranges) = class_escape(self.current_token.value);
+                self.eat(TokenType::ClassEscape)?;
+                if negated {
+                    Ok(utf8::class(&utf8::negate(&ranges)))
+                } else {
+                    let bytes = ranges.iter().map(|&(start, end)| (start as u8, end as u8));
+                    Ok(Node::class(bytes.collect()))
+                }
+            }
+            TokenType::Look => {
+                let look = match (self.current_token.value, self.flags.multi_line) {
+                    ('^', false) => Look::Start,
+                    ('^', true) => Look::StartLine,
+                    ('$', false) => Look::End,
+                    ('$', true) => Look::EndLine,
+                    ('b', _) => Look::WordBoundary,
+                    _ => Look::NotWordBoundary,
+                };
+                self.eat(TokenType::Look)?;
+                Ok(Node::look(look))
+            }
+            TokenType::Byte => {
+                let byte = self.current_token.value as u8;
+                let node = if byte.is_ascii() {
+                    self.character(self.current_token.value)
+                } else {
+                    Node::character(byte)
+                };
+                self.eat(TokenType::Byte)?;
                 Ok(node)
             }
             _ => {
-                let node = Node::character(self.current_token.value);
+                let node = self.character(self.current_token.value);
                 self.eat(TokenType::Char)?;
                 Ok(node)
             }
         }
     }
 
+    /// A character, or a class of both cases of a letter when the regex is
+    /// case insensitive. Non-ASCII characters are matched as their UTF-8
+    /// encoding.
+    fn character(&self, character: char) -> Node {
+        if !character.is_ascii() {
+            return utf8::character(character);
+        }
+        let character = character as u8;
+        if self.flags.case_insensitive && character.is_ascii_alphabetic() {
+            let lower = character.to_ascii_lowercase();
+            let upper = character.to_ascii_uppercase();
+            Node::class(vec![(lower, lower), (upper, upper)])
+        } else {
+            Node::character(character)
+        }
+    }
+
+    /// Parses a bracketed class such as '[a-z_]' or '[^0-9]'. ASCII
+    /// characters and raw bytes are matched as bytes, other characters as
+    /// their UTF-8 encoding. A negated class matches the characters that are
+    /// not in it, so it can not contain raw bytes above 0x7f.
+    fn class(&mut self) -> Result<Node, String> {
+        let (negated, ranges) = self.scanner.class()?;
+        let byte = |token: &Token| match token.type_ {
+            TokenType::Byte => Some(token.value as u8),
+            _ => Some(token.value as u8).filter(|_| token.value.is_ascii()),
+        };
+        let code_point = |token: &Token| match token.type_ {
+            TokenType::Byte if !token.value.is_ascii() => None,
+            _ => Some(token.value as u32),
+        };
+        let mut bytes = Vec::new();
+        let mut code_points = Vec::new();
+        for (start, end) in ranges {
+            if start.type_ == TokenType::ClassEscape {
+                match class_escape(start.value) {
+                    (true, ranges) => code_points.extend(utf8::negate(&ranges)),
+                    (false, ranges) => {
+                        bytes.extend(ranges.iter().map(|&(start, end)| (start as u8, end as u8)))
+                    }
+                }
+                continue;
+            }
+            match (byte(&start), byte(&end)) {
+                (Some(start), Some(end)) if start <= end => bytes.push((start, end)),
+                _ => match (code_point(&start), code_point(&end)) {
+                    (Some(start), Some(end)) if start <= end => code_points.push((start, end)),
+                    _ => {
+                        return Err(format!(
+                            "Parsing error. Invalid class range {}-{}.",
+                            start.value.escape_default(),
+                            end.value.escape_default()
+                        ))
+                    }
+                },
+            }
+        }
+        if self.flags.case_insensitive {
+            let letters: Vec<(u8, u8)> = bytes
+                .iter()
+                .flat_map(|&(start, end)| start..=end)
+                .filter(|byte| byte.is_ascii_alphabetic())
+                .map(|byte| byte ^ 0x20)
+                .map(|byte| (byte, byte))
+                .collect();
+            bytes.extend(letters);
+        }
+
+        if negated {
+            if bytes.iter().any(|&(_, end)| !end.is_ascii()) {
+                return Err(
+                    "Parsing error. A negated class can not contain raw bytes above 0x7f."
+                        .to_string(),
+                );
+            }
+            code_points.extend(bytes.iter().map(|&(start, end)| (start as u32, end as u32)));
+            return Ok(utf8::class(&utf8::negate(&merge(code_points))));
+        }
+        if code_points.is_empty() {
+            return Ok(if bytes.is_empty() {
+                Node::Nothing
+            } else {
+                Node::class(bytes)
+            });
+        }
+        // The ASCII bytes are the same characters, so they go into one class
+        // with the other characters and only the raw bytes stay apart
+        let (ascii, raw): (Vec<_>, Vec<_>) =
+            bytes.into_iter().partition(|&(_, end)| end.is_ascii());
+        code_points.extend(ascii.iter().map(|&(start, end)| (start as u32, end as u32)));
+        let characters = utf8::class(&merge(code_points));
+        Ok(if raw.is_empty() {
+            characters
+        } else {
+            Node::union(Node::class(raw), characters)
+        })
+    }
+
+    /// Parses a Unicode general category after '\p' or '\P', given either as
+    /// a single letter, e.g. '\pL', or inside braces, e.g. '\p{Lu}'. '\P'
+    /// matches the characters that are not in the category.
+    fn property(&mut self) -> Result<Node, String> {
+        let negated = self.current_token.value == 'P';
+        self.eat(TokenType::Property)?;
+        let name = if self.current_token.value == '{' {
+            self.eat(TokenType::Char)?;
+            let mut name = String::new();
+            while self.current_token.value != '}' {
+                name.push(self.current_token.value);
+                self.eat(TokenType::Char)?;
+            }
+            self.eat(TokenType::Char)?;
+            name
+        } else {
+            let name = self.current_token.value.to_string();
+            self.eat(TokenType::Char)?;
+            name
+        };
+
+        let ranges = utf8::property(&name)?;
+        if negated {
+            Ok(utf8::class(&utf8::negate(&ranges)))
+        } else {
+            Ok(utf8::class(&ranges))
+        }
+    }
+
+    /// Parses the flags after '(?'. A '-' turns off the flags after it.
+    fn flag_group(&mut self) -> Result<Node, String> {
+        self.eat(TokenType::FlagGroup)?;
+        if self.current_token.type_ == TokenType::Char
+            && matches!(self.current_token.value, '=' | '!' | '<')
+        {
+            return self.lookaround();
+        }
+        let outer = self.flags;
+        let mut flags = self.flags;
+        let mut enable = true;
+        loop {
+            match (self.current_token.type_, self.current_token.value) {
+                (TokenType::Char, 'i') => flags.case_insensitive = enable,
+                (TokenType::Char, 's') => flags.dot_all = enable,
+                (TokenType::Char, 'm') => flags.multi_line = enable,
+                (TokenType::Char, 'x') => flags.verbose = enable,
+                (TokenType::Char, '-') if enable => enable = false,
+                (TokenType::Char, ':') => {
+                    self.set_flags(flags);
+                    self.advance();
+                    return self.group(outer);
+                }
+                (TokenType::RightParen, _) => {
+                    self.set_flags(flags);
+                    self.advance();
+                    return Ok(Node::Empty);
+                }
+                (TokenType::EOF, _) => {
+                    return Err("Parsing error. Unterminated flag group.".to_string())
+                }
+                (_, value) => return Err(format!("Unknown flag '{}'", value)),
+            }
+            self.advance();
+        }
+    }
+
+    /// Parses a lookahead or a lookbehind after '(?'
+    fn lookaround(&mut self) -> Result<Node, String> {
+        let behind = self.current_token.value == '<';
+        if behind {
+            self.eat(TokenType::Char)?;
+        }
+        let negated = match self.current_token.value {
+            '=' => false,
+            '!' => true,
+            _ => return Err("Parsing error. Expected '=' or '!' after '(?<'.".to_string()),
+        };
+        self.eat(TokenType::Char)?;
+        let node = self.group(self.flags)?;
+        if behind {
+            Ok(Node::lookbehind(negated, node))
+        } else {
+            Ok(Node::lookahead(negated, node))
+        }
+    }
+
+    /// Parses the rest of a group after its opening, up to and including the
+    /// ')'. The flags set inside the group are reset to `outer` after it.
+    fn group(&mut self, outer: Flags) -> Result<Node, String> {
+        if self.depth >= self.nesting_limit {
+            return Err(format!(
+                "Parsing error. Groups are nested deeper than the limit of {}.",
+                self.nesting_limit
+            ));
+        }
+        self.depth += 1;
+        let node = self.subexpr();
+        self.depth -= 1;
+        let node = node?;
+        self.set_flags(outer);
+        self.eat(TokenType::RightParen)?;
+        Ok(node)
+    }
+
     /// Corresponds to the production:
-    /// <star> -> <factor> '*' | <factor>
+    /// <star> -> <factor> QUANTIFIER | <factor> QUANTIFIER '?' | <factor>
+    ///
+    /// where QUANTIFIER is '*', '+' or '?'. With the '?' after it, the
+    /// quantifier is lazy and repeats as few times as possible. 'a?' is
+    /// 'a|()', or '()|a' when lazy, since the left alternative is preferred.
     fn star(&mut self) -> Result<Node, String> {
         let node = self.factor()?;
-        match self.current_token.type_ {
-            TokenType::Star => {
-                self.eat(TokenType::Star)?;
-                Ok(Node::star(node))
-            }
-            _ => Ok(node),
+        let quantifier = self.current_token.type_;
+        if !matches!(
+            quantifier,
+            TokenType::Star | TokenType::Plus | TokenType::Question
+        ) {
+            return Ok(node);
+        }
+        self.eat(quantifier)?;
+        let greedy = self.current_token.type_ != TokenType::Question;
+        if !greedy {
+            self.eat(TokenType::Question)?;
         }
+        Ok(match quantifier {
+            TokenType::Star if greedy => Node::star(node),
+            TokenType::Star => Node::lazy_star(node),
+            TokenType::Plus if greedy => Node::plus(node),
+            TokenType::Plus => Node::lazy_plus(node),
+            _ if greedy => Node::union(node, Node::Empty),
+            _ => Node::union(Node::Empty, node),
+        })
     }
 
     /// Corresponds to the production:
     /// <seq> -> <subseq> | ''
     fn seq(&mut self) -> Result<Node, String> {
-        match self.current_token.type_ {
-            TokenType::LeftParen => self.subseq(),
-            TokenType::Char => self.subseq(),
-            _ => Ok(Node::character(0x00)),
+        if self.at_factor() {
+            self.subseq()
+        } else {
+            Ok(Node::Empty)
         }
     }
 
     /// Corresponds to the production:
     /// <subseq> -><star> <subseq> | <star>
     fn subseq(&mut self) -> Result<Node, String> {
-        let node = self.star()?;
-        match self.current_token.type_ {
-            TokenType::LeftParen => Ok(Node::concat(node, self.subseq()?)),
-            TokenType::Char => Ok(Node::concat(node, self.subseq()?)),
-            _ => Ok(node),
+        let mut nodes = vec![self.star()?];
+        while self.at_factor() {
+            nodes.push(self.star()?);
         }
+        Ok(fold_right(nodes, Node::concat))
     }
 
     /// Corresponds to the production:
     /// <expr> -> <subexpr> EOF
-    pub fn expr(&mut self) -> Result<NFA, String> {
+    pub fn parse(&mut self) -> Result<Node, String> {
         let node = self.subexpr()?;
         self.eat(TokenType::EOF)?;
+        if self.max_backreference > self.group_count {
+            return Err(format!(
+                "Backreference to group {} that does not exist",
+                self.max_backreference
+            ));
+        }
+        Ok(node)
+    }
+
+    /// Parses the regex and converts the AST into NFA
+    pub fn expr(&mut self) -> Result<NFA, String> {
+        self.expr_with(Construction::Thompson)
+    }
 
-        let mut counter = Counter::new();
-        let fragment = node.to_fragment(&mut counter);
-        Ok(fragment.to_nfa())
+    /// Parses the regex and converts the AST into NFA with the given construction
+    pub fn expr_with(&mut self, construction: Construction) -> Result<NFA, String> {
+        let node = self.parse()?;
+        if node.has_backreferences() {
+            return Err("Backreferences can not be converted into NFA".to_string());
+        }
+        construction.try_to_nfa(node, DfaLimits::unlimited())
     }
 
     /// Corresponds to the producion:
     /// <subexpr> -> <seq> '|' <subexpr> | <seq>
     fn subexpr(&mut self) -> Result<Node, String> {
-        let node = self.seq()?;
-        match self.current_token.type_ {
-            TokenType::Union => {
-                self.eat(TokenType::Union)?;
-                Ok(Node::union(node, self.subexpr()?))
-            }
-            _ => Ok(node),
+        let mut nodes = vec![self.seq()?];
+        while self.current_token.type_ == TokenType::Union {
+            self.eat(TokenType::Union)?;
+            nodes.push(self.seq()?);
+        }
+        Ok(fold_right(nodes, Node::union))
+    }
+}
+
+/// The ASCII ranges of '\d', '\w' or '\s', and if the escape is the negated
+/// '\D', '\W' or '\S'
+fn class_escape(letter: char) -> (bool, Vec<(u32, u32)>) {
+    let ranges = match letter.to_ascii_lowercase() {
+        'd' => vec![('0', '9')],
+        'w' => vec![('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')],
+        _ => vec![('\t', '\r'), (' ', ' ')],
+    };
+    let ranges = ranges
+        .into_iter()
+        .map(|(start, end)| (start as u32, end as u32))
+        .collect();
+    (letter.is_ascii_uppercase(), ranges)
+}
+
+/// Sorts the ranges and merges the ones that overlap or touch
+fn merge(mut ranges: Vec<(u32, u32)>) -> Vec<(u32, u32)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(u32, u32)> = Vec::new();
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some((_, last)) if start <= *last + 1 => *last = (*last).max(end),
+            _ => merged.push((start, end)),
         }
     }
+    merged
+}
+
+/// Combines the nodes with the operator from the right, e.g. [a, b, c]
+/// into a(bc), the same as the right recursive grammar
+fn fold_right(mut nodes: Vec<Node>, operator: fn(Node, Node) -> Node) -> Node {
+    let mut node = nodes.pop().unwrap();
+    while let Some(left) = nodes.pop() {
+        node = operator(left, node);
+    }
+    node
 }
 
 //grcov-excl-start
@@ -201,7 +624,7 @@ mod tests {
         let node = parser.star().unwrap();
         let mut correct = false;
         match node {
-            Node::Star { operand: _ } => correct = true,
+            Node::Star { .. } => correct = true,
             _ => assert!(false),
         }
         assert!(correct);
@@ -219,6 +642,196 @@ mod tests {
         }
     }
 
+    #[test]
+    fn flag_group() {
+        let regex = "(?is-x)a".to_string();
+        let scanner = Scanner::new(regex);
+        let mut parser = Parser::with_flags(
+            scanner,
+            Flags {
+                verbose: true,
+                ..Flags::default()
+            },
+        );
+        let node = parser.factor().unwrap();
+        assert_eq!(node, Node::Empty);
+        assert!(parser.flags.case_insensitive);
+        assert!(parser.flags.dot_all);
+        assert!(!parser.flags.verbose);
+        let node = parser.factor().unwrap();
+        assert_eq!(node, Node::class(vec![(b'A', b'A'), (b'a', b'a')]));
+    }
+
+    #[test]
+    fn classes() {
+        let parse = |regex: &str| Parser::new(Scanner::new(regex.to_string())).parse();
+        let expected = [
+            ("[a-c_]", Node::class(vec![(b'a', b'c'), (b'_', b'_')])),
+            (
+                "(?i:[a-b9])",
+                Node::class(vec![(b'9', b'9'), (b'A', b'B'), (b'a', b'b')]),
+            ),
+            ("[\\x00-\\xff]", Node::class(vec![(0, 0xff)])),
+            ("[ä]", utf8::character('ä')),
+            ("[^a]", utf8::class(&utf8::negate(&[(0x61, 0x61)]))),
+            (
+                "[*|.]",
+                Node::class(vec![(b'*', b'*'), (b'.', b'.'), (b'|', b'|')]),
+            ),
+        ];
+        for (regex, node) in expected {
+            assert_eq!(parse(regex), Ok(node), "regex {}", regex);
+        }
+        for regex in ["[b-a]", "[^\\xff]", "[\\x80-ä]", "[a", "[a-"] {
+            assert!(parse(regex).is_err(), "regex {}", regex);
+        }
+    }
+
+    #[test]
+    fn looks() {
+        let scanner = Scanner::new("^$\\b(?m)^$\\B".to_string());
+        let mut parser = Parser::new(scanner);
+        let looks = [Look::Start, Look::End, Look::WordBoundary];
+        for look in looks {
+            assert_eq!(parser.factor().unwrap(), Node::look(look));
+        }
+        assert_eq!(parser.factor().unwrap(), Node::Empty);
+        let looks = [Look::StartLine, Look::EndLine, Look::NotWordBoundary];
+        for look in looks {
+            assert_eq!(parser.factor().unwrap(), Node::look(look));
+        }
+    }
+
+    #[test]
+    fn groups_and_backreferences() {
+        let scanner = Scanner::new("(a)((b)|c)\\3".to_string());
+        let node = Parser::new(scanner).parse().unwrap();
+        assert_eq!(node.group_count(), 3);
+        assert!(node.has_backreferences());
+
+        let scanner = Scanner::new("(?i:a)(b)\\2".to_string());
+        assert!(Parser::new(scanner).parse().is_err());
+        let scanner = Scanner::new("(a)\\1".to_string());
+        assert!(Parser::new(scanner).expr().is_err());
+    }
+
+    #[test]
+    fn lookarounds() {
+        let scanner = Scanner::new("(?=a)(?!b)(?<=c)(?<!d)".to_string());
+        let mut parser = Parser::new(scanner);
+        let expected = [
+            Node::lookahead(false, Node::character(b'a')),
+            Node::lookahead(true, Node::character(b'b')),
+            Node::lookbehind(false, Node::character(b'c')),
+            Node::lookbehind(true, Node::character(b'd')),
+        ];
+        for node in expected {
+            assert_eq!(parser.factor().unwrap(), node);
+        }
+
+        let scanner = Scanner::new("(?<a)".to_string());
+        assert!(Parser::new(scanner).parse().is_err());
+        let scanner = Scanner::new("(?=a".to_string());
+        assert!(Parser::new(scanner).parse().is_err());
+    }
+
+    #[test]
+    fn quantifiers() {
+        let a = || Node::character(b'a');
+        let expected = [
+            ("a*?", Node::lazy_star(a())),
+            ("a+", Node::plus(a())),
+            ("a+?", Node::lazy_plus(a())),
+            ("a?", Node::union(a(), Node::Empty)),
+            ("a??", Node::union(Node::Empty, a())),
+            ("a\\?", Node::concat(a(), Node::character(b'?'))),
+        ];
+        for (regex, node) in expected {
+            let scanner = Scanner::new(regex.to_string());
+            assert_eq!(Parser::new(scanner).parse().unwrap(), node);
+        }
+        let scanner = Scanner::new("+a".to_string());
+        assert!(Parser::new(scanner).parse().is_err());
+    }
+
+    #[test]
+    fn empty_and_nothing() {
+        let a = || Node::character(b'a');
+        let expected = [
+            ("", Node::Empty),
+            ("()", Node::capture(1, Node::Empty)),
+            ("a|", Node::union(a(), Node::Empty)),
+            ("|a", Node::union(Node::Empty, a())),
+            ("a\\e", Node::concat(a(), Node::Empty)),
+            ("[]", Node::Nothing),
+            ("a[]", Node::concat(a(), Node::Nothing)),
+        ];
+        for (regex, node) in expected {
+            let scanner = Scanner::new(regex.to_string());
+            assert_eq!(Parser::new(scanner).parse().unwrap(), node);
+        }
+        for construction in [Construction::Thompson, Construction::Glushkov] {
+            let parse = |regex: &str| {
+                let mut parser = Parser::new(Scanner::new(regex.to_string()));
+                parser.expr_with(construction).unwrap()
+            };
+            assert!(parse("()*").accepts(b""));
+            assert!(!parse("()*").accepts(b"\0"));
+            assert!(parse("a(|b)").accepts(b"a"));
+            assert!(parse("\\e|b").accepts(b""));
+            assert!(!parse("[]|b").accepts(b""));
+            assert!(!parse("[]*a[]").accepts(b"a"));
+        }
+    }
+
+    #[test]
+    fn escapes() {
+        let parse = |regex: &str| Parser::new(Scanner::new(regex.to_string())).parse();
+        let digits = Node::class(vec![(b'0', b'9')]);
+        assert_eq!(parse("\\d"), Ok(digits));
+        assert_eq!(parse("\\D"), parse("[^0-9]"));
+        assert_eq!(parse("[\\w-]"), parse("[0-9A-Z_a-z\\-]"));
+        assert_eq!(parse("[^\\s]"), parse("\\S"));
+        assert_eq!(parse("[\\Sa]"), parse("\\S"));
+        assert_eq!(parse("\\cI\\o{12}"), parse("\\t\\n"));
+        assert_eq!(
+            parse("a\\"),
+            Err("Parsing error. Trailing backslash at position 1.".to_string())
+        );
+        assert_eq!(
+            parse("(a\\q)"),
+            Err("Parsing error. Unknown escape \\q at position 2.".to_string())
+        );
+        assert_eq!(
+            parse("\\pL\\"),
+            Err("Parsing error. Trailing backslash at position 3.".to_string())
+        );
+        let mut scanner = Scanner::new("a\\01".to_string());
+        scanner.strict = true;
+        assert!(Parser::new(scanner).parse().is_err());
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let mut parser = Parser::new(Scanner::new("((a)(?:b))(?=(c))".to_string()));
+        parser.set_nesting_limit(2);
+        assert!(parser.parse().is_ok());
+        for regex in ["(((a)))", "(?i:(?:(a)))", "((?=(a)))"] {
+            let mut parser = Parser::new(Scanner::new(regex.to_string()));
+            parser.set_nesting_limit(2);
+            assert!(parser.parse().is_err());
+        }
+        // A long sequence is not nested
+        let regex = "a|".repeat(50_000) + &"b".repeat(50_000);
+        let node = Parser::new(Scanner::new(regex)).parse().unwrap();
+        assert_eq!(node.group_count(), 0);
+        assert!(!node.has_looks());
+        // Cloned, compared and dropped without recursion
+        let copy = node.clone();
+        assert_eq!(copy, node);
+        assert!(Node::concat(copy, Node::Empty) > node);
+    }
+
     #[test]
     fn print_tokens() {
         let regex = "ab".to_string();
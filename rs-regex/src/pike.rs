@@ -0,0 +1,361 @@
+use crate::ast::Node;
+use crate::look::Context;
+use crate::program::{compile, Instruction};
+use std::collections::HashMap;
+
+/// Simulates the NFA of the regex with threads that are kept in the order of
+/// their priority, so that the first match found is the one a backtracker
+/// would find. Every position of the input is visited once. At a position an
+/// instruction is added at most once for every set of stars whose iteration
+/// started there, so the search takes linear time in the input when the
+/// stars that can match the empty string are not nested deeply.
+/// https://swtch.com/~rsc/regexp/regexp2.html
+pub struct PikeVM {
+    program: Vec<Instruction>,
+}
+
+/// The position every instruction was last added to a list at. The stars
+/// whose iteration has not consumed anything yet change where a thread can
+/// go, so an instruction reached inside such iterations is a different
+/// state for every set of them.
+struct Added {
+    outside: Vec<usize>,
+    inside: HashMap<(usize, Vec<usize>), usize>,
+}
+
+impl Added {
+    fn new(size: usize) -> Self {
+        Added {
+            outside: vec![usize::MAX; size],
+            inside: HashMap::new(),
+        }
+    }
+
+    /// Returns false if the state was already added at the position
+    fn insert(&mut self, pc: usize, empty: &[usize], at: usize) -> bool {
+        let added = match empty {
+            [] => &mut self.outside[pc],
+            _ => self
+                .inside
+                .entry((pc, empty.to_vec()))
+                .or_insert(usize::MAX),
+        };
+        std::mem::replace(added, at) != at
+    }
+}
+
+/// A thread is at an instruction and remembers where its match started
+#[derive(Debug, Clone, Copy)]
+struct Thread {
+    pc: usize,
+    start: usize,
+}
+
+impl PikeVM {
+    /// Backreferences, lookarounds and boolean operators are not supported
+    pub fn new(node: &Node) -> Result<Self, String> {
+        if node.has_lookarounds() {
+            return Err(
+                "Leftmost-first matching does not support backreferences, lookarounds, intersection or complement"
+                    .to_string(),
+            );
+        }
+        Ok(PikeVM::with_lookarounds(node)?.0)
+    }
+
+    /// Like `new`, but the lookarounds are compiled into assertions. Where
+    /// they hold is looked up in the tables given to `find_with`, so their
+    /// operands are returned in the order of the tables, with true for the
+    /// lookaheads.
+    pub fn with_lookarounds(node: &Node) -> Result<(Self, Vec<(bool, Node)>), String> {
+        if node.has_backreferences() || node.has_boolean_operators() {
+            return Err(
+                "Leftmost-first matching does not support backreferences, lookarounds, intersection or complement"
+                    .to_string(),
+            );
+        }
+        // In the order the lookarounds are numbered in the program
+        let mut lookarounds = Vec::new();
+        let mut nodes = vec![node];
+        while let Some(node) = nodes.pop() {
+            match node {
+                Node::Lookahead { operand, .. } => lookarounds.push((true, (**operand).clone())),
+                Node::Lookbehind { operand, .. } => lookarounds.push((false, (**operand).clone())),
+                _ => {}
+            }
+            nodes.extend(node.children().into_iter().rev());
+        }
+        let program = compile(node).instructions;
+        Ok((PikeVM { program }, lookarounds))
+    }
+
+    /// Finds the leftmost match, and of those the one with the highest
+    /// priority: the left alternative of '|' is preferred, greedy
+    /// quantifiers prefer more repetitions and lazy ones fewer.
+    pub fn find(&self, input: &[u8]) -> Option<(usize, usize)> {
+        self.search(input, false, false, &[])
+    }
+
+    /// Finds the leftmost match, and of those the longest one
+    pub fn find_longest(&self, input: &[u8]) -> Option<(usize, usize)> {
+        self.search(input, true, false, &[])
+    }
+
+    /// Finds the leftmost match, and of those the longest one or the one with
+    /// the highest priority. The tables say where the operands of the
+    /// lookarounds match: from every position of the input for the
+    /// lookaheads, and up to it for the lookbehinds.
+    pub fn find_with(
+        &self,
+        input: &[u8],
+        longest: bool,
+        lookarounds: &[Vec<bool>],
+    ) -> Option<(usize, usize)> {
+        self.search(input, longest, false, lookarounds)
+    }
+
+    /// Checks if the whole input matches
+    pub fn matches(&self, input: &[u8]) -> bool {
+        self.search(input, true, true, &[]) == Some((0, input.len()))
+    }
+
+    /// For the longest matches the threads are kept in the order of their
+    /// start instead of their priority. A thread that gets to an instruction
+    /// first then has the leftmost start, and the threads with the same
+    /// instruction would continue the same way.
+    fn search(
+        &self,
+        input: &[u8],
+        longest: bool,
+        anchored: bool,
+        lookarounds: &[Vec<bool>],
+    ) -> Option<(usize, usize)> {
+        let mut current = Vec::new();
+        let mut next = Vec::new();
+        let mut added = Added::new(self.program.len());
+        let mut found: Option<(usize, usize)> = None;
+        for at in 0..=input.len() {
+            // A new thread starting here has the lowest priority
+            if found.is_none() && (!anchored || at == 0) {
+                let thread = Thread { pc: 0, start: at };
+                self.add(&mut current, &mut added, thread, at, input, lookarounds);
+            } else if current.is_empty() {
+                break;
+            }
+            for &Thread { pc, start } in current.iter() {
+                // Only the matches that start at the same place can be longer
+                if longest && found.is_some_and(|(first, _)| start > first) {
+                    continue;
+                }
+                match &self.program[pc] {
+                    Instruction::Bytes(ranges) => match input.get(at) {
+                        Some(&byte) if ranges.iter().any(|&(lo, hi)| lo <= byte && byte <= hi) => {
+                            let thread = Thread { pc: pc + 1, start };
+                            self.add(&mut next, &mut added, thread, at + 1, input, lookarounds);
+                        }
+                        _ => {}
+                    },
+                    Instruction::Match if longest => {
+                        if found.is_none_or(|(first, _)| start <= first) {
+                            found = Some((start, at));
+                        }
+                    }
+                    // The threads after this one have a lower priority
+                    Instruction::Match => {
+                        found = Some((start, at));
+                        break;
+                    }
+                    _ => unreachable!("only consuming instructions are kept in the list"),
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        found
+    }
+
+    /// Follows the instructions that do not consume input, in the order of
+    /// their priority, and adds the threads that are left to the list. Like
+    /// in the backtracker, an iteration of a star that matches the empty
+    /// string ends the loop, so the stars whose iteration started here are
+    /// kept with every instruction, innermost last.
+    fn add(
+        &self,
+        list: &mut Vec<Thread>,
+        added: &mut Added,
+        Thread { pc, start }: Thread,
+        at: usize,
+        input: &[u8],
+        lookarounds: &[Vec<bool>],
+    ) {
+        let mut stack = vec![(pc, Vec::new())];
+        while let Some((pc, mut empty)) = stack.pop() {
+            if !added.insert(pc, &empty, at) {
+                continue;
+            }
+            match &self.program[pc] {
+                Instruction::Split(first, second) => {
+                    stack.push((*second, empty.clone()));
+                    stack.push((*first, empty));
+                }
+                Instruction::Jump(target) => stack.push((*target, empty)),
+                Instruction::Mark(star) => {
+                    empty.push(*star);
+                    stack.push((pc + 1, empty));
+                }
+                Instruction::Repeat { star, .. } if empty.last() == Some(star) => {
+                    empty.pop();
+                    stack.push((pc + 1, empty));
+                }
+                Instruction::Repeat { start, .. } => stack.push((*start, empty)),
+                // The groups only matter to the backtracker
+                Instruction::Open(_) | Instruction::Close(_) => stack.push((pc + 1, empty)),
+                Instruction::Look(look) => {
+                    let behind = Context::of(at.checked_sub(1).map(|i| input[i]));
+                    let ahead = Context::of(input.get(at).cloned());
+                    if look.holds(behind, ahead) {
+                        stack.push((pc + 1, empty));
+                    }
+                }
+                // The operand is decided by the table, so its instructions
+                // are skipped
+                Instruction::Lookahead {
+                    index,
+                    negated,
+                    end,
+                }
+                | Instruction::Lookbehind {
+                    index,
+                    negated,
+                    end,
+                } => {
+                    if lookarounds[*index][at] != *negated {
+                        stack.push((*end, empty));
+                    }
+                }
+                Instruction::Fail => {}
+                Instruction::Backreference(_) => {
+                    unreachable!("backreferences are rejected before")
+                }
+                Instruction::Bytes(_) | Instruction::Match => list.push(Thread { pc, start }),
+            }
+        }
+    }
+}
+
+//grcov-excl-start
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backtrack::{Backtracker, DEFAULT_STEP_BUDGET};
+    use crate::parser::Parser;
+    use crate::regex::MatchKind;
+    use crate::scanner::Scanner;
+    use rand::Rng;
+
+    fn find(regex: &str, input: &str) -> Option<(usize, usize)> {
+        let scanner = Scanner::new(regex.to_string());
+        let node = Parser::new(scanner).parse().unwrap();
+        PikeVM::new(&node).unwrap().find(input.as_bytes())
+    }
+
+    #[test]
+    fn priorities() {
+        assert_eq!(find("a|ab", "ab"), Some((0, 1)));
+        assert_eq!(find("ab|a", "ab"), Some((0, 2)));
+        assert_eq!(find("a*", "aaa"), Some((0, 3)));
+        assert_eq!(find("a*?", "aaa"), Some((0, 0)));
+        assert_eq!(find("a+?", "aaa"), Some((0, 1)));
+        assert_eq!(find("a??b", "ab"), Some((0, 2)));
+        assert_eq!(find("<.*?>", "<a><b>"), Some((0, 3)));
+        assert_eq!(find("<.*>", "<a><b>"), Some((0, 6)));
+    }
+
+    #[test]
+    fn leftmost() {
+        assert_eq!(find("b+", "aabbb"), Some((2, 5)));
+        assert_eq!(find("x|b*?c", "abbc"), Some((1, 4)));
+        assert_eq!(find("\\bb", "ab b"), Some((3, 4)));
+        assert_eq!(find("(a*)*", "b"), Some((0, 0)));
+        assert_eq!(find("c", "ab"), None);
+    }
+
+    #[test]
+    fn longest() {
+        let vm = |regex: &str| {
+            let scanner = Scanner::new(regex.to_string());
+            PikeVM::new(&Parser::new(scanner).parse().unwrap()).unwrap()
+        };
+        assert_eq!(vm("a|ab").find_longest(b"ab"), Some((0, 2)));
+        assert_eq!(vm("a*?").find_longest(b"aaa"), Some((0, 3)));
+        assert_eq!(vm("abcd|c").find_longest(b"xabcd"), Some((1, 5)));
+        assert_eq!(vm("b(a|b)*|aab").find_longest(b"aabab"), Some((0, 3)));
+        assert_eq!(vm("c").find_longest(b"ab"), None);
+        assert!(vm("(a|b)*c").matches(b"abc"));
+        assert!(!vm("(a|b)*c").matches(b"abcc"));
+        assert!(!vm("b").matches(b"ab"));
+    }
+
+    #[test]
+    fn empty_iterations() {
+        // An iteration that matches the empty string ends the loop
+        assert_eq!(find("(b*?)*", "bb"), Some((0, 0)));
+        assert_eq!(find("(b*?)*?c", "bbc"), Some((0, 3)));
+        assert_eq!(find("(|b)*", "bb"), Some((0, 0)));
+        assert_eq!(find("(b|)*", "bb"), Some((0, 2)));
+        assert_eq!(find("(b*?)+b", "bb"), Some((0, 1)));
+    }
+
+    /// A random regex with nested greedy and lazy quantifiers
+    fn random_regex(rng: &mut impl Rng, depth: usize) -> String {
+        let choice = if depth == 0 { 0 } else { rng.gen_range(0..4) };
+        const QUANTIFIERS: [&str; 6] = ["*", "+", "?", "*?", "+?", "??"];
+        if choice == 0 {
+            return ["a", "b", "", "\\b"][rng.gen_range(0..4)].to_string();
+        }
+        let left = random_regex(rng, depth - 1);
+        let right = random_regex(rng, depth - 1);
+        match choice {
+            1 => left + &right,
+            2 => format!("{}|{}", left, right),
+            _ => format!(
+                "({}){}",
+                left,
+                QUANTIFIERS[rng.gen_range(0..QUANTIFIERS.len())]
+            ),
+        }
+    }
+
+    #[test]
+    fn same_as_backtracking() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..500 {
+            let regex = random_regex(&mut rng, 4);
+            let node = Parser::new(Scanner::new(regex.clone())).parse().unwrap();
+            let vm = PikeVM::new(&node).unwrap();
+            let backtracker = Backtracker::new(node, DEFAULT_STEP_BUDGET).unwrap();
+            for _ in 0..20 {
+                let input: Vec<u8> = (0..rng.gen_range(0..8))
+                    .map(|_| b"ab "[rng.gen_range(0..3)])
+                    .collect();
+                assert_eq!(
+                    vm.find(&input),
+                    backtracker.find(&input, MatchKind::LeftmostFirst).unwrap(),
+                    "{} in {:?}",
+                    regex,
+                    String::from_utf8_lossy(&input)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported() {
+        let scanner = Scanner::new("(a)\\1".to_string());
+        let node = Parser::new(scanner).parse().unwrap();
+        assert!(PikeVM::new(&node).is_err());
+    }
+}
+
+//grcov-excl-stop
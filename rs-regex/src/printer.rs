@@ -0,0 +1,332 @@
+use crate::ast::Node;
+use crate::look::Look;
+use std::fmt::{self, Display, Formatter};
+
+/// How tightly the printed form of a node binds. An operand that binds less
+/// tightly than its operator needs is put in a non-capturing group.
+const UNION: u8 = 0;
+const CONCAT: u8 = 1;
+const POSTFIX: u8 = 2;
+const ATOM: u8 = 3;
+
+/// The characters that are escaped outside of a class. '&' and '~' are the
+/// operators of intersection and complement.
+const META: &[u8] = b"\\|*+?.^$()[&~";
+/// The characters that are escaped inside of a class
+const CLASS_META: &[u8] = b"\\]^-";
+
+/// A part of the printed pattern
+enum Item<'a> {
+    Node(&'a Node, u8),
+    Text(&'static str),
+    Owned(String),
+}
+
+/// Prints the node as a pattern that parses back into an equal node. Only
+/// the parentheses that the precedence needs are printed, and they are
+/// non-capturing. The quantifier '?' is recognized from the union with the
+/// empty string that the parser makes of it.
+///
+/// Intersections and complements have no syntax in the parser. They are
+/// printed as `(?:a&b)` and `~a`, and the literal '&' and '~' are escaped so
+/// that the notation stays unambiguous.
+///
+/// The tree is walked with an explicit stack, so it can be arbitrarily deep.
+impl Display for Node {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut items = vec![Item::Node(self, UNION)];
+        while let Some(item) = items.pop() {
+            let (node, min) = match item {
+                Item::Text(text) => {
+                    f.write_str(text)?;
+                    continue;
+                }
+                Item::Owned(text) => {
+                    f.write_str(&text)?;
+                    continue;
+                }
+                Item::Node(node, min) => (node, min),
+            };
+            let (precedence, parts) = parts(node);
+            let grouped = precedence < min;
+            if grouped {
+                items.push(Item::Text(")"));
+            }
+            items.extend(parts.into_iter().rev());
+            if grouped {
+                items.push(Item::Text("(?:"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The precedence of the printed form of the node and its parts in order
+fn parts(node: &Node) -> (u8, Vec<Item<'_>>) {
+    match node {
+        Node::Empty => (ATOM, vec![Item::Text("\\e")]),
+        Node::Nothing => (ATOM, vec![Item::Text("[]")]),
+        Node::Character { character } => (ATOM, vec![Item::Owned(escape(*character, META))]),
+        Node::Class { ranges } => (ATOM, vec![Item::Owned(class(ranges))]),
+        Node::Look { look } => (ATOM, vec![Item::Text(look_text(*look))]),
+        Node::Union { left, right } if **right == Node::Empty => {
+            (POSTFIX, vec![Item::Node(left, ATOM), Item::Text("?")])
+        }
+        Node::Union { left, right } if **left == Node::Empty => {
+            (POSTFIX, vec![Item::Node(right, ATOM), Item::Text("??")])
+        }
+        Node::Union { left, right } => (
+            UNION,
+            vec![
+                Item::Node(left, CONCAT),
+                Item::Text("|"),
+                Item::Node(right, UNION),
+            ],
+        ),
+        Node::Concat { left, right } => (
+            CONCAT,
+            vec![Item::Node(left, POSTFIX), Item::Node(right, CONCAT)],
+        ),
+        Node::Star { operand, greedy } => {
+            let star = if *greedy { "*" } else { "*?" };
+            (POSTFIX, vec![Item::Node(operand, ATOM), Item::Text(star)])
+        }
+        Node::Plus { operand, greedy } => {
+            let plus = if *greedy { "+" } else { "+?" };
+            (POSTFIX, vec![Item::Node(operand, ATOM), Item::Text(plus)])
+        }
+        Node::Capture { operand, .. } if **operand == Node::Empty => (ATOM, vec![Item::Text("()")]),
+        Node::Capture { operand, .. } => (
+            ATOM,
+            vec![Item::Text("("), Item::Node(operand, UNION), Item::Text(")")],
+        ),
+        Node::Backreference { index } => (ATOM, vec![Item::Owned(format!("\\{}", index))]),
+        Node::Lookahead { negated, operand } => {
+            let open = if *negated { "(?!" } else { "(?=" };
+            (
+                ATOM,
+                vec![
+                    Item::Text(open),
+                    Item::Node(operand, UNION),
+                    Item::Text(")"),
+                ],
+            )
+        }
+        Node::Lookbehind { negated, operand } => {
+            let open = if *negated { "(?<!" } else { "(?<=" };
+            (
+                ATOM,
+                vec![
+                    Item::Text(open),
+                    Item::Node(operand, UNION),
+                    Item::Text(")"),
+                ],
+            )
+        }
+        Node::And { left, right } => (
+            ATOM,
+            vec![
+                Item::Text("(?:"),
+                Item::Node(left, CONCAT),
+                Item::Text("&"),
+                Item::Node(right, CONCAT),
+                Item::Text(")"),
+            ],
+        ),
+        Node::Not { operand } => (POSTFIX, vec![Item::Text("~"), Item::Node(operand, ATOM)]),
+    }
+}
+
+fn look_text(look: Look) -> &'static str {
+    match look {
+        Look::Start => "^",
+        Look::End => "$",
+        Look::StartLine => "(?m:^)",
+        Look::EndLine => "(?m:$)",
+        Look::WordBoundary => "\\b",
+        Look::NotWordBoundary => "\\B",
+    }
+}
+
+/// A bracketed class, with the ranges of two bytes written as two bytes
+fn class(ranges: &[(u8, u8)]) -> String {
+    let mut text = String::from("[");
+    for &(start, end) in ranges {
+        text.push_str(&escape(start, CLASS_META));
+        if end > start {
+            if end > start + 1 {
+                text.push('-');
+            }
+            text.push_str(&escape(end, CLASS_META));
+        }
+    }
+    text.push(']');
+    text
+}
+
+/// Writes the byte so that it is read back as the same byte, with a
+/// backslash before the special characters
+fn escape(byte: u8, special: &[u8]) -> String {
+    match byte {
+        b'\n' => "\\n".to_string(),
+        b'\t' => "\\t".to_string(),
+        b'\r' => "\\r".to_string(),
+        _ if special.contains(&byte) => format!("\\{}", byte as char),
+        _ if byte.is_ascii_graphic() || byte == b' ' => (byte as char).to_string(),
+        _ => format!("\\x{:02X}", byte),
+    }
+}
+
+//grcov-excl-start
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn parse(regex: &str) -> Result<Node, String> {
+        Parser::new(Scanner::new(regex.to_string())).parse()
+    }
+
+    #[test]
+    fn minimal_parentheses() {
+        let printed = [
+            ("ab|cd", "ab|cd"),
+            ("(?:a|b)c", "(?:a|b)c"),
+            ("(?:ab)*", "(?:ab)*"),
+            ("(?:(?:a))", "a"),
+            ("a+b?c??d*?", "a+b?c??d*?"),
+            ("(?:a*)+", "(?:a*)+"),
+            ("(a)(?:b)", "(a)b"),
+            ("(a)(?=a)(?<!b|c)\\1", "(a)(?=a)(?<!b|c)\\1"),
+            ("(?m:^a$\\b)", "(?m:^)a(?m:$)\\b"),
+            ("(?i:a)", "[Aa]"),
+        ];
+        for (regex, expected) in printed {
+            let node = parse(regex).unwrap();
+            assert_eq!(node.to_string(), expected, "regex {}", regex);
+            assert_eq!(parse(&node.to_string()), Ok(node), "regex {}", regex);
+        }
+        // The UTF-8 sequences are long to write out, only the round trip
+        // is checked
+        for regex in ["[^\\x00-\\x7f]", "ä+", "(?i:ä)"] {
+            let node = parse(regex).unwrap();
+            assert_eq!(parse(&node.to_string()), Ok(node), "regex {}", regex);
+        }
+    }
+
+    #[test]
+    fn escapes() {
+        let node = parse("\\*\\[]\\\\\\n\\x00\\x7f\\xff&~").unwrap();
+        assert_eq!(node.to_string(), "\\*\\[]\\\\\\n\\x00\\x7F\\xFF\\&\\~");
+        let node = Node::class(vec![(b'-', b'-'), (b'a', b'b'), (b']', b']'), (0x80, 0xff)]);
+        assert_eq!(node.to_string(), "[\\-\\]ab\\x80-\\xFF]");
+        assert_eq!(parse(&node.to_string()), Ok(node));
+        assert_eq!(Node::Nothing.to_string(), "[]");
+        assert_eq!(Node::Empty.to_string(), "\\e");
+        assert_eq!(parse("()|a|").unwrap().to_string(), "()|a?");
+    }
+
+    #[test]
+    fn boolean_operators() {
+        let a = Node::character(b'a');
+        let node = Node::concat(
+            Node::intersection(Node::star(a.clone()), Node::union(a.clone(), a.clone())),
+            Node::complement(a),
+        );
+        assert_eq!(node.to_string(), "(?:a*&(?:a|a))~a");
+    }
+
+    #[test]
+    fn deep() {
+        let regex = "a|".repeat(50_000) + &"b".repeat(50_000);
+        let node = parse(&regex).unwrap();
+        assert_eq!(node.to_string(), regex);
+    }
+
+    /// A random regex from the parts that the parser knows
+    fn random_regex(rng: &mut impl Rng, depth: usize) -> String {
+        const ATOMS: [&str; 24] = [
+            "a",
+            "b",
+            "\\n",
+            "\\x41",
+            "\\xc3",
+            "ä",
+            ".",
+            "[a-c]",
+            "[^x]",
+            "[\\]-]",
+            "[a\\xf0-\\xff]",
+            "^",
+            "$",
+            "\\b",
+            "\\B",
+            "(?m:^$)",
+            "(?i:ab)",
+            "(?s:.)",
+            "\\*",
+            "\\1",
+            "()",
+            "(?)",
+            "\\e",
+            "[]",
+        ];
+        let choice = if depth == 0 { 0 } else { rng.gen_range(0..8) };
+        let mut inner = |count| {
+            let regexes: Vec<String> = (0..count).map(|_| random_regex(rng, depth - 1)).collect();
+            regexes
+        };
+        match choice {
+            0 => ATOMS[rng.gen_range(0..ATOMS.len())].to_string(),
+            1 | 2 => inner(3).concat(),
+            3 => inner(2).join("|"),
+            4 => format!("({})", inner(1)[0]),
+            5 => format!("(?:{})", inner(1)[0]),
+            6 => {
+                const QUANTIFIERS: [&str; 6] = ["*", "+", "?", "*?", "+?", "??"];
+                let operand = inner(1).concat();
+                let quantifier = QUANTIFIERS[rng.gen_range(0..QUANTIFIERS.len())];
+                format!("(?:{}){}", operand, quantifier)
+            }
+            _ => {
+                const LOOKAROUNDS: [&str; 4] = ["(?=", "(?!", "(?<=", "(?<!"];
+                let operand = inner(1).concat();
+                let open = LOOKAROUNDS[rng.gen_range(0..LOOKAROUNDS.len())];
+                format!("{}{})", open, operand)
+            }
+        }
+    }
+
+    #[test]
+    fn parse_print_parse() {
+        // The seed is in the messages, so that a failure can be reproduced
+        let seed = rand::thread_rng().gen();
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut checked = 0;
+        while checked < 1000 {
+            let regex = random_regex(&mut rng, 4);
+            let node = match parse(&regex) {
+                Ok(node) => node,
+                Err(_) => continue,
+            };
+            let printed = node.to_string();
+            let reparsed = parse(&printed);
+            assert_eq!(
+                reparsed,
+                Ok(node),
+                "regex {} printed {} seed {}",
+                regex,
+                printed,
+                seed
+            );
+            assert_eq!(reparsed.unwrap().to_string(), printed, "seed {}", seed);
+            checked += 1;
+        }
+    }
+}
+
+//grcov-excl-stop
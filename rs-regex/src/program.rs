@@ -0,0 +1,280 @@
+use crate::ast::Node;
+use crate::look::Look;
+
+/// The program that the backtracking engine and the Pike VM run. The
+/// backtracker follows one alternative at a time, and the Pike VM follows
+/// all of them at once in the order of their priority.
+pub struct Program {
+    pub instructions: Vec<Instruction>,
+    pub star_count: usize,
+}
+
+/// An instruction of the program the regex is compiled into
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    /// Consumes a byte in one of the ranges
+    Bytes(Vec<(u8, u8)>),
+    /// Continues from both, the first one has the higher priority
+    Split(usize, usize),
+    Jump(usize),
+    Look(Look),
+    Fail,
+    Match,
+    /// Remembers where the group starts
+    Open(usize),
+    /// Sets the match of the group, from where it was opened to here
+    Close(usize),
+    Backreference(usize),
+    /// Remembers where an iteration of the star starts
+    Mark(usize),
+    /// Ends an iteration of the star and goes back to its start. An
+    /// iteration that matched the empty string ends the loop instead, as in
+    /// Perl, otherwise it could repeat forever.
+    Repeat {
+        star: usize,
+        start: usize,
+    },
+    /// The operand of the lookaround is the program from the next
+    /// instruction up to its own `Match`, and the search goes on at the end.
+    /// The lookarounds are numbered in the order of their '('.
+    Lookahead {
+        index: usize,
+        negated: bool,
+        end: usize,
+    },
+    Lookbehind {
+        index: usize,
+        negated: bool,
+        end: usize,
+    },
+}
+
+/// The work left for the compiler, with the parts of the program that are
+/// patched once their targets are known
+enum Task<'a> {
+    Compile(&'a Node),
+    Emit(Instruction),
+    /// Leaves a placeholder to be patched and remembers where it is
+    Hole,
+    Union,
+    Star {
+        greedy: bool,
+        star: usize,
+    },
+    Plus {
+        greedy: bool,
+        star: usize,
+    },
+    Lookahead {
+        index: usize,
+        negated: bool,
+    },
+    Lookbehind {
+        index: usize,
+        negated: bool,
+    },
+}
+
+/// Compiles the node into a program that ends with `Match`. The node is
+/// walked with an explicit stack, so it can be arbitrarily deep.
+pub fn compile(node: &Node) -> Program {
+    let mut program = Vec::new();
+    let mut star_count = 0;
+    let mut lookaround_count = 0;
+    let mut holes = Vec::new();
+    let mut tasks = vec![Task::Emit(Instruction::Match), Task::Compile(node)];
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Compile(node) => match node {
+                Node::Empty => {}
+                Node::Nothing => program.push(Instruction::Fail),
+                Node::Character { character } => {
+                    program.push(Instruction::Bytes(vec![(*character, *character)]))
+                }
+                Node::Class { ranges } => program.push(Instruction::Bytes(ranges.clone())),
+                Node::Look { look } => program.push(Instruction::Look(*look)),
+                // Split to the left, the left, jump to the end, the right
+                Node::Union { left, right } => tasks.extend([
+                    Task::Union,
+                    Task::Compile(right),
+                    Task::Hole,
+                    Task::Compile(left),
+                    Task::Hole,
+                ]),
+                Node::Concat { left, right } => {
+                    tasks.extend([Task::Compile(right), Task::Compile(left)])
+                }
+                // Split to the operand or the end, mark, the operand, repeat
+                Node::Star { operand, greedy } => {
+                    tasks.extend([
+                        Task::Star {
+                            greedy: *greedy,
+                            star: star_count,
+                        },
+                        Task::Compile(operand),
+                        Task::Emit(Instruction::Mark(star_count)),
+                        Task::Hole,
+                    ]);
+                    star_count += 1;
+                }
+                // Jump to the mark, then the same as the star
+                Node::Plus { operand, greedy } => {
+                    tasks.extend([
+                        Task::Plus {
+                            greedy: *greedy,
+                            star: star_count,
+                        },
+                        Task::Compile(operand),
+                        Task::Emit(Instruction::Mark(star_count)),
+                        Task::Hole,
+                        Task::Hole,
+                    ]);
+                    star_count += 1;
+                }
+                Node::Capture { index, operand } => tasks.extend([
+                    Task::Emit(Instruction::Close(*index)),
+                    Task::Compile(operand),
+                    Task::Emit(Instruction::Open(*index)),
+                ]),
+                Node::Backreference { index } => program.push(Instruction::Backreference(*index)),
+                Node::Lookahead { negated, operand } => {
+                    tasks.extend([
+                        Task::Lookahead {
+                            index: lookaround_count,
+                            negated: *negated,
+                        },
+                        Task::Emit(Instruction::Match),
+                        Task::Compile(operand),
+                        Task::Hole,
+                    ]);
+                    lookaround_count += 1;
+                }
+                Node::Lookbehind { negated, operand } => {
+                    tasks.extend([
+                        Task::Lookbehind {
+                            index: lookaround_count,
+                            negated: *negated,
+                        },
+                        Task::Emit(Instruction::Match),
+                        Task::Compile(operand),
+                        Task::Hole,
+                    ]);
+                    lookaround_count += 1;
+                }
+                Node::And { .. } | Node::Not { .. } => {
+                    unreachable!("boolean operators are rejected before")
+                }
+            },
+            Task::Emit(instruction) => program.push(instruction),
+            Task::Hole => {
+                holes.push(program.len());
+                program.push(Instruction::Fail);
+            }
+            Task::Union => {
+                let jump = holes.pop().unwrap();
+                let split = holes.pop().unwrap();
+                program[split] = Instruction::Split(split + 1, jump + 1);
+                program[jump] = Instruction::Jump(program.len());
+            }
+            Task::Star { greedy, star } | Task::Plus { greedy, star } => {
+                let split = holes.pop().unwrap();
+                if let Task::Plus { .. } = task {
+                    let jump = holes.pop().unwrap();
+                    program[jump] = Instruction::Jump(split + 1);
+                }
+                program.push(Instruction::Repeat { star, start: split });
+                let (body, end) = (split + 1, program.len());
+                program[split] = if greedy {
+                    Instruction::Split(body, end)
+                } else {
+                    Instruction::Split(end, body)
+                };
+            }
+            Task::Lookahead { index, negated } => {
+                let hole = holes.pop().unwrap();
+                let end = program.len();
+                program[hole] = Instruction::Lookahead {
+                    index,
+                    negated,
+                    end,
+                };
+            }
+            Task::Lookbehind { index, negated } => {
+                let hole = holes.pop().unwrap();
+                let end = program.len();
+                program[hole] = Instruction::Lookbehind {
+                    index,
+                    negated,
+                    end,
+                };
+            }
+        }
+    }
+    Program {
+        instructions: program,
+        star_count,
+    }
+}
+
+//grcov-excl-start
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn program(regex: &str) -> Vec<Instruction> {
+        let node = Parser::new(Scanner::new(regex.to_string()))
+            .parse()
+            .unwrap();
+        compile(&node).instructions
+    }
+
+    #[test]
+    fn instructions() {
+        let a = || Instruction::Bytes(vec![(b'a', b'a')]);
+        assert_eq!(
+            program("a|b*?"),
+            vec![
+                Instruction::Split(1, 3),
+                a(),
+                Instruction::Jump(7),
+                Instruction::Split(7, 4),
+                Instruction::Mark(0),
+                Instruction::Bytes(vec![(b'b', b'b')]),
+                Instruction::Repeat { star: 0, start: 3 },
+                Instruction::Match,
+            ]
+        );
+        assert_eq!(
+            program("(a)(?!a)"),
+            vec![
+                Instruction::Open(1),
+                a(),
+                Instruction::Close(1),
+                Instruction::Lookahead {
+                    index: 0,
+                    negated: true,
+                    end: 6,
+                },
+                a(),
+                Instruction::Match,
+                Instruction::Match,
+            ]
+        );
+        assert_eq!(
+            program("a+"),
+            vec![
+                Instruction::Jump(2),
+                Instruction::Split(2, 5),
+                Instruction::Mark(0),
+                a(),
+                Instruction::Repeat { star: 0, start: 1 },
+                Instruction::Match,
+            ]
+        );
+    }
+}
+
+//grcov-excl-stop
@@ -1,30 +1,578 @@
-use crate::dfa::DFA;
-use crate::parser::Parser;
+use crate::ast::Node;
+use crate::backtrack::{Backtracker, DEFAULT_STEP_BUDGET};
+use crate::derivative::DerivativeDFA;
+use crate::dfa::{DfaLimits, DFA};
+use crate::error::RegexError;
+use crate::literal::Prefilter;
+use crate::lookaround::{self, Lookaround};
+use crate::nfa::{Construction, DEFAULT_NFA_SIZE_LIMIT, NFA};
+use crate::parser::{Flags, Parser, DEFAULT_NESTING_LIMIT};
+use crate::pike::PikeVM;
 use crate::scanner::Scanner;
+use crate::simulation::Simulation;
+use std::fmt::{self, Display, Formatter};
+
+/// The algorithm that does the matching
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// The DFA if the regex is regular, otherwise backtracking
+    Auto,
+    /// Compiles the regex into a DFA, matches in linear time
+    Dfa,
+    /// Simulates the NFA without building a DFA, matches in O(n·m) time for
+    /// an NFA of m states. Used when the DFA would be too big.
+    Nfa,
+    /// Tries the alternatives one by one, supports backreferences but may need exponential time
+    Backtrack,
+}
+
+impl Display for Engine {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Engine::Auto => write!(f, "automatic"),
+            Engine::Dfa => write!(f, "DFA"),
+            Engine::Nfa => write!(f, "NFA"),
+            Engine::Backtrack => write!(f, "backtracking"),
+        }
+    }
+}
+
+/// Which of the matches starting at the leftmost position is found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchKind {
+    /// The longest one, as in POSIX
+    #[default]
+    LeftmostLongest,
+    /// The first one in the order of priority, as in Perl: the left
+    /// alternative of '|' is preferred and lazy quantifiers such as '*?'
+    /// repeat as few times as possible
+    LeftmostFirst,
+}
+
+/// The sizes of a compiled regex
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Statistics {
+    pub engine: Engine,
+    /// The states of the DFA, zero without a DFA
+    pub dfa_states: usize,
+    /// The byte equivalence classes, which are the columns of the DFA table
+    pub byte_classes: usize,
+}
+
+enum Matcher {
+    /// The reverse DFA reads the input backwards and finds where the matches
+    /// start. A DFA loaded from a file has none.
+    Dfa {
+        dfa: Box<DFA>,
+        reverse: Option<Box<DFA>>,
+    },
+    /// Only the reverse DFA would have been too big, so the DFA matches whole
+    /// inputs and finds where the matches end, but where they start is found
+    /// by simulating the NFA
+    Forward {
+        dfa: Box<DFA>,
+        simulation: Box<Simulation>,
+    },
+    /// The DFA would have been too big, or the NFA engine was chosen
+    Nfa(Box<Simulation>),
+    Backtrack(Backtracker),
+    /// The DFA matches whole inputs, but the leftmost-first matches are found
+    /// by simulating the NFA, since the DFA does not know the priorities.
+    /// Without a DFA the whole inputs are matched by simulation as well.
+    Pike {
+        dfa: Option<Box<DFA>>,
+        vm: PikeVM,
+    },
+    /// For lookarounds the DFA matches whole inputs, but the lookarounds
+    /// can see past the ends of a match inside an input. Those are found by
+    /// deciding the lookarounds at every position with their own DFAs first,
+    /// and then simulating the NFA with them as assertions.
+    Hybrid {
+        dfa: Box<DFA>,
+        vm: PikeVM,
+        lookarounds: Vec<Lookaround>,
+    },
+}
 
 pub struct Regex {
-    dfa: DFA,
+    matcher: Matcher,
+    kind: MatchKind,
+    /// Skips to the literals of the regex when searching with the DFA
+    prefilter: Option<Prefilter>,
 }
 
-impl Regex {
-    pub fn new(regex: String) -> Result<Regex, String> {
-        if !regex.is_ascii() {
-            return Err("Regex was not ascii".to_string());
+/// Collects the options for compiling a regex
+#[derive(Debug, Clone)]
+pub struct RegexBuilder {
+    regex: String,
+    flags: Flags,
+    strict: bool,
+    construction: Construction,
+    engine: Engine,
+    step_budget: usize,
+    kind: MatchKind,
+    nesting_limit: usize,
+    nfa_size_limit: usize,
+    dfa_limits: DfaLimits,
+}
+
+impl RegexBuilder {
+    pub fn new(regex: String) -> Self {
+        RegexBuilder {
+            regex,
+            flags: Flags::default(),
+            strict: false,
+            construction: Construction::Thompson,
+            engine: Engine::Auto,
+            step_budget: DEFAULT_STEP_BUDGET,
+            kind: MatchKind::default(),
+            nesting_limit: DEFAULT_NESTING_LIMIT,
+            nfa_size_limit: DEFAULT_NFA_SIZE_LIMIT,
+            dfa_limits: DfaLimits::default(),
         }
+    }
+
+    /// ASCII letters match both cases, same as the inline flag `(?i)`
+    pub fn case_insensitive(&mut self, yes: bool) -> &mut Self {
+        self.flags.case_insensitive = yes;
+        self
+    }
+
+    /// '.' matches also a newline, same as the inline flag `(?s)`
+    pub fn dot_matches_new_line(&mut self, yes: bool) -> &mut Self {
+        self.flags.dot_all = yes;
+        self
+    }
+
+    /// '^' and '$' match also at the start and at the end of a line, same as
+    /// the inline flag `(?m)`
+    pub fn multi_line(&mut self, yes: bool) -> &mut Self {
+        self.flags.multi_line = yes;
+        self
+    }
+
+    /// Whitespace and '#' comments are ignored, same as the inline flag `(?x)`
+    pub fn verbose(&mut self, yes: bool) -> &mut Self {
+        self.flags.verbose = yes;
+        self
+    }
+
+    /// The algorithm for converting the regex into NFA
+    pub fn construction(&mut self, construction: Construction) -> &mut Self {
+        self.construction = construction;
+        self
+    }
+
+    /// The matching engine, `Engine::Auto` by default
+    pub fn engine(&mut self, engine: Engine) -> &mut Self {
+        self.engine = engine;
+        self
+    }
+
+    /// The most steps the backtracking engine may take in a single search
+    /// before giving up with an error
+    pub fn step_budget(&mut self, steps: usize) -> &mut Self {
+        self.step_budget = steps;
+        self
+    }
+
+    /// Rejects the escapes and class ranges that could be read in more than
+    /// one way, such as '\01', '\xA', '\@' and '[a-c-e]'
+    pub fn strict(&mut self, yes: bool) -> &mut Self {
+        self.strict = yes;
+        self
+    }
+
+    /// Which match the searches find, `MatchKind::LeftmostLongest` by
+    /// default. Lazy quantifiers only make a difference with
+    /// `MatchKind::LeftmostFirst`.
+    pub fn match_kind(&mut self, kind: MatchKind) -> &mut Self {
+        self.kind = kind;
+        self
+    }
+
+    /// How deep the groups can be nested before compiling fails with a
+    /// syntax error
+    pub fn nesting_limit(&mut self, limit: usize) -> &mut Self {
+        self.nesting_limit = limit;
+        self
+    }
+
+    /// The most states the NFA may have. Compiling a bigger regex fails with
+    /// `RegexError::TooBig`.
+    pub fn nfa_size_limit(&mut self, states: usize) -> &mut Self {
+        self.nfa_size_limit = states;
+        self
+    }
+
+    /// The most states the DFA may have. With `Engine::Auto` a regex whose
+    /// DFA would be bigger is matched by simulating the NFA instead, and
+    /// with `Engine::Dfa` compiling it fails with `RegexError::TooBig`.
+    pub fn dfa_state_limit(&mut self, states: usize) -> &mut Self {
+        self.dfa_limits.states = states;
+        self
+    }
+
+    /// About how many bytes of memory building the DFA may use, the same way
+    /// as `dfa_state_limit`
+    pub fn dfa_size_limit(&mut self, bytes: usize) -> &mut Self {
+        self.dfa_limits.memory = bytes;
+        self
+    }
+
+    /// Parses the regex with the flags into the AST, without compiling it
+    pub fn parse(&self) -> Result<Node, RegexError> {
+        let mut scanner = Scanner::new(self.regex.clone());
+        scanner.strict = self.strict;
+        let mut parser = Parser::with_flags(scanner, self.flags);
+        parser.set_nesting_limit(self.nesting_limit);
+        Ok(parser.parse()?)
+    }
+
+    pub fn build(&self) -> Result<Regex, RegexError> {
+        let node = self.parse()?;
+        let prefilter = Prefilter::new(&node);
+        let matcher = match self.engine {
+            Engine::Dfa | Engine::Nfa => self.automaton(node)?,
+            Engine::Backtrack => self.backtracker(node)?,
+            Engine::Auto if node.has_backreferences() => self.backtracker(node)?,
+            Engine::Auto if node.has_lookarounds() => match self.automaton(node.clone()) {
+                Err(RegexError::Unsupported { .. } | RegexError::TooBig(_)) => {
+                    self.backtracker(node)?
+                }
+                matcher => matcher?,
+            },
+            Engine::Auto => self.automaton(node)?,
+        };
+        Ok(Regex {
+            matcher,
+            kind: self.kind,
+            prefilter,
+        })
+    }
+
+    /// The matcher for the DFA and the NFA engines
+    fn automaton(&self, node: Node) -> Result<Matcher, RegexError> {
+        let engine = match self.engine {
+            Engine::Nfa => Engine::Nfa,
+            _ => Engine::Dfa,
+        };
+        let unsupported = |reason: String| RegexError::Unsupported { engine, reason };
+        if node.has_backreferences() {
+            return Err(unsupported(
+                "backreferences can not be matched with a finite automaton".to_string(),
+            ));
+        }
+        if !node.has_lookarounds() && self.kind == MatchKind::LeftmostFirst {
+            let vm = PikeVM::new(&node).map_err(unsupported)?;
+            let nfa = self.nfa(node)?;
+            let dfa = self.within_limits(|| nfa.try_to_dfa(self.dfa_limits))?;
+            return Ok(Matcher::Pike {
+                dfa: dfa.map(Box::new),
+                vm,
+            });
+        }
+        if !node.has_lookarounds() {
+            let nfa = self.nfa(node)?;
+            let dfa = self.within_limits(|| nfa.try_to_dfa(self.dfa_limits))?;
+            let reverse = self.within_limits(|| {
+                nfa.reverse()
+                    .try_to_dfa(self.dfa_limits)?
+                    .try_unanchored(self.dfa_limits)
+            })?;
+            return Ok(match (dfa, reverse) {
+                (Some(dfa), Some(reverse)) => Matcher::Dfa {
+                    dfa: Box::new(dfa),
+                    reverse: Some(Box::new(reverse)),
+                },
+                (Some(dfa), None) => Matcher::Forward {
+                    dfa: Box::new(dfa),
+                    simulation: Box::new(Simulation::new(&nfa)),
+                },
+                (None, _) => Matcher::Nfa(Box::new(Simulation::new(&nfa))),
+            });
+        }
+        if engine == Engine::Nfa {
+            return Err(unsupported(
+                "lookarounds are matched with a DFA and backtracking".to_string(),
+            ));
+        }
+        // Intersections do not support assertions
+        if node.has_looks() {
+            return Err(unsupported(
+                "lookarounds can not be combined with assertions".to_string(),
+            ));
+        }
+        let (vm, operands) = PikeVM::with_lookarounds(&node).map_err(unsupported)?;
+        let rewritten = lookaround::to_intersections(node).map_err(unsupported)?;
+        let dfa = self
+            .nfa(rewritten)?
+            .try_to_dfa(self.dfa_limits)
+            .map_err(RegexError::TooBig)?;
+        let lookarounds = operands
+            .into_iter()
+            .map(|(ahead, operand)| {
+                Lookaround::new(ahead, &self.nfa(operand)?, self.dfa_limits)
+                    .map_err(RegexError::TooBig)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Matcher::Hybrid {
+            dfa: Box::new(dfa),
+            vm,
+            lookarounds,
+        })
+    }
+
+    /// Converts the node into NFA, which fails if it has too many states.
+    /// The automata only need the language, so the node is simplified first.
+    fn nfa(&self, node: Node) -> Result<NFA, RegexError> {
+        let nfa = self
+            .construction
+            .try_to_nfa(node.simplify(), self.dfa_limits)
+            .map_err(RegexError::TooBig)?;
+        if nfa.state_count() > self.nfa_size_limit {
+            return Err(RegexError::TooBig(format!(
+                "The NFA has more than {} states",
+                self.nfa_size_limit
+            )));
+        }
+        Ok(nfa)
+    }
+
+    /// Builds a DFA unless the NFA engine was chosen. A DFA over the limits
+    /// is an error only if the DFA engine was chosen, otherwise the NFA is
+    /// simulated instead.
+    fn within_limits(
+        &self,
+        build: impl FnOnce() -> Result<DFA, String>,
+    ) -> Result<Option<DFA>, RegexError> {
+        match self.engine {
+            Engine::Nfa => Ok(None),
+            Engine::Dfa => build().map(Some).map_err(RegexError::TooBig),
+            _ => Ok(build().ok()),
+        }
+    }
+
+    fn backtracker(&self, node: Node) -> Result<Matcher, RegexError> {
+        Ok(Matcher::Backtrack(self.backtracker_for(node)?))
+    }
+
+    fn backtracker_for(&self, node: Node) -> Result<Backtracker, RegexError> {
+        Backtracker::new(node, self.step_budget).map_err(|reason| RegexError::Unsupported {
+            engine: Engine::Backtrack,
+            reason,
+        })
+    }
+}
+
+impl Regex {
+    pub fn new(regex: String) -> Result<Regex, RegexError> {
+        RegexBuilder::new(regex).build()
+    }
+
+    /// Compiles the regex using the given NFA construction
+    pub fn with_construction(
+        regex: String,
+        construction: Construction,
+    ) -> Result<Regex, RegexError> {
+        RegexBuilder::new(regex).construction(construction).build()
+    }
+
+    /// Builds the DFA directly from the derivatives of the regex instead of
+    /// going through the NFA. Derivatives do not support assertions or
+    /// backreferences, and lookarounds are rewritten into intersections.
+    pub fn with_derivatives(regex: String) -> Result<Regex, RegexError> {
         let scanner = Scanner::new(regex);
         let mut parser = Parser::new(scanner);
-        let nfa = parser.expr()?;
-        Ok(Regex { dfa: nfa.to_dfa() })
+        let mut node = parser.parse()?;
+        let unsupported = |reason: String| RegexError::Unsupported {
+            engine: Engine::Dfa,
+            reason,
+        };
+        if node.has_looks() || node.has_backreferences() {
+            return Err(unsupported(
+                "assertions and backreferences are not supported with derivatives".to_string(),
+            ));
+        }
+        if node.has_lookarounds() {
+            node = lookaround::to_intersections(node).map_err(unsupported)?;
+        }
+        let dfa = DerivativeDFA::try_new(node, DfaLimits::default()).map_err(RegexError::TooBig)?;
+        Ok(Regex::from_dfa(dfa.to_dfa()))
     }
 
-    /// Tries to recognize the input string against the DFA
+    /// Uses an already compiled DFA, e.g. one loaded with `DFA::from_bytes`
+    pub fn from_dfa(dfa: DFA) -> Regex {
+        Regex {
+            matcher: Matcher::Dfa {
+                dfa: Box::new(dfa),
+                reverse: None,
+            },
+            kind: MatchKind::LeftmostLongest,
+            prefilter: None,
+        }
+    }
+
+    /// The engine that was picked for the regex, never `Engine::Auto`
+    pub fn engine(&self) -> Engine {
+        match self.matcher {
+            Matcher::Dfa { .. }
+            | Matcher::Forward { .. }
+            | Matcher::Pike { dfa: Some(_), .. }
+            | Matcher::Hybrid { .. } => Engine::Dfa,
+            Matcher::Nfa(_) | Matcher::Pike { dfa: None, .. } => Engine::Nfa,
+            Matcher::Backtrack(_) => Engine::Backtrack,
+        }
+    }
+
+    /// The engine and the size of the DFA
+    pub fn statistics(&self) -> Statistics {
+        let dfa = self.dfa();
+        Statistics {
+            engine: self.engine(),
+            dfa_states: dfa.map_or(0, DFA::state_count),
+            byte_classes: dfa.map_or(0, DFA::class_count),
+        }
+    }
+
+    /// Which match the searches find
+    pub fn match_kind(&self) -> MatchKind {
+        self.kind
+    }
+
+    /// The DFA of the regex, if it uses one. With lookarounds the DFA only
+    /// matches whole inputs correctly.
+    pub fn dfa(&self) -> Option<&DFA> {
+        match &self.matcher {
+            Matcher::Dfa { dfa, .. }
+            | Matcher::Forward { dfa, .. }
+            | Matcher::Pike { dfa: Some(dfa), .. }
+            | Matcher::Hybrid { dfa, .. } => Some(dfa),
+            Matcher::Nfa(_) | Matcher::Pike { dfa: None, .. } | Matcher::Backtrack(_) => None,
+        }
+    }
+
+    /// Tries to recognize the UTF-8 encoding of the input string against the DFA
     pub fn matches(&self, string: String) -> Result<bool, String> {
-        if !string.is_ascii() {
-            return Err("Input string was not ascii".to_string());
+        self.matches_bytes(string.as_bytes())
+    }
+
+    /// Checks if the whole input matches. The input can be any bytes, it does
+    /// not have to be valid UTF-8. Fails only if backtracking runs out of
+    /// steps.
+    pub fn matches_bytes(&self, bytes: &[u8]) -> Result<bool, String> {
+        match &self.matcher {
+            Matcher::Dfa { dfa, .. }
+            | Matcher::Forward { dfa, .. }
+            | Matcher::Pike { dfa: Some(dfa), .. }
+            | Matcher::Hybrid { dfa, .. } => Ok(dfa.recognizer().accepts(bytes)),
+            Matcher::Nfa(simulation) => Ok(simulation.matches(bytes)),
+            Matcher::Pike { dfa: None, vm } => Ok(vm.matches(bytes)),
+            Matcher::Backtrack(backtracker) => backtracker.matches(bytes),
+        }
+    }
+
+    /// Finds the leftmost match in the bytes and returns its start and end.
+    /// Of the matches starting at the same position, the one given by the
+    /// match kind is returned.
+    pub fn find_bytes(&self, bytes: &[u8]) -> Result<Option<(usize, usize)>, String> {
+        match &self.matcher {
+            Matcher::Dfa {
+                dfa,
+                reverse: Some(reverse),
+            } => Ok(find_two_pass(dfa, reverse, bytes, self.prefilter.as_ref())),
+            Matcher::Dfa { dfa, reverse: None } => Ok(find(dfa, bytes, self.prefilter.as_ref())),
+            Matcher::Forward { dfa, simulation } => {
+                Ok(simulation.find_start(bytes).and_then(|start| {
+                    let dead: Vec<bool> = (0..dfa.state_count())
+                        .map(|state| dfa.is_dead_state(state))
+                        .collect();
+                    longest_match(dfa, bytes, start, &dead).map(|end| (start, end))
+                }))
+            }
+            Matcher::Nfa(simulation) => Ok(simulation.find(bytes)),
+            Matcher::Pike { vm, .. } => Ok(vm.find(bytes)),
+            Matcher::Hybrid {
+                vm, lookarounds, ..
+            } => {
+                let tables: Vec<Vec<bool>> = lookarounds
+                    .iter()
+                    .map(|lookaround| lookaround.table(bytes))
+                    .collect();
+                let longest = self.kind == MatchKind::LeftmostLongest;
+                Ok(vm.find_with(bytes, longest, &tables))
+            }
+            Matcher::Backtrack(backtracker) => backtracker.find(bytes, self.kind),
+        }
+    }
+}
+
+/// Tries the DFA from every position where a match can start, which is
+/// every position unless the prefilter can skip some of them
+fn find(dfa: &DFA, bytes: &[u8], prefilter: Option<&Prefilter>) -> Option<(usize, usize)> {
+    let dead: Vec<bool> = (0..dfa.state_count())
+        .map(|state| dfa.is_dead_state(state))
+        .collect();
+    let mut starts: Box<dyn Iterator<Item = usize>> = match prefilter {
+        Some(prefilter) => Box::new(prefilter.candidates(bytes)),
+        None => Box::new(0..=bytes.len()),
+    };
+    starts.find_map(|start| longest_match(dfa, bytes, start, &dead).map(|end| (start, end)))
+}
+
+/// Finds the leftmost-longest match in two linear passes. The reverse DFA
+/// is unanchored, so reading the input backwards from the end it accepts at
+/// every position where some match starts, and the last of them is the
+/// leftmost start. The forward DFA then finds the end of the longest match
+/// from there.
+fn find_two_pass(
+    dfa: &DFA,
+    reverse: &DFA,
+    bytes: &[u8],
+    prefilter: Option<&Prefilter>,
+) -> Option<(usize, usize)> {
+    // No match starts before the first candidate
+    let first = match prefilter {
+        Some(prefilter) => prefilter.candidates(bytes).next()?,
+        None => 0,
+    };
+    // Backwards, the byte after a position is the one read before it
+    let mut state = reverse.start_state_after(None);
+    let mut start = None;
+    for at in (first..=bytes.len()).rev() {
+        let next = at.checked_sub(1).map(|i| bytes[i]);
+        if reverse.accepts_before(state, next) {
+            start = Some(at);
+        }
+        if at == first {
+            break;
         }
-        let mut recognizer = self.dfa.recognizer();
-        Ok(recognizer.accepts(string.as_bytes()))
+        state = reverse.get_transition(state, bytes[at - 1]);
     }
+    let start = start?;
+    let dead: Vec<bool> = (0..dfa.state_count())
+        .map(|state| dfa.is_dead_state(state))
+        .collect();
+    longest_match(dfa, bytes, start, &dead).map(|end| (start, end))
+}
+
+/// The end of the longest match starting at the position. The bytes around
+/// the match are given to the DFA for the assertions. Stops as soon as the
+/// DFA gets into a dead state.
+fn longest_match(dfa: &DFA, bytes: &[u8], start: usize, dead: &[bool]) -> Option<usize> {
+    let previous = start.checked_sub(1).map(|i| bytes[i]);
+    let mut state = dfa.start_state_after(previous);
+    let mut longest = None;
+    for end in start..=bytes.len() {
+        if dfa.accepts_before(state, bytes.get(end).cloned()) {
+            longest = Some(end);
+        }
+        if end == bytes.len() || dead[state] {
+            break;
+        }
+        state = dfa.get_transition(state, bytes[end]);
+    }
+    longest
 }
 
 //grcov-excl-start
@@ -43,7 +591,7 @@ mod tests {
 
     #[test]
     fn fails2() {
-        let regex = "¥";
+        let regex = "¥\\p{Xx}";
         let dfa = Regex::new(regex.to_string());
         assert!(!dfa.is_ok());
     }
@@ -53,7 +601,599 @@ mod tests {
         let regex = "asd";
         let dfa = Regex::new(regex.to_string()).unwrap();
         let m = dfa.matches("¥".to_string());
-        assert!(!m.is_ok());
+        assert!(!m.unwrap());
+    }
+
+    #[test]
+    fn unicode() {
+        let regex = "(Jyväskylä|Åbo|Москва|東京)(!|¥)*";
+        let dfa = Regex::new(regex.to_string()).unwrap();
+        let to_accept = vec!["Jyväskylä", "Åbo!¥", "Москва", "東京¥¥"];
+        let to_reject = vec!["Jyvaskyla", "Abo", "東", "Москва?", "¥"];
+        for s in to_accept {
+            assert!(dfa.matches(s.to_string()).unwrap());
+        }
+        for s in to_reject {
+            assert!(!dfa.matches(s.to_string()).unwrap());
+        }
+    }
+
+    #[test]
+    fn unicode_dot() {
+        let dfa = Regex::new("a.b".to_string()).unwrap();
+        for s in ["aäb", "a東b", "a😀b", "axb"] {
+            assert!(dfa.matches(s.to_string()).unwrap());
+        }
+        assert!(!dfa.matches("aääb".to_string()).unwrap());
+    }
+
+    #[test]
+    fn matches_bytes() {
+        let regex = Regex::new("\\x00\\xff(\\x80|\\r\\n)*\\0".to_string()).unwrap();
+        assert!(regex
+            .matches_bytes(&[0x00, 0xff, 0x80, b'\r', b'\n', 0x80, 0x00])
+            .unwrap());
+        assert!(regex.matches_bytes(&[0x00, 0xff, 0x00]).unwrap());
+        assert!(!regex.matches_bytes(&[0x00, 0xff, b'\r', 0x00]).unwrap());
+        assert!(!regex.matches_bytes(&[0x00, 0xfe, 0x00]).unwrap());
+
+        // A raw byte is not the UTF-8 encoding of the code point
+        let regex = Regex::new("\\xe4".to_string()).unwrap();
+        assert!(regex.matches_bytes(&[0xe4]).unwrap());
+        assert!(!regex.matches("ä".to_string()).unwrap());
+
+        let regex = Regex::new("(?i)\\x41\\t".to_string()).unwrap();
+        assert!(regex.matches("a\t".to_string()).unwrap());
+    }
+
+    #[test]
+    fn find_bytes() {
+        let regex = Regex::new("\\x7e(\\x01|\\x02)*\\x7e".to_string()).unwrap();
+        let frame = [0x00, 0x7e, 0x7e, 0x01, 0x02, 0x7e, 0x7e];
+        assert_eq!(regex.find_bytes(&frame).unwrap(), Some((1, 3)));
+        assert_eq!(regex.find_bytes(&frame[2..]).unwrap(), Some((0, 4)));
+        assert_eq!(regex.find_bytes(&[0x7e, 0x01]).unwrap(), None);
+
+        let regex = Regex::new("a*".to_string()).unwrap();
+        assert_eq!(regex.find_bytes(b"baa").unwrap(), Some((0, 0)));
+        assert_eq!(regex.find_bytes(b"aab").unwrap(), Some((0, 2)));
+        assert_eq!(regex.find_bytes(b"").unwrap(), Some((0, 0)));
+    }
+
+    #[test]
+    fn anchors() {
+        let regex = Regex::new("^ab*$".to_string()).unwrap();
+        assert!(regex.matches("abb".to_string()).unwrap());
+        assert_eq!(regex.find_bytes(b"abb").unwrap(), Some((0, 3)));
+        assert_eq!(regex.find_bytes(b"xabb").unwrap(), None);
+        assert_eq!(regex.find_bytes(b"abb\n").unwrap(), None);
+
+        // '^' can not match after the first byte
+        let regex = Regex::new("a^b".to_string()).unwrap();
+        assert!(!regex.matches("ab".to_string()).unwrap());
+
+        let regex = Regex::new("b$|c".to_string()).unwrap();
+        assert_eq!(regex.find_bytes(b"abcb").unwrap(), Some((2, 3)));
+    }
+
+    #[test]
+    fn multi_line_anchors() {
+        let regex = Regex::new("(?m)^b*$".to_string()).unwrap();
+        assert_eq!(regex.find_bytes(b"a\nbb\nc").unwrap(), Some((2, 4)));
+        let regex = Regex::new("(?m)a$\n^b".to_string()).unwrap();
+        assert!(regex.matches("a\nb".to_string()).unwrap());
+        let regex = RegexBuilder::new("a$".to_string())
+            .multi_line(true)
+            .build()
+            .unwrap();
+        assert_eq!(regex.find_bytes(b"ba\nb").unwrap(), Some((1, 2)));
+        let regex = Regex::new("a$".to_string()).unwrap();
+        assert_eq!(regex.find_bytes(b"ba\nb").unwrap(), None);
+    }
+
+    #[test]
+    fn builder_options() {
+        let mut builder = RegexBuilder::new("<A.*?>$".to_string());
+        builder
+            .case_insensitive(true)
+            .dot_matches_new_line(true)
+            .multi_line(true)
+            .match_kind(MatchKind::LeftmostFirst)
+            .construction(Construction::Glushkov)
+            .dfa_state_limit(1_000);
+        let regex = builder.build().unwrap();
+        assert_eq!(regex.match_kind(), MatchKind::LeftmostFirst);
+        assert_eq!(regex.find_bytes(b"<a>\n<b>\n").unwrap(), Some((0, 3)));
+
+        // The options stay in the builder, so it can be changed and reused
+        let regex = builder
+            .match_kind(MatchKind::LeftmostLongest)
+            .build()
+            .unwrap();
+        assert_eq!(regex.find_bytes(b"<a>\n<b>\n").unwrap(), Some((0, 7)));
+        let regex = builder.engine(Engine::Nfa).build().unwrap();
+        assert_eq!(regex.engine(), Engine::Nfa);
+        assert_eq!(regex.find_bytes(b"<a>\n<b>\n").unwrap(), Some((0, 7)));
+    }
+
+    #[test]
+    fn word_boundaries() {
+        let regex = Regex::new("\\bcat\\b".to_string()).unwrap();
+        assert_eq!(regex.find_bytes(b"concat cat").unwrap(), Some((7, 10)));
+        assert_eq!(regex.find_bytes(b"cats").unwrap(), None);
+        assert!(regex.matches("cat".to_string()).unwrap());
+
+        let regex = Regex::new("\\Bcat".to_string()).unwrap();
+        assert_eq!(regex.find_bytes(b"cat concat").unwrap(), Some((7, 10)));
+        assert!(!regex.matches("cat".to_string()).unwrap());
+
+        let regex = Regex::new("a\\b( |b)".to_string()).unwrap();
+        assert!(regex.matches("a ".to_string()).unwrap());
+        assert!(!regex.matches("ab".to_string()).unwrap());
+    }
+
+    #[test]
+    fn assertions_survive_minimization_and_files() {
+        let regex = Regex::new("(?m)\\bab*$".to_string()).unwrap();
+        let dfa = DFA::from_bytes(&regex.dfa().unwrap().minimize().to_bytes()).unwrap();
+        let loaded = Regex::from_dfa(dfa);
+        for input in [&b"ab"[..], b"x ab\n", b"xab", b"ab c", b"abb"] {
+            assert_eq!(
+                loaded.find_bytes(input).unwrap(),
+                regex.find_bytes(input).unwrap()
+            );
+        }
+        assert!(Regex::with_derivatives("^a".to_string()).is_err());
+    }
+
+    #[test]
+    fn backreferences() {
+        let regex = Regex::new("(a|b)*=\\1".to_string()).unwrap();
+        assert_eq!(regex.engine(), Engine::Backtrack);
+        assert!(regex.dfa().is_none());
+        assert!(regex.matches("aab=b".to_string()).unwrap());
+        assert!(!regex.matches("aab=a".to_string()).unwrap());
+        assert_eq!(regex.find_bytes(b"xa=a").unwrap(), Some((1, 4)));
+
+        let dfa = RegexBuilder::new("(a)\\1".to_string())
+            .engine(Engine::Dfa)
+            .build();
+        assert!(dfa.is_err());
+        assert!(Regex::new("(a)\\2".to_string()).is_err());
+    }
+
+    #[test]
+    fn engine_selection() {
+        let regex = Regex::new("(a|b)*c".to_string()).unwrap();
+        assert_eq!(regex.engine(), Engine::Dfa);
+        let regex = RegexBuilder::new("(a|b)*c".to_string())
+            .engine(Engine::Backtrack)
+            .build()
+            .unwrap();
+        assert_eq!(regex.engine(), Engine::Backtrack);
+        assert!(regex.matches("abac".to_string()).unwrap());
+    }
+
+    #[test]
+    fn step_budget() {
+        let regex = RegexBuilder::new("((a*)*)\\1b".to_string())
+            .step_budget(10_000)
+            .build()
+            .unwrap();
+        assert!(regex.matches("a".repeat(30)).is_err());
+        assert!(regex.matches("aab".to_string()).is_ok());
+    }
+
+    #[test]
+    fn lookarounds() {
+        let regex = Regex::new("(a|b)*(?<=a)b(?!a)(a|b)*".to_string()).unwrap();
+        assert_eq!(regex.engine(), Engine::Dfa);
+        assert!(regex.matches("abb".to_string()).unwrap());
+        assert!(!regex.matches("bba".to_string()).unwrap());
+        assert!(!regex.matches("aba".to_string()).unwrap());
+        // The lookahead sees past the end of the match
+        let regex = Regex::new("a(?=b)".to_string()).unwrap();
+        assert!(!regex.matches("ab".to_string()).unwrap());
+        assert_eq!(regex.find_bytes(b"aab").unwrap(), Some((1, 2)));
+        let regex = Regex::with_derivatives("(?!a)(a|b)*".to_string()).unwrap();
+        assert!(regex.matches("ba".to_string()).unwrap());
+        assert!(!regex.matches("ab".to_string()).unwrap());
+    }
+
+    #[test]
+    fn lookarounds_in_long_inputs() {
+        let haystack = "a".repeat(100_000) + "b";
+        for kind in [MatchKind::LeftmostLongest, MatchKind::LeftmostFirst] {
+            let regex = RegexBuilder::new("a*(?=b)".to_string())
+                .match_kind(kind)
+                .build()
+                .unwrap();
+            assert_eq!(regex.engine(), Engine::Dfa);
+            assert_eq!(
+                regex.find_bytes(haystack.as_bytes()).unwrap(),
+                Some((0, 100_000))
+            );
+            let regex = RegexBuilder::new("(?<=a)b|a(?!a)".to_string())
+                .match_kind(kind)
+                .build()
+                .unwrap();
+            assert_eq!(
+                regex.find_bytes(haystack.as_bytes()).unwrap(),
+                Some((99_999, 100_000))
+            );
+        }
+        let regex = Regex::new("a*(?=b)".to_string()).unwrap();
+        assert_eq!(regex.find_bytes("a".repeat(10_000).as_bytes()), Ok(None));
+    }
+
+    #[test]
+    fn long_regex_with_lookarounds() {
+        let literal = "a".repeat(100_000);
+        let regex = Regex::new(literal.clone() + "(?=b)").unwrap();
+        let haystack = literal.clone() + "b";
+        assert_eq!(
+            regex.find_bytes(haystack.as_bytes()),
+            Ok(Some((0, 100_000)))
+        );
+        assert_eq!(regex.find_bytes(b"aab"), Ok(None));
+        assert_eq!(regex.matches(literal), Ok(false));
+    }
+
+    #[test]
+    fn lookarounds_find_the_same_matches() {
+        let mut rng = rand::thread_rng();
+        let patterns = [
+            "a*(?=b)",
+            "(?<=a|bb)(a|b)+",
+            "(a|b)*?(?!a)",
+            "(?<!b)a+(?=c)|b(?!c)",
+            "(a|ab)(?=b*c)(b|c)*",
+        ];
+        for pattern in patterns {
+            for kind in [MatchKind::LeftmostLongest, MatchKind::LeftmostFirst] {
+                let build = |engine| {
+                    RegexBuilder::new(pattern.to_string())
+                        .engine(engine)
+                        .match_kind(kind)
+                        .build()
+                        .unwrap()
+                };
+                let (dfa, backtracker) = (build(Engine::Dfa), build(Engine::Backtrack));
+                for _ in 0..100 {
+                    let haystack: Vec<u8> = (0..rng.gen_range(0..12))
+                        .map(|_| b"abc"[rng.gen_range(0..3)])
+                        .collect();
+                    assert_eq!(
+                        dfa.find_bytes(&haystack),
+                        backtracker.find_bytes(&haystack),
+                        "{} in {:?}",
+                        pattern,
+                        String::from_utf8_lossy(&haystack)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn match_kinds() {
+        let first = |regex: &str, engine| {
+            RegexBuilder::new(regex.to_string())
+                .match_kind(MatchKind::LeftmostFirst)
+                .engine(engine)
+                .build()
+                .unwrap()
+        };
+        for engine in [Engine::Dfa, Engine::Backtrack] {
+            let regex = first("<.*?>", engine);
+            assert_eq!(regex.match_kind(), MatchKind::LeftmostFirst);
+            assert_eq!(regex.find_bytes(b"x<a><b>").unwrap(), Some((1, 4)));
+            assert!(regex.matches("<a><b>".to_string()).unwrap());
+            let regex = first("a|ab", engine);
+            assert_eq!(regex.find_bytes(b"ab").unwrap(), Some((0, 1)));
+            let regex = first("b+?|c", engine);
+            assert_eq!(regex.find_bytes(b"abbc").unwrap(), Some((1, 2)));
+        }
+        // Leftmost-longest ignores the laziness
+        let regex = Regex::new("<.*?>".to_string()).unwrap();
+        assert_eq!(regex.match_kind(), MatchKind::LeftmostLongest);
+        assert_eq!(regex.find_bytes(b"x<a><b>").unwrap(), Some((1, 7)));
+        let regex = Regex::new("ab?c+".to_string()).unwrap();
+        assert!(regex.matches("acc".to_string()).unwrap());
+        assert!(!regex.matches("ab".to_string()).unwrap());
+        // Lookarounds are searched with the DFAs of their operands
+        let regex = first("a+?(?=b)", Engine::Auto);
+        assert_eq!(regex.find_bytes(b"aab").unwrap(), Some((0, 2)));
+    }
+
+    #[test]
+    fn prefilter_finds_the_same_matches() {
+        let mut rng = rand::thread_rng();
+        for pattern in ["E:(a|b)*", "(a|b)*ab", "a(b|c)*d", "x*(ab|cb)y", "\\bab"] {
+            let regex = Regex::new(pattern.to_string()).unwrap();
+            // A regex from a DFA has no literals to skip to
+            let plain = Regex::from_dfa(regex.dfa().unwrap().minimize());
+            for _ in 0..200 {
+                let haystack: Vec<u8> = (0..rng.gen_range(0..20))
+                    .map(|_| b"abcdxyE: "[rng.gen_range(0..9)])
+                    .collect();
+                assert_eq!(
+                    regex.find_bytes(&haystack).unwrap(),
+                    plain.find_bytes(&haystack).unwrap()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn reverse_dfa_finds_the_same_matches() {
+        let mut rng = rand::thread_rng();
+        let patterns = [
+            "a*",
+            "(a|ab)(c|bcd)",
+            "^ab|b$",
+            "(?m)^b*$",
+            "\\ba+\\B",
+            "(a|b)*c(a|b)*",
+        ];
+        for pattern in patterns {
+            let regex = Regex::new(pattern.to_string()).unwrap();
+            let forward = Regex::from_dfa(regex.dfa().unwrap().minimize());
+            for _ in 0..200 {
+                let haystack: Vec<u8> = (0..rng.gen_range(0..20))
+                    .map(|_| b"abcd\n "[rng.gen_range(0..6)])
+                    .collect();
+                assert_eq!(
+                    regex.find_bytes(&haystack).unwrap(),
+                    forward.find_bytes(&haystack).unwrap(),
+                    "{} in {:?}",
+                    pattern,
+                    String::from_utf8_lossy(&haystack)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn statistics() {
+        let statistics = Regex::new("(a|b)*c".to_string()).unwrap().statistics();
+        assert_eq!(statistics.engine, Engine::Dfa);
+        // 'a' and 'b' merged into one class by simplifying, 'c' and the rest
+        assert_eq!(statistics.byte_classes, 3);
+        assert!(statistics.dfa_states > 0);
+        let statistics = Regex::new("a\\b".to_string()).unwrap().statistics();
+        // 'a', the other word bytes, a newline and the rest
+        assert_eq!(statistics.byte_classes, 4);
+        let statistics = Regex::new("(a)\\1".to_string()).unwrap().statistics();
+        assert_eq!(statistics.engine, Engine::Backtrack);
+        assert_eq!(statistics.byte_classes, 0);
+    }
+
+    #[test]
+    fn size_limits() {
+        // The DFA needs a state for every combination of the last 13 bytes
+        let pattern = format!("(a|b)*a{}", "(a|b)".repeat(12));
+        let limited = |engine| {
+            RegexBuilder::new(pattern.clone())
+                .engine(engine)
+                .dfa_state_limit(1_000)
+                .build()
+        };
+        let simulated = limited(Engine::Auto).unwrap();
+        assert_eq!(simulated.engine(), Engine::Nfa);
+        assert_eq!(simulated.statistics().dfa_states, 0);
+        assert!(matches!(limited(Engine::Dfa), Err(RegexError::TooBig(_))));
+
+        let regex = Regex::new(pattern.clone()).unwrap();
+        assert_eq!(regex.engine(), Engine::Dfa);
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let haystack: Vec<u8> = (0..rng.gen_range(0..30))
+                .map(|_| b"abc"[rng.gen_range(0..3)])
+                .collect();
+            assert_eq!(
+                simulated.matches_bytes(&haystack),
+                regex.matches_bytes(&haystack)
+            );
+            assert_eq!(simulated.find_bytes(&haystack), regex.find_bytes(&haystack));
+        }
+
+        let regex = RegexBuilder::new(pattern.clone())
+            .dfa_size_limit(10_000)
+            .build()
+            .unwrap();
+        assert_eq!(regex.engine(), Engine::Nfa);
+        let error = RegexBuilder::new(pattern.clone())
+            .nfa_size_limit(10)
+            .build();
+        assert!(matches!(error, Err(RegexError::TooBig(_))));
+
+        // Only the reverse DFA needs a state for every combination
+        let pattern = format!("{}a(a|b)*", "(a|b)".repeat(12));
+        let forward = RegexBuilder::new(pattern.clone())
+            .dfa_state_limit(1_000)
+            .build()
+            .unwrap();
+        assert_eq!(forward.engine(), Engine::Dfa);
+        assert!(forward.dfa().is_some());
+        let regex = Regex::new(pattern.clone()).unwrap();
+        for _ in 0..100 {
+            let haystack: Vec<u8> = (0..rng.gen_range(0..30))
+                .map(|_| b"abc"[rng.gen_range(0..3)])
+                .collect();
+            assert_eq!(
+                forward.matches_bytes(&haystack),
+                regex.matches_bytes(&haystack)
+            );
+            assert_eq!(forward.find_bytes(&haystack), regex.find_bytes(&haystack));
+        }
+
+        // Lookarounds fall back to backtracking
+        let regex = RegexBuilder::new("a(?=b)".to_string())
+            .dfa_state_limit(1)
+            .build()
+            .unwrap();
+        assert_eq!(regex.engine(), Engine::Backtrack);
+        // So do the intersections that are too big to determinize
+        let pattern = format!("(?=(a|b)*a{})(a|b)*", "(a|b)".repeat(16));
+        let limited = |engine| {
+            RegexBuilder::new(pattern.clone())
+                .engine(engine)
+                .dfa_state_limit(1_000)
+                .build()
+        };
+        assert_eq!(limited(Engine::Auto).unwrap().engine(), Engine::Backtrack);
+        assert!(matches!(limited(Engine::Dfa), Err(RegexError::TooBig(_))));
+    }
+
+    #[test]
+    fn nfa_engine_finds_the_same_matches() {
+        let mut rng = rand::thread_rng();
+        let patterns = [
+            "(a|ab)(c|bcd)",
+            "(?m)^b*$",
+            "\\ba+\\B",
+            "(a|b)*c(a|b)*",
+            "ä+|.b",
+        ];
+        for pattern in patterns {
+            for construction in [Construction::Thompson, Construction::Glushkov] {
+                let build = |engine| {
+                    RegexBuilder::new(pattern.to_string())
+                        .engine(engine)
+                        .construction(construction)
+                        .build()
+                        .unwrap()
+                };
+                let (dfa, nfa) = (build(Engine::Dfa), build(Engine::Nfa));
+                for _ in 0..100 {
+                    let haystack: Vec<u8> = (0..rng.gen_range(0..20))
+                        .map(|_| "abcdä\n ".as_bytes()[rng.gen_range(0..8)])
+                        .collect();
+                    assert_eq!(dfa.find_bytes(&haystack), nfa.find_bytes(&haystack));
+                    assert_eq!(dfa.matches_bytes(&haystack), nfa.matches_bytes(&haystack));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nfa_engine() {
+        let nfa = |pattern: &str, kind| {
+            RegexBuilder::new(pattern.to_string())
+                .engine(Engine::Nfa)
+                .match_kind(kind)
+                .build()
+                .unwrap()
+        };
+        let regex = nfa("a|ab", MatchKind::LeftmostLongest);
+        assert_eq!(regex.engine(), Engine::Nfa);
+        assert_eq!(regex.find_bytes(b"xab"), Ok(Some((1, 3))));
+        assert!(regex.matches_bytes(b"ab").unwrap());
+        assert!(!regex.matches_bytes(b"abb").unwrap());
+        let regex = nfa("a|ab", MatchKind::LeftmostFirst);
+        assert_eq!(regex.engine(), Engine::Nfa);
+        assert_eq!(regex.find_bytes(b"xab"), Ok(Some((1, 2))));
+        assert!(regex.matches_bytes(b"ab").unwrap());
+        assert!(nfa("\\bab$", MatchKind::LeftmostLongest)
+            .matches_bytes(b"ab")
+            .unwrap());
+
+        let error = RegexBuilder::new("a(?=b)".to_string())
+            .engine(Engine::Nfa)
+            .build();
+        assert!(matches!(
+            error,
+            Err(RegexError::Unsupported {
+                engine: Engine::Nfa,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn long_patterns() {
+        let regex = Regex::new("ab".repeat(2_500)).unwrap();
+        assert!(regex.matches("ab".repeat(2_500)).unwrap());
+        assert!(!regex.matches("ab".repeat(2_499)).unwrap());
+        assert!(Regex::new(format!("({}", "a".repeat(50_000))).is_err());
+
+        let nested = |depth| format!("{}a{}", "(".repeat(depth), ")".repeat(depth));
+        let regex = Regex::new(nested(DEFAULT_NESTING_LIMIT)).unwrap();
+        assert!(regex.matches("a".to_string()).unwrap());
+        assert!(matches!(
+            Regex::new(nested(DEFAULT_NESTING_LIMIT + 1)),
+            Err(RegexError::Syntax(_))
+        ));
+        let error = RegexBuilder::new(nested(3)).nesting_limit(2).build();
+        assert!(error.is_err());
+
+        // '+' does not copy its operand, so nesting it stays small
+        let pluses = format!("{}a{}", "(".repeat(30), ")+".repeat(30));
+        for engine in [Engine::Dfa, Engine::Nfa, Engine::Backtrack] {
+            let regex = RegexBuilder::new(pluses.clone())
+                .engine(engine)
+                .build()
+                .unwrap();
+            assert!(regex.matches("aaa".to_string()).unwrap());
+            assert!(!regex.matches(String::new()).unwrap());
+        }
+    }
+
+    #[test]
+    fn unsupported_lookarounds() {
+        // Lookarounds inside '*' fall back to backtracking
+        let regex = Regex::new("((?=a)(a|b))*".to_string()).unwrap();
+        assert_eq!(regex.engine(), Engine::Backtrack);
+        assert!(regex.matches("aa".to_string()).unwrap());
+        assert!(!regex.matches("ab".to_string()).unwrap());
+
+        let error = RegexBuilder::new("((?=a)(a|b))*".to_string())
+            .engine(Engine::Dfa)
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error,
+            RegexError::Unsupported {
+                engine: Engine::Dfa,
+                ..
+            }
+        ));
+        let error = RegexBuilder::new("^(?=a)".to_string())
+            .engine(Engine::Dfa)
+            .build();
+        assert!(error.is_err());
+
+        // A lookahead before a lookbehind needs the lookbehind at the end
+        let regex = Regex::new("(?=a)[ab](?<=a)".to_string()).unwrap();
+        assert_eq!(regex.engine(), Engine::Dfa);
+        assert!(regex.matches("a".to_string()).unwrap());
+        assert_eq!(regex.find_bytes(b"bab"), Ok(Some((1, 2))));
+        let regex = Regex::new("(?=a)[ab](?<=a)b".to_string()).unwrap();
+        assert_eq!(regex.engine(), Engine::Backtrack);
+        assert!(regex.matches("ab".to_string()).unwrap());
+        assert!(matches!(
+            Regex::new("(?=a".to_string()),
+            Err(RegexError::Syntax(_))
+        ));
+    }
+
+    #[test]
+    fn unicode_properties() {
+        let dfa = Regex::new("\\p{Lu}\\pL*( \\p{Lu}\\pL*)*".to_string()).unwrap();
+        let to_accept = vec!["Äänekoski", "Östen Ärlig", "Ωmega", "Jyväskylä"];
+        let to_reject = vec!["äänekoski", "Östen ärlig", "Jyväskylä1", ""];
+        for s in to_accept {
+            assert!(dfa.matches(s.to_string()).unwrap());
+        }
+        for s in to_reject {
+            assert!(!dfa.matches(s.to_string()).unwrap());
+        }
+
+        let dfa = Regex::new("\\P{L}*".to_string()).unwrap();
+        assert!(dfa.matches("123 !?€".to_string()).unwrap());
+        assert!(!dfa.matches("12ä".to_string()).unwrap());
     }
 
     #[test]
@@ -163,6 +1303,78 @@ mod tests {
         }
     }
 
+    #[test]
+    fn dot() {
+        let dfa = Regex::new("a.c".to_string()).unwrap();
+        assert!(dfa.matches("abc".to_string()).unwrap());
+        assert!(dfa.matches("a.c".to_string()).unwrap());
+        assert!(!dfa.matches("a\nc".to_string()).unwrap());
+        assert!(!dfa.matches("ac".to_string()).unwrap());
+        let dfa = Regex::new("a\\.c".to_string()).unwrap();
+        assert!(!dfa.matches("abc".to_string()).unwrap());
+        assert!(dfa.matches("a.c".to_string()).unwrap());
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let dfa = RegexBuilder::new("ab(c|D)*".to_string())
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        let to_accept = vec!["ab", "AB", "aBcdCD", "Abdddd"];
+        let to_reject = vec!["a", "abe", "aab"];
+        for s in to_accept {
+            assert!(dfa.matches(s.to_string()).unwrap());
+        }
+        for s in to_reject {
+            assert!(!dfa.matches(s.to_string()).unwrap());
+        }
+    }
+
+    #[test]
+    fn inline_flags() {
+        let dfa = Regex::new("a(?i)b".to_string()).unwrap();
+        assert!(dfa.matches("aB".to_string()).unwrap());
+        assert!(!dfa.matches("AB".to_string()).unwrap());
+
+        let dfa = Regex::new("(?s)a.b".to_string()).unwrap();
+        assert!(dfa.matches("a\nb".to_string()).unwrap());
+
+        let dfa = Regex::new("(?x) a b # comment\n c".to_string()).unwrap();
+        assert!(dfa.matches("abc".to_string()).unwrap());
+        assert!(!dfa.matches("a b c".to_string()).unwrap());
+
+        let dfa = RegexBuilder::new("a(?-i)b".to_string())
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        assert!(dfa.matches("Ab".to_string()).unwrap());
+        assert!(!dfa.matches("AB".to_string()).unwrap());
+    }
+
+    #[test]
+    fn inline_flags_scope() {
+        // The flags end with the group they are set in
+        let dfa = Regex::new("((?i)a)a".to_string()).unwrap();
+        assert!(dfa.matches("Aa".to_string()).unwrap());
+        assert!(!dfa.matches("AA".to_string()).unwrap());
+
+        let dfa = Regex::new("(?i:a)a|b".to_string()).unwrap();
+        assert!(dfa.matches("Aa".to_string()).unwrap());
+        assert!(!dfa.matches("aA".to_string()).unwrap());
+        assert!(!dfa.matches("B".to_string()).unwrap());
+
+        let dfa = Regex::new("((?x) a b )c d".to_string()).unwrap();
+        assert!(dfa.matches("abc d".to_string()).unwrap());
+        assert!(!dfa.matches("abcd".to_string()).unwrap());
+    }
+
+    #[test]
+    fn unknown_flag() {
+        assert!(Regex::new("(?q)a".to_string()).is_err());
+        assert!(Regex::new("(?i".to_string()).is_err());
+    }
+
     #[test]
     fn random_integers() {
         let regex = "(0|(-|()*)(1|2|3|4|5|6|7|8|9)(0|1|2|3|4|5|6|7|8|9)*)";
@@ -1,28 +1,285 @@
 use crate::tokens::{Token, TokenType};
 
+/// The characters that a backslash makes literal, both outside and inside of
+/// a class. The space and '#' are here for the verbose mode.
+const META: &str = "\\|*+?.^$()[]{}-&~# ";
+
 #[derive(Debug)]
 pub struct Scanner {
     regex: String,
+    /// The length of the whole regex in bytes, for the positions in errors
+    length: usize,
+    /// In verbose mode whitespace and comments starting with '#' are skipped
+    pub verbose: bool,
+    /// In strict mode the escapes and class ranges that could be read in
+    /// more than one way are errors, see `Scanner::escape`
+    pub strict: bool,
+    /// Why the last `Error` token was returned
+    error: Option<String>,
 }
 
 impl Scanner {
     pub fn new(regex: String) -> Self {
         Scanner {
+            length: regex.len(),
             regex: regex.chars().rev().collect(),
+            verbose: false,
+            strict: false,
+            error: None,
         }
     }
 
+    /// The message of the last `Error` token
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
     pub fn get_next_token(&mut self) -> Token {
+        if self.verbose {
+            self.skip_whitespace();
+        }
+        let position = self.position();
         match self.regex.pop() {
             Some(c) => match c {
-                '\\' => Token::new(TokenType::Char, self.regex.pop().unwrap() as u8),
-                '|' => Token::new(TokenType::Union, c as u8),
-                '*' => Token::new(TokenType::Star, c as u8),
-                '(' => Token::new(TokenType::LeftParen, c as u8),
-                ')' => Token::new(TokenType::RightParen, c as u8),
-                _ => Token::new(TokenType::Char, c as u8),
+                '\\' => match self.escape(position, false) {
+                    Ok(token) => token,
+                    Err(error) => {
+                        self.error = Some(error);
+                        Token::new(TokenType::Error, '\\')
+                    }
+                },
+                '|' => Token::new(TokenType::Union, c),
+                '*' => Token::new(TokenType::Star, c),
+                '+' => Token::new(TokenType::Plus, c),
+                '?' => Token::new(TokenType::Question, c),
+                '.' => Token::new(TokenType::Dot, c),
+                '^' | '$' => Token::new(TokenType::Look, c),
+                '(' if self.regex.ends_with('?') => {
+                    self.regex.pop();
+                    Token::new(TokenType::FlagGroup, '?')
+                }
+                '(' => Token::new(TokenType::LeftParen, c),
+                '[' => Token::new(TokenType::LeftBracket, c),
+                ')' => Token::new(TokenType::RightParen, c),
+                _ => Token::new(TokenType::Char, c),
             },
-            None => Token::new(TokenType::EOF, 0xff),
+            None => Token::new(TokenType::EOF, '\u{ff}'),
+        }
+    }
+
+    /// Reads a bracketed class after the '[', up to and including the ']'.
+    /// Returns if the class is negated with '^', and its ranges, where a
+    /// single character is a range from itself to itself. A '-' is a
+    /// character when it is first or last, or next to a class escape such
+    /// as '\d'. The escapes are the same as outside of the class, and a raw
+    /// byte from '\xHH' is a `Byte` token.
+    pub fn class(&mut self) -> Result<(bool, Vec<(Token, Token)>), String> {
+        let negated = self.regex.ends_with('^');
+        if negated {
+            self.regex.pop();
+        }
+        let mut ranges = Vec::new();
+        loop {
+            let position = self.position();
+            let dash = self.regex.ends_with('-');
+            let start = match self.class_atom()? {
+                Some(start) => start,
+                None => break,
+            };
+            if self.strict && dash && !ranges.is_empty() && !self.regex.ends_with(']') {
+                return Err(format!(
+                    "Parsing error. Ambiguous '-' in a class at position {}, escape it.",
+                    position
+                ));
+            }
+            let mut next = self.regex.chars().rev();
+            if next.next() != Some('-') || matches!(next.next(), Some(']') | None) {
+                ranges.push((start.clone(), start));
+                continue;
+            }
+            let range = self.position();
+            self.regex.pop();
+            let end = match self.class_atom()? {
+                Some(end) => end,
+                None => unreachable!("the ']' was checked above"),
+            };
+            if start.type_ != TokenType::ClassEscape && end.type_ != TokenType::ClassEscape {
+                ranges.push((start, end));
+                continue;
+            }
+            if self.strict {
+                return Err(format!(
+                    "Parsing error. A class escape can not bound a range at position {}.",
+                    range
+                ));
+            }
+            let dash = Token::new(TokenType::Char, '-');
+            ranges.extend([(start.clone(), start), (dash.clone(), dash)]);
+            ranges.push((end.clone(), end));
+        }
+        Ok((negated, ranges))
+    }
+
+    /// Reads a character of a class, or nothing at the ']'
+    fn class_atom(&mut self) -> Result<Option<Token>, String> {
+        let unterminated = || "Parsing error. Unterminated class.".to_string();
+        let position = self.position();
+        match self.regex.pop().ok_or_else(unterminated)? {
+            ']' => Ok(None),
+            '\\' if self.regex.is_empty() => Err(unterminated()),
+            '\\' => self.escape(position, true).map(Some),
+            c => Ok(Some(Token::new(TokenType::Char, c))),
+        }
+    }
+
+    /// The byte offset of the next character in the regex
+    fn position(&self) -> usize {
+        self.length - self.regex.len()
+    }
+
+    /// Reads an escape after the backslash at the position:
+    ///
+    /// - '\n', '\t', '\r', '\a', '\f' and '\v' are control characters, and
+    ///   '\cX' is the control character of the letter X, e.g. '\cJ' is '\n'
+    /// - '\xHH' is a raw byte and '\x{H...}' a character by its code point
+    /// - '\o{O...}' and '\0OO' are bytes in octal, '\0' alone is the zero byte
+    /// - '\d', '\w' and '\s' are digits, word characters and whitespace, and
+    ///   '\D', '\W' and '\S' everything else
+    /// - a backslash before a metacharacter, such as '\*', makes it literal
+    ///
+    /// Outside of a class '\p', '\P', '\b', '\B', '\1' to '\9' and '\e' are
+    /// read as well. Other letters and digits are errors, and so is a
+    /// backslash at the end. Other punctuation is literal after a backslash,
+    /// except in strict mode, where it is an error like the forms that could
+    /// mean something else: '\x' with one digit, '\0' followed by an octal
+    /// digit and a backreference followed by a digit.
+    fn escape(&mut self, position: usize, in_class: bool) -> Result<Token, String> {
+        let error = |message: &str| format!("Parsing error. {} at position {}.", message, position);
+        let c = self
+            .regex
+            .pop()
+            .ok_or_else(|| error("Trailing backslash"))?;
+        let literal = |c| Ok(Token::new(TokenType::Char, c));
+        match c {
+            'n' => literal('\n'),
+            't' => literal('\t'),
+            'r' => literal('\r'),
+            'a' => literal('\u{7}'),
+            'f' => literal('\u{c}'),
+            'v' => literal('\u{b}'),
+            'c' => match self.regex.pop() {
+                Some(letter) if letter.is_ascii_alphabetic() => {
+                    literal(char::from(letter as u8 & 0x1f))
+                }
+                _ => Err(error("\\c needs a letter")),
+            },
+            'x' if self.regex.ends_with('{') => {
+                let code_point = self
+                    .braced(16)
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| error("Invalid \\x{...} escape"))?;
+                literal(code_point)
+            }
+            'x' => match self.hex_byte() {
+                Some(byte) => Ok(Token::new(TokenType::Byte, char::from(byte))),
+                None => match self.digits(16, 1) {
+                    Some(_) if self.strict => Err(error("\\x needs two hex digits")),
+                    Some(byte) => Ok(Token::new(TokenType::Byte, char::from(byte as u8))),
+                    None => Err(error("\\x needs hex digits")),
+                },
+            },
+            'o' => match self.braced(8) {
+                Some(byte) if byte <= 0xff => {
+                    Ok(Token::new(TokenType::Byte, char::from(byte as u8)))
+                }
+                _ => Err(error("Invalid \\o{...} escape")),
+            },
+            '0' => match self.digits(8, 2) {
+                Some(_) if self.strict => Err(error("Ambiguous octal escape, use \\o{...}")),
+                Some(byte) => Ok(Token::new(TokenType::Byte, char::from(byte as u8))),
+                None => literal('\0'),
+            },
+            'd' | 'D' | 'w' | 'W' | 's' | 'S' => Ok(Token::new(TokenType::ClassEscape, c)),
+            'p' | 'P' | 'b' | 'B' | '1'..='9' | 'e' if in_class => Err(format!(
+                "Parsing error. \\{} can not be used in a class at position {}.",
+                c, position
+            )),
+            'p' | 'P' => Ok(Token::new(TokenType::Property, c)),
+            'b' | 'B' => Ok(Token::new(TokenType::Look, c)),
+            '1'..='9' if self.strict && self.regex.ends_with(|c: char| c.is_ascii_digit()) => {
+                Err(error("Ambiguous backreference followed by a digit"))
+            }
+            '1'..='9' => Ok(Token::new(TokenType::Backreference, c)),
+            'e' => Ok(Token::new(TokenType::Empty, 'e')),
+            c if META.contains(c) => literal(c),
+            c if c.is_ascii_punctuation() && !self.strict => literal(c),
+            c => Err(format!(
+                "Parsing error. Unknown escape \\{} at position {}.",
+                c, position
+            )),
+        }
+    }
+
+    /// Reads the two hexadecimal digits of '\xHH'. Nothing is consumed if
+    /// the digits are missing.
+    fn hex_byte(&mut self) -> Option<u8> {
+        let digits: String = self.regex.chars().rev().take(2).collect();
+        if digits.len() != 2 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        self.regex.truncate(self.regex.len() - 2);
+        u8::from_str_radix(&digits, 16).ok()
+    }
+
+    /// Reads at most `count` digits in the radix, nothing if there are none
+    fn digits(&mut self, radix: u32, count: usize) -> Option<u32> {
+        let mut value = None;
+        for _ in 0..count {
+            match self
+                .regex
+                .chars()
+                .next_back()
+                .and_then(|c| c.to_digit(radix))
+            {
+                Some(digit) => {
+                    self.regex.pop();
+                    value = Some(value.unwrap_or(0) * radix + digit);
+                }
+                None => break,
+            }
+        }
+        value
+    }
+
+    /// Reads the digits in braces, such as '{1F600}'
+    fn braced(&mut self, radix: u32) -> Option<u32> {
+        if self.regex.pop() != Some('{') {
+            return None;
+        }
+        let value = self.digits(radix, 8)?;
+        match self.regex.pop() {
+            Some('}') => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Skips whitespace and comments that last until the end of the line
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.regex.pop() {
+            match c {
+                ' ' | '\t' | '\n' | '\r' => continue,
+                '#' => {
+                    while let Some(c) = self.regex.pop() {
+                        if c == '\n' {
+                            break;
+                        }
+                    }
+                }
+                _ => {
+                    self.regex.push(c);
+                    break;
+                }
+            }
         }
     }
 }
@@ -36,10 +293,10 @@ mod tests {
         let regex = "abc";
         let mut scanner = Scanner::new(regex.to_string());
 
-        let token_a = Token::new(TokenType::Char, 'a' as u8);
-        let token_b = Token::new(TokenType::Char, 'b' as u8);
-        let token_c = Token::new(TokenType::Char, 'c' as u8);
-        let token_eof = Token::new(TokenType::EOF, 0xff);
+        let token_a = Token::new(TokenType::Char, 'a');
+        let token_b = Token::new(TokenType::Char, 'b');
+        let token_c = Token::new(TokenType::Char, 'c');
+        let token_eof = Token::new(TokenType::EOF, '\u{ff}');
 
         let result = scanner.get_next_token();
         assert_eq!(result, token_a);
@@ -58,10 +315,10 @@ mod tests {
     fn scanner_closure_tokens() {
         let regex = "a*b";
         let mut scanner = Scanner::new(regex.to_string());
-        let token_a = Token::new(TokenType::Char, 'a' as u8);
-        let token_star = Token::new(TokenType::Star, '*' as u8);
-        let token_b = Token::new(TokenType::Char, 'b' as u8);
-        let token_eof = Token::new(TokenType::EOF, 0xff);
+        let token_a = Token::new(TokenType::Char, 'a');
+        let token_star = Token::new(TokenType::Star, '*');
+        let token_b = Token::new(TokenType::Char, 'b');
+        let token_eof = Token::new(TokenType::EOF, '\u{ff}');
 
         let result = scanner.get_next_token();
         assert_eq!(result, token_a);
@@ -76,16 +333,213 @@ mod tests {
         assert_eq!(result, token_eof);
     }
 
+    #[test]
+    fn scanner_dot_and_flag_group_tokens() {
+        let mut scanner = Scanner::new("(?i).\\.".to_string());
+        assert_eq!(scanner.get_next_token().type_, TokenType::FlagGroup);
+        assert_eq!(scanner.get_next_token(), Token::new(TokenType::Char, 'i'));
+        assert_eq!(scanner.get_next_token().type_, TokenType::RightParen);
+        assert_eq!(scanner.get_next_token().type_, TokenType::Dot);
+        assert_eq!(scanner.get_next_token(), Token::new(TokenType::Char, '.'));
+        assert_eq!(scanner.get_next_token().type_, TokenType::EOF);
+    }
+
+    #[test]
+    fn scanner_unicode_and_property_tokens() {
+        let mut scanner = Scanner::new("ä\\pL\\P{Lu}".to_string());
+        assert_eq!(scanner.get_next_token(), Token::new(TokenType::Char, 'ä'));
+        assert_eq!(
+            scanner.get_next_token(),
+            Token::new(TokenType::Property, 'p')
+        );
+        assert_eq!(scanner.get_next_token(), Token::new(TokenType::Char, 'L'));
+        assert_eq!(
+            scanner.get_next_token(),
+            Token::new(TokenType::Property, 'P')
+        );
+        assert_eq!(scanner.get_next_token(), Token::new(TokenType::Char, '{'));
+    }
+
+    #[test]
+    fn scanner_escapes() {
+        let mut scanner = Scanner::new("\\x41\\xff\\0\\n\\t\\r\\xg".to_string());
+        assert_eq!(scanner.get_next_token(), Token::new(TokenType::Byte, 'A'));
+        assert_eq!(
+            scanner.get_next_token(),
+            Token::new(TokenType::Byte, '\u{ff}')
+        );
+        assert_eq!(scanner.get_next_token(), Token::new(TokenType::Char, '\0'));
+        assert_eq!(scanner.get_next_token(), Token::new(TokenType::Char, '\n'));
+        assert_eq!(scanner.get_next_token(), Token::new(TokenType::Char, '\t'));
+        assert_eq!(scanner.get_next_token(), Token::new(TokenType::Char, '\r'));
+        assert_eq!(scanner.get_next_token().type_, TokenType::Error);
+        assert_eq!(
+            scanner.error(),
+            Some("Parsing error. \\x needs hex digits at position 16.")
+        );
+
+        let mut scanner = Scanner::new("\\1\\9".to_string());
+        assert_eq!(
+            scanner.get_next_token(),
+            Token::new(TokenType::Backreference, '1')
+        );
+        assert_eq!(
+            scanner.get_next_token(),
+            Token::new(TokenType::Backreference, '9')
+        );
+        assert_eq!(scanner.get_next_token().type_, TokenType::EOF);
+    }
+
+    #[test]
+    fn scanner_escape_grammar() {
+        let mut scanner =
+            Scanner::new("\\a\\f\\v\\cJ\\x{e4}\\x{1F600}\\xA\\o{101}\\o{377}\\012\\08".to_string());
+        let char = |c| Token::new(TokenType::Char, c);
+        let byte = |b: u8| Token::new(TokenType::Byte, char::from(b));
+        let expected = [
+            char('\u{7}'),
+            char('\u{c}'),
+            char('\u{b}'),
+            char('\n'),
+            char('ä'),
+            char('😀'),
+            byte(0xa),
+            byte(b'A'),
+            byte(0xff),
+            byte(b'\n'),
+            char('\0'),
+            char('8'),
+        ];
+        for token in expected {
+            assert_eq!(scanner.get_next_token(), token);
+        }
+        let mut scanner = Scanner::new("\\d\\W\\s\\*\\{\\&\\ \\@\\e".to_string());
+        for letter in ['d', 'W', 's'] {
+            let token = Token::new(TokenType::ClassEscape, letter);
+            assert_eq!(scanner.get_next_token(), token);
+        }
+        for c in ['*', '{', '&', ' ', '@'] {
+            assert_eq!(scanner.get_next_token(), char(c));
+        }
+        assert_eq!(scanner.get_next_token().type_, TokenType::Empty);
+        assert_eq!(scanner.get_next_token().type_, TokenType::EOF);
+    }
+
+    #[test]
+    fn scanner_escape_errors() {
+        let errors = [
+            ("ab\\", "Trailing backslash at position 2"),
+            ("a\\q", "Unknown escape \\q at position 1"),
+            ("\\ä", "Unknown escape \\ä at position 0"),
+            ("ä\\c1", "\\c needs a letter at position 2"),
+            ("\\x{110000}", "Invalid \\x{...} escape at position 0"),
+            ("\\x{41", "Invalid \\x{...} escape at position 0"),
+            ("\\o{400}", "Invalid \\o{...} escape at position 0"),
+        ];
+        for (regex, message) in errors {
+            let mut scanner = Scanner::new(regex.to_string());
+            while !matches!(
+                scanner.get_next_token().type_,
+                TokenType::Error | TokenType::EOF
+            ) {}
+            let expected = format!("Parsing error. {}.", message);
+            assert_eq!(scanner.error(), Some(expected.as_str()), "regex {}", regex);
+        }
+        let mut scanner = Scanner::new("[\\b]".to_string());
+        scanner.get_next_token();
+        assert_eq!(
+            scanner.class(),
+            Err("Parsing error. \\b can not be used in a class at position 1.".to_string())
+        );
+    }
+
+    #[test]
+    fn scanner_strict() {
+        for regex in ["\\@", "\\xA", "\\01", "(a)\\10"] {
+            let mut scanner = Scanner::new(regex.to_string());
+            scanner.strict = true;
+            while !matches!(
+                scanner.get_next_token().type_,
+                TokenType::Error | TokenType::EOF
+            ) {}
+            assert!(scanner.error().is_some(), "regex {}", regex);
+        }
+        for (regex, strict) in [
+            ("[a-c-e]", false),
+            ("[\\d-z]", false),
+            ("[a-\\w]", false),
+            ("[-a-c-]", true),
+            ("[\\d\\-z]", true),
+        ] {
+            let mut scanner = Scanner::new(regex.to_string());
+            scanner.strict = true;
+            scanner.get_next_token();
+            assert_eq!(scanner.class().is_ok(), strict, "regex {}", regex);
+        }
+        let mut scanner = Scanner::new("[\\d-z]".to_string());
+        scanner.get_next_token();
+        let char = |c| Token::new(TokenType::Char, c);
+        let escape = Token::new(TokenType::ClassEscape, 'd');
+        let expected = vec![
+            (escape.clone(), escape),
+            (char('-'), char('-')),
+            (char('z'), char('z')),
+        ];
+        assert_eq!(scanner.class(), Ok((false, expected)));
+    }
+
+    #[test]
+    fn scanner_look_tokens() {
+        let mut scanner = Scanner::new("^\\b\\B$\\$".to_string());
+        for value in ['^', 'b', 'B', '$'] {
+            assert_eq!(scanner.get_next_token(), Token::new(TokenType::Look, value));
+        }
+        assert_eq!(scanner.get_next_token(), Token::new(TokenType::Char, '$'));
+    }
+
+    #[test]
+    fn scanner_class() {
+        let mut scanner = Scanner::new("[^a-c\\]-\\x80-][]".to_string());
+        assert_eq!(scanner.get_next_token().type_, TokenType::LeftBracket);
+        let (negated, ranges) = scanner.class().unwrap();
+        assert!(negated);
+        let char = |c| Token::new(TokenType::Char, c);
+        let expected = vec![
+            (char('a'), char('c')),
+            (char(']'), Token::new(TokenType::Byte, '\u{80}')),
+            (char('-'), char('-')),
+        ];
+        assert_eq!(ranges, expected);
+        assert_eq!(scanner.get_next_token().type_, TokenType::LeftBracket);
+        assert_eq!(scanner.class(), Ok((false, Vec::new())));
+        assert_eq!(scanner.get_next_token().type_, TokenType::EOF);
+
+        let mut scanner = Scanner::new("[a-".to_string());
+        scanner.get_next_token();
+        assert!(scanner.class().is_err());
+    }
+
+    #[test]
+    fn scanner_verbose() {
+        let mut scanner = Scanner::new(" a # comment\n\tb\\ c".to_string());
+        scanner.verbose = true;
+        assert_eq!(scanner.get_next_token(), Token::new(TokenType::Char, 'a'));
+        assert_eq!(scanner.get_next_token(), Token::new(TokenType::Char, 'b'));
+        assert_eq!(scanner.get_next_token(), Token::new(TokenType::Char, ' '));
+        assert_eq!(scanner.get_next_token(), Token::new(TokenType::Char, 'c'));
+        assert_eq!(scanner.get_next_token().type_, TokenType::EOF);
+    }
+
     #[test]
     fn scanner_group_and_union_tokens() {
         let regex = "a(b|a)";
         let mut scanner = Scanner::new(regex.to_string());
-        let token_a = Token::new(TokenType::Char, 'a' as u8);
-        let token_lparen = Token::new(TokenType::LeftParen, '(' as u8);
-        let token_b = Token::new(TokenType::Char, 'b' as u8);
-        let token_union = Token::new(TokenType::Union, '|' as u8);
-        let token_rparen = Token::new(TokenType::RightParen, ')' as u8);
-        let token_eof = Token::new(TokenType::EOF, 0xff);
+        let token_a = Token::new(TokenType::Char, 'a');
+        let token_lparen = Token::new(TokenType::LeftParen, '(');
+        let token_b = Token::new(TokenType::Char, 'b');
+        let token_union = Token::new(TokenType::Union, '|');
+        let token_rparen = Token::new(TokenType::RightParen, ')');
+        let token_eof = Token::new(TokenType::EOF, '\u{ff}');
 
         let result = scanner.get_next_token();
         assert_eq!(result, token_a);
@@ -0,0 +1,334 @@
+use crate::ast::Node;
+use std::collections::HashSet;
+
+/// An operator waits on the stack until its operands are simplified
+enum Step {
+    Simplify(Node),
+    /// The number of alternatives of the union
+    Union(usize),
+    Concat,
+    Star(bool),
+    Plus(bool),
+    And,
+    Not,
+    Lookahead(bool),
+    Lookbehind(bool),
+}
+
+impl Node {
+    /// Rewrites the node into a smaller one that matches the same language,
+    /// using the identities of regular algebra:
+    ///
+    /// - `a|a` is `a`, and the single characters and classes of a union are
+    ///   merged into one class
+    /// - `(a*)*`, `(a+)*`, `(a*)+`, `(a?)*` and `a*a*` are `a*`, and
+    ///   `(a*|b)*` is `(a|b)*`
+    /// - `()a` and `a()` are `a`, and `()*` is `()`
+    /// - `[]a`, `a[]`, `[]+` and `[]&a` are `[]`, `a|[]` is `a` and `[]*`
+    ///   is `()`
+    /// - `~~a` is `a` and `a&a` is `a`
+    ///
+    /// Only the language is kept, so the groups are dropped and the order
+    /// of the alternatives may change. The result is meant for building the
+    /// automata, the backtracking engine and the Pike VM need the original
+    /// node. The tree is walked with an explicit stack, so it can be
+    /// arbitrarily deep.
+    pub fn simplify(self) -> Node {
+        let mut steps = vec![Step::Simplify(self)];
+        let mut simplified: Vec<Node> = Vec::new();
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Simplify(mut node) => {
+                    match &mut node {
+                        Node::Union { .. } => {
+                            let alternatives = alternatives(node);
+                            steps.push(Step::Union(alternatives.len()));
+                            steps.extend(alternatives.into_iter().rev().map(Step::Simplify));
+                        }
+                        Node::Concat { left, right } => steps.extend([
+                            Step::Concat,
+                            Step::Simplify(right.take()),
+                            Step::Simplify(left.take()),
+                        ]),
+                        Node::And { left, right } => steps.extend([
+                            Step::And,
+                            Step::Simplify(right.take()),
+                            Step::Simplify(left.take()),
+                        ]),
+                        Node::Star { operand, greedy } => {
+                            steps.extend([Step::Star(*greedy), Step::Simplify(operand.take())])
+                        }
+                        Node::Plus { operand, greedy } => {
+                            steps.extend([Step::Plus(*greedy), Step::Simplify(operand.take())])
+                        }
+                        Node::Not { operand } => {
+                            steps.extend([Step::Not, Step::Simplify(operand.take())])
+                        }
+                        Node::Lookahead { negated, operand } => steps
+                            .extend([Step::Lookahead(*negated), Step::Simplify(operand.take())]),
+                        Node::Lookbehind { negated, operand } => steps
+                            .extend([Step::Lookbehind(*negated), Step::Simplify(operand.take())]),
+                        Node::Capture { operand, .. } => steps.push(Step::Simplify(operand.take())),
+                        Node::Class { ranges } => simplified.push(class(std::mem::take(ranges))),
+                        _ => simplified.push(node),
+                    }
+                }
+                Step::Union(count) => {
+                    let operands = simplified.split_off(simplified.len() - count);
+                    simplified.push(union(operands));
+                }
+                Step::Concat => {
+                    let right = simplified.pop().unwrap();
+                    let left = simplified.pop().unwrap();
+                    simplified.push(match (left, right) {
+                        (Node::Nothing, _) | (_, Node::Nothing) => Node::Nothing,
+                        (Node::Empty, node) | (node, Node::Empty) => node,
+                        (left @ Node::Star { .. }, right @ Node::Star { .. }) if left == right => {
+                            left
+                        }
+                        (left, right) => concat(left, right),
+                    });
+                }
+                Step::Star(greedy) => {
+                    let operand = simplified.pop().unwrap();
+                    simplified.push(star(operand, greedy));
+                }
+                Step::Plus(greedy) => {
+                    let operand = simplified.pop().unwrap();
+                    simplified.push(match operand {
+                        Node::Empty | Node::Nothing | Node::Star { .. } => operand,
+                        operand => Node::Plus {
+                            operand: Box::new(operand),
+                            greedy,
+                        },
+                    });
+                }
+                Step::And => {
+                    let right = simplified.pop().unwrap();
+                    let left = simplified.pop().unwrap();
+                    simplified.push(match (left, right) {
+                        (Node::Nothing, _) | (_, Node::Nothing) => Node::Nothing,
+                        (left, right) if left == right => left,
+                        (left, right) => Node::intersection(left, right),
+                    });
+                }
+                Step::Not => {
+                    let mut operand = simplified.pop().unwrap();
+                    simplified.push(match &mut operand {
+                        Node::Not { operand } => operand.take(),
+                        _ => Node::complement(operand),
+                    });
+                }
+                Step::Lookahead(negated) => {
+                    let operand = simplified.pop().unwrap();
+                    simplified.push(Node::lookahead(negated, operand));
+                }
+                Step::Lookbehind(negated) => {
+                    let operand = simplified.pop().unwrap();
+                    simplified.push(Node::lookbehind(negated, operand));
+                }
+            }
+        }
+        simplified.pop().unwrap()
+    }
+}
+
+/// The alternatives of a chain of unions in order. The groups around them
+/// are dropped, so that `(a|b)|c` has three alternatives.
+fn alternatives(node: Node) -> Vec<Node> {
+    let mut alternatives = Vec::new();
+    let mut nodes = vec![node];
+    while let Some(mut node) = nodes.pop() {
+        match &mut node {
+            Node::Union { left, right } => {
+                nodes.push(right.take());
+                nodes.push(left.take());
+            }
+            Node::Capture { operand, .. } => nodes.push(operand.take()),
+            _ => alternatives.push(node),
+        }
+    }
+    alternatives
+}
+
+/// The concatenation nested to the right, like the parser nests it, so that
+/// `(ab)c` prints as `abc` once the group is dropped
+fn concat(left: Node, right: Node) -> Node {
+    let mut parts = Vec::new();
+    let mut node = left;
+    while let Node::Concat { left, right } = &mut node {
+        parts.push(left.take());
+        node = right.take();
+    }
+    parts.push(node);
+    parts
+        .into_iter()
+        .rev()
+        .fold(right, |right, left| Node::concat(left, right))
+}
+
+/// The union of the simplified alternatives, without the duplicates and
+/// with the bytes in one class where the first of them was
+fn union(operands: Vec<Node>) -> Node {
+    let mut alternatives = Vec::new();
+    let mut seen: HashSet<Node> = HashSet::new();
+    let mut bytes = Vec::new();
+    let mut class_at = None;
+    for operand in operands {
+        // A simplified alternative is a union only when it came out of a
+        // concatenation with the empty string, like `()(a|b)`
+        for alternative in self::alternatives(operand) {
+            match &alternative {
+                Node::Nothing => {}
+                Node::Character { character } => {
+                    class_at.get_or_insert(alternatives.len());
+                    bytes.push((*character, *character));
+                }
+                Node::Class { ranges } => {
+                    class_at.get_or_insert(alternatives.len());
+                    bytes.extend(ranges);
+                }
+                _ => {
+                    if seen.insert(alternative.clone()) {
+                        alternatives.push(alternative);
+                    }
+                }
+            }
+        }
+    }
+    if let Some(index) = class_at {
+        alternatives.insert(index, class(bytes));
+    }
+    // A star already matches the empty string
+    if alternatives
+        .iter()
+        .any(|alternative| matches!(alternative, Node::Star { .. }))
+    {
+        alternatives.retain(|alternative| *alternative != Node::Empty);
+    }
+    alternatives
+        .into_iter()
+        .rev()
+        .reduce(|right, left| Node::union(left, right))
+        .unwrap_or(Node::Nothing)
+}
+
+/// The star of the simplified operand, without the stars, the pluses and
+/// the empty strings inside it that repeating makes redundant, so that
+/// `(a*|b?)*` is `(a|b)*`
+fn star(mut operand: Node, greedy: bool) -> Node {
+    let operand = match &mut operand {
+        Node::Star { operand, .. } | Node::Plus { operand, .. } => operand.take(),
+        Node::Union { .. } => union(
+            alternatives(operand)
+                .into_iter()
+                .map(|mut alternative| match &mut alternative {
+                    Node::Star { operand, .. } | Node::Plus { operand, .. } => operand.take(),
+                    _ => alternative,
+                })
+                .filter(|alternative| *alternative != Node::Empty)
+                .collect(),
+        ),
+        _ => operand,
+    };
+    match operand {
+        Node::Empty | Node::Nothing => Node::Empty,
+        operand => Node::Star {
+            operand: Box::new(operand),
+            greedy,
+        },
+    }
+}
+
+/// A class of a single byte is that character, and an empty class matches
+/// nothing
+fn class(ranges: Vec<(u8, u8)>) -> Node {
+    match Node::class(ranges) {
+        Node::Class { ref ranges } if ranges.is_empty() => Node::Nothing,
+        Node::Class { ref ranges } if ranges.len() == 1 && ranges[0].0 == ranges[0].1 => {
+            Node::character(ranges[0].0)
+        }
+        node => node,
+    }
+}
+
+//grcov-excl-start
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::automaton::equivalent;
+    use crate::nfa::Construction;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn parse(regex: &str) -> Node {
+        Parser::new(Scanner::new(regex.to_string()))
+            .parse()
+            .unwrap()
+    }
+
+    fn simplified(regex: &str) -> String {
+        parse(regex).simplify().to_string()
+    }
+
+    #[test]
+    fn identities() {
+        let simplified_forms = [
+            ("a|a", "a"),
+            ("(a|a)", "a"),
+            ("(?:x*)*", "x*"),
+            ("(?:x?)*", "x*"),
+            ("(?:x*)+", "x*"),
+            ("(?:x*|y?|(?:x|z)*)*", "[x-z]*"),
+            ("()*b", "b"),
+            ("a()b\\e", "ab"),
+            ("a|(b|c)|d", "[a-d]"),
+            ("a|bc|[d-f]|a", "[ad-f]|bc"),
+            ("(?:ab|ab)*|", "(?:ab)*"),
+            ("(?:\\e|[])*", "\\e"),
+            ("a[]|b", "b"),
+            ("(?=a|a)b", "(?=a)b"),
+            ("((ab)c)d", "abcd"),
+            ("(a+)b", "a+b"),
+        ];
+        for (regex, expected) in simplified_forms {
+            assert_eq!(simplified(regex), expected, "regex {}", regex);
+        }
+        assert_eq!(Node::Nothing.simplify(), Node::Nothing);
+        let a = Node::character(b'a');
+        let node = Node::complement(Node::complement(Node::intersection(a.clone(), a.clone())));
+        assert_eq!(node.simplify(), a);
+    }
+
+    #[test]
+    fn same_language_smaller_nfa() {
+        let regexes = [
+            "(a|a)(x*)*()*b",
+            "(a|b|c|d|e)*(?:abc|abc)?",
+            "(?:(?:a?)*|b+)*c|",
+            "(?i:hello|world|hello)",
+            "\\b(a|a)$",
+        ];
+        for regex in regexes {
+            let node = parse(regex);
+            let nfa = Construction::Thompson.to_nfa(node.clone());
+            let simplified = Construction::Thompson.to_nfa(node.simplify());
+            assert!(
+                simplified.state_count() < nfa.state_count(),
+                "regex {}",
+                regex
+            );
+            assert!(equivalent(&nfa, &simplified), "regex {}", regex);
+        }
+    }
+
+    #[test]
+    fn deep() {
+        let regex = "a|".repeat(50_000) + &"b".repeat(50_000);
+        let node = parse(&regex).simplify();
+        assert_eq!(node.to_string(), "a|".to_string() + &"b".repeat(50_000));
+    }
+}
+
+//grcov-excl-stop
@@ -0,0 +1,274 @@
+use crate::automaton::Automaton;
+use crate::classes::ByteClasses;
+use crate::look::{Context, Look};
+use crate::nfa::{Label, NFA};
+use crate::sparse_set::SparseSet;
+use std::collections::HashMap;
+
+/// Matches by following the set of states the NFA can be in at every
+/// position, without determinizing it. The search takes O(n·m) time for an
+/// input of length n and an NFA of m states, and the memory stays linear in
+/// the size of the NFA, so there is no state explosion as with the DFA.
+///
+/// The states of the NFA are numbered densely, so that the sets of states
+/// can be sparse sets.
+/// https://swtch.com/~rsc/regexp/regexp1.html
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    start: usize,
+    accepts: Vec<bool>,
+    epsilons: Vec<Vec<usize>>,
+    looks: Vec<Vec<(Look, usize)>>,
+    /// The transitions of every state as pairs of a byte class and a target
+    moves: Vec<Vec<(usize, usize)>>,
+    classes: ByteClasses,
+}
+
+/// The states of a position, and where the match of every state started
+struct States {
+    set: SparseSet,
+    starts: Vec<usize>,
+}
+
+impl States {
+    fn new(capacity: usize) -> Self {
+        States {
+            set: SparseSet::new(capacity),
+            starts: vec![0; capacity],
+        }
+    }
+}
+
+impl Simulation {
+    /// Copies the states that can be reached from the start state
+    pub fn new(nfa: &NFA) -> Self {
+        let mut ids = HashMap::new();
+        let mut id = |state: i32| {
+            let next_id = ids.len();
+            *ids.entry(state).or_insert(next_id)
+        };
+        let start = id(nfa.start_state());
+        let mut transitions = Vec::new();
+        nfa.for_each_transition(|from, label, to| transitions.push((id(from), label, id(to))));
+
+        let mut accepts = vec![false; ids.len()];
+        for (&state, &id) in ids.iter() {
+            accepts[id] = nfa.is_accept_state(state);
+        }
+        let mut epsilons = vec![Vec::new(); ids.len()];
+        let mut looks = vec![Vec::new(); ids.len()];
+        let mut moves = vec![Vec::new(); ids.len()];
+        for (from, label, to) in transitions {
+            match label {
+                Label::Epsilon => epsilons[from].push(to),
+                Label::Look(look) => looks[from].push((look, to)),
+                Label::Class(class) => moves[from].push((class, to)),
+            }
+        }
+        Simulation {
+            start,
+            accepts,
+            epsilons,
+            looks,
+            moves,
+            classes: nfa.classes(),
+        }
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.accepts.len()
+    }
+
+    /// Finds the leftmost match, and of those the longest one
+    pub fn find(&self, input: &[u8]) -> Option<(usize, usize)> {
+        self.search(input, false, true)
+    }
+
+    /// Finds where the leftmost match starts. The search stops as soon as no
+    /// match can start before it, without following the match to its end.
+    pub fn find_start(&self, input: &[u8]) -> Option<usize> {
+        self.search(input, false, false).map(|(start, _)| start)
+    }
+
+    /// Checks if the whole input matches
+    pub fn matches(&self, input: &[u8]) -> bool {
+        self.search(input, true, true) == Some((0, input.len()))
+    }
+
+    /// Every state remembers where its match started. The states are kept
+    /// in the order of their start, so when two matches get to the same
+    /// state, the one that started first keeps it. Both would continue the
+    /// same way, so the leftmost one is enough.
+    fn search(&self, input: &[u8], anchored: bool, longest: bool) -> Option<(usize, usize)> {
+        let mut current = States::new(self.state_count());
+        let mut next = States::new(self.state_count());
+        let mut stack = Vec::new();
+        let mut found: Option<(usize, usize)> = None;
+        for at in 0..=input.len() {
+            let leftmost = current.set.as_slice().first();
+            if !longest
+                && found.is_some_and(|(first, _)| {
+                    leftmost.is_none_or(|&state| current.starts[state] >= first)
+                })
+            {
+                break;
+            }
+            if found.is_none() && (!anchored || at == 0) {
+                self.add(&mut current, &mut stack, self.start, at, at, input);
+            } else if current.set.is_empty() {
+                break;
+            }
+            let class = input.get(at).map(|&byte| self.classes.get(byte) as usize);
+            for &state in current.set.as_slice() {
+                let start = current.starts[state];
+                // Only the matches that start at the same place can be longer
+                if found.is_some_and(|(first, _)| start > first) {
+                    break;
+                }
+                if self.accepts[state] && found.is_none_or(|(first, _)| start <= first) {
+                    found = Some((start, at));
+                }
+                for &(on, to) in self.moves[state].iter() {
+                    if Some(on) == class {
+                        self.add(&mut next, &mut stack, to, start, at + 1, input);
+                    }
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.set.clear();
+        }
+        found
+    }
+
+    /// Adds the state and the states after it that do not read a byte. The
+    /// assertions are checked with the bytes around the position.
+    fn add(
+        &self,
+        states: &mut States,
+        stack: &mut Vec<usize>,
+        state: usize,
+        start: usize,
+        at: usize,
+        input: &[u8],
+    ) {
+        let behind = Context::of(at.checked_sub(1).map(|i| input[i]));
+        let ahead = Context::of(input.get(at).cloned());
+        stack.push(state);
+        while let Some(state) = stack.pop() {
+            if !states.set.insert(state) {
+                continue;
+            }
+            states.starts[state] = start;
+            stack.extend(self.epsilons[state].iter());
+            let looks = self.looks[state].iter();
+            stack.extend(
+                looks
+                    .filter(|(look, _)| look.holds(behind, ahead))
+                    .map(|&(_, to)| to),
+            );
+        }
+    }
+
+    /// The states after the states that do not read a byte, as a sorted
+    /// list. The assertions are followed only when the contexts are known.
+    fn closure(&self, states: Vec<usize>, contexts: Option<(Context, Context)>) -> Vec<usize> {
+        let mut set = SparseSet::new(self.state_count());
+        let mut stack = states;
+        while let Some(state) = stack.pop() {
+            if !set.insert(state) {
+                continue;
+            }
+            stack.extend(self.epsilons[state].iter());
+            if let Some((behind, ahead)) = contexts {
+                let looks = self.looks[state].iter();
+                stack.extend(
+                    looks
+                        .filter(|(look, _)| look.holds(behind, ahead))
+                        .map(|&(_, to)| to),
+                );
+            }
+        }
+        let mut closure = set.as_slice().to_vec();
+        closure.sort_unstable();
+        closure
+    }
+}
+
+/// Runs like the NFA it was built from, with the assertions checked when
+/// the byte after the position is known
+impl Automaton for Simulation {
+    type State = (Vec<usize>, Context);
+
+    fn start_state(&self) -> Self::State {
+        (self.closure(vec![self.start], None), Context::Edge)
+    }
+
+    fn step(&self, (states, behind): &Self::State, byte: u8) -> Self::State {
+        let ahead = Context::of(Some(byte));
+        let class = self.classes.get(byte) as usize;
+        let moved = self
+            .closure(states.clone(), Some((*behind, ahead)))
+            .into_iter()
+            .flat_map(|state| self.moves[state].iter())
+            .filter(|&&(on, _)| on == class)
+            .map(|&(_, to)| to)
+            .collect();
+        let behind = if self.looks.iter().all(Vec::is_empty) {
+            Context::Edge
+        } else {
+            ahead
+        };
+        (self.closure(moved, None), behind)
+    }
+
+    fn is_accepting(&self, (states, behind): &Self::State) -> bool {
+        let closure = self.closure(states.clone(), Some((*behind, Context::Edge)));
+        closure.into_iter().any(|state| self.accepts[state])
+    }
+
+    fn is_dead(&self, (states, _): &Self::State) -> bool {
+        states.is_empty()
+    }
+}
+
+//grcov-excl-start
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nfa::Construction;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn simulation(regex: &str, construction: Construction) -> Simulation {
+        let node = Parser::new(Scanner::new(regex.to_string()))
+            .parse()
+            .unwrap();
+        Simulation::new(&construction.to_nfa(node))
+    }
+
+    #[test]
+    fn find() {
+        for construction in [Construction::Thompson, Construction::Glushkov] {
+            let found = simulation("(a|ab)(c|bcd)", construction);
+            assert_eq!(found.find(b"xabcd"), Some((1, 5)));
+            assert!(found.matches(b"abcd"));
+            assert!(!found.matches(b"abcdx"));
+            assert_eq!(simulation("b*", construction).find(b"ab"), Some((0, 0)));
+            assert_eq!(simulation("c", construction).find(b"ab"), None);
+        }
+        let found = simulation("\\bb+$", Construction::Thompson);
+        assert_eq!(found.find(b"ab bb"), Some((3, 5)));
+        assert!(!found.matches(b"abb"));
+    }
+
+    #[test]
+    fn state_count() {
+        // Thompson's construction has two states for every character
+        assert_eq!(simulation("ab", Construction::Thompson).state_count(), 4);
+        // and Glushkov's a state for every character and a start state
+        assert_eq!(simulation("ab", Construction::Glushkov).state_count(), 3);
+    }
+}
+
+//grcov-excl-stop
@@ -0,0 +1,78 @@
+/// A set of integers below a fixed capacity, with constant time insertion,
+/// lookup and clearing. The elements are kept in a dense list in the order
+/// they were inserted, and the sparse list tells where in the dense list an
+/// element is. The sparse list does not need to be cleared, since an entry
+/// only counts if the dense list points back to it.
+/// https://research.swtch.com/sparse
+#[derive(Debug, Clone)]
+pub struct SparseSet {
+    dense: Vec<usize>,
+    sparse: Vec<usize>,
+}
+
+impl SparseSet {
+    pub fn new(capacity: usize) -> Self {
+        SparseSet {
+            dense: Vec::with_capacity(capacity),
+            sparse: vec![0; capacity],
+        }
+    }
+
+    pub fn contains(&self, value: usize) -> bool {
+        let index = self.sparse[value];
+        index < self.dense.len() && self.dense[index] == value
+    }
+
+    /// Adds the value unless it is already in the set. Returns true if it
+    /// was added.
+    pub fn insert(&mut self, value: usize) -> bool {
+        if self.contains(value) {
+            return false;
+        }
+        self.sparse[value] = self.dense.len();
+        self.dense.push(value);
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.dense.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.dense.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dense.is_empty()
+    }
+
+    /// The elements in the order they were inserted
+    pub fn as_slice(&self) -> &[usize] {
+        &self.dense
+    }
+}
+
+//grcov-excl-start
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_and_clear() {
+        let mut set = SparseSet::new(10);
+        assert!(set.is_empty());
+        assert!(set.insert(7));
+        assert!(set.insert(2));
+        assert!(!set.insert(7));
+        assert!(set.contains(2));
+        assert!(!set.contains(3));
+        assert_eq!(set.as_slice(), &[7, 2]);
+        set.clear();
+        assert!(!set.contains(7));
+        assert!(set.insert(2));
+        assert_eq!(set.len(), 1);
+    }
+}
+
+//grcov-excl-stop
@@ -3,28 +3,48 @@ use std::fmt::{self, Display, Formatter};
 #[derive(Debug, Copy, PartialOrd, PartialEq, Clone)]
 pub enum TokenType {
     Star,
+    Plus,
+    Question,
     RightParen,
     LeftParen,
+    /// '[', the class up to the ']' is read with `Scanner::class`
+    LeftBracket,
+    FlagGroup,
+    Property,
+    Dot,
+    /// '\e', the empty string
+    Empty,
+    /// '\1' to '\9', the value is the digit
+    Backreference,
+    /// '^', '$', '\b' or '\B', the value tells which
+    Look,
     Char,
+    /// A raw byte given with '\xHH' or in octal, the value holds the byte
+    Byte,
+    /// '\d', '\w' or '\s', or the negated '\D', '\W' or '\S', the value is
+    /// the letter
+    ClassEscape,
     Union,
     NoOp,
+    /// An invalid escape, `Scanner::error` tells what is wrong
+    Error,
     EOF,
 }
 
 #[derive(Clone, PartialEq, Debug)]
 pub struct Token {
     pub type_: TokenType,
-    pub value: u8,
+    pub value: char,
 }
 
 impl Token {
-    pub fn new(type_: TokenType, value: u8) -> Self {
+    pub fn new(type_: TokenType, value: char) -> Self {
         Token { type_, value }
     }
 }
 
 impl Display for Token {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "Token({:?}, {})", self.type_, self.value as char)
+        write!(f, "Token({:?}, {})", self.type_, self.value)
     }
 }
@@ -0,0 +1,275 @@
+use crate::ast::Node;
+use std::sync::OnceLock;
+use unicode_general_category::get_general_category;
+
+/// The largest code point of every UTF-8 encoding length
+const MAX_CODE_POINTS: [u32; 3] = [0x7f, 0x7ff, 0xffff];
+const SURROGATES: (u32, u32) = (0xd800, 0xdfff);
+
+/// Splits a range of code points into sequences of byte ranges, so that the
+/// UTF-8 encodings of the code points in the range are exactly the byte
+/// strings matched by the sequences. For example 'ä'..='ö' becomes
+/// [0xc3][0xa4-0xb6].
+pub fn sequences(start: u32, end: u32) -> Vec<Vec<(u8, u8)>> {
+    let mut result = Vec::new();
+    split(start, end.min(char::MAX as u32), &mut result);
+    result
+}
+
+fn split(start: u32, end: u32, result: &mut Vec<Vec<(u8, u8)>>) {
+    if start > end {
+        return;
+    }
+    // Surrogates can not be encoded
+    if start <= SURROGATES.1 && SURROGATES.0 <= end {
+        if start < SURROGATES.0 {
+            split(start, SURROGATES.0 - 1, result);
+        }
+        if SURROGATES.1 < end {
+            split(SURROGATES.1 + 1, end, result);
+        }
+        return;
+    }
+    // Both ends have to have an encoding of the same length
+    for max in MAX_CODE_POINTS {
+        if start <= max && max < end {
+            split(start, max, result);
+            split(max + 1, end, result);
+            return;
+        }
+    }
+    if end <= 0x7f {
+        result.push(vec![(start as u8, end as u8)]);
+        return;
+    }
+    // Every continuation byte holds 6 bits. The range is split until every
+    // byte position covers a full range of the bits below it.
+    let len = encode(end).len();
+    for i in 1..len {
+        let mask = (1u32 << (6 * i)) - 1;
+        if start & !mask != end & !mask {
+            if start & mask != 0 {
+                split(start, start | mask, result);
+                split((start | mask) + 1, end, result);
+                return;
+            }
+            if end & mask != mask {
+                split(start, (end & !mask) - 1, result);
+                split(end & !mask, end, result);
+                return;
+            }
+        }
+    }
+    let start = encode(start);
+    let end = encode(end);
+    result.push(start.into_iter().zip(end).collect());
+}
+
+fn encode(code_point: u32) -> Vec<u8> {
+    let character = char::from_u32(code_point).unwrap();
+    let mut bytes = [0; 4];
+    character.encode_utf8(&mut bytes).as_bytes().to_vec()
+}
+
+/// Builds the node that matches the UTF-8 encoding of any code point in the
+/// ranges. Sequences with the same leading bytes share the node for them.
+pub fn class(ranges: &[(u32, u32)]) -> Node {
+    let mut all = Vec::new();
+    for &(start, end) in ranges {
+        all.extend(sequences(start, end));
+    }
+    all.sort();
+    from_sequences(&all)
+}
+
+fn from_sequences(sequences: &[Vec<(u8, u8)>]) -> Node {
+    let mut alternatives = Vec::new();
+    let mut i = 0;
+    while i < sequences.len() {
+        let head = sequences[i][0];
+        let mut j = i;
+        while j < sequences.len() && sequences[j][0] == head {
+            j += 1;
+        }
+        let node = Node::class(vec![head]);
+        let tails: Vec<Vec<(u8, u8)>> = sequences[i..j]
+            .iter()
+            .filter(|sequence| sequence.len() > 1)
+            .map(|sequence| sequence[1..].to_vec())
+            .collect();
+        if tails.is_empty() {
+            alternatives.push(node);
+        } else {
+            alternatives.push(Node::concat(node, from_sequences(&tails)));
+        }
+        i = j;
+    }
+    balanced_union(alternatives)
+}
+
+/// Joins the nodes with '|' into a balanced tree, keeping the tree shallow
+fn balanced_union(mut nodes: Vec<Node>) -> Node {
+    match nodes.len() {
+        0 => Node::Nothing,
+        1 => nodes.pop().unwrap(),
+        len => {
+            let right = nodes.split_off(len / 2);
+            Node::union(balanced_union(nodes), balanced_union(right))
+        }
+    }
+}
+
+/// The node for a single code point
+pub fn character(character: char) -> Node {
+    let code_point = character as u32;
+    class(&[(code_point, code_point)])
+}
+
+/// The code point ranges of a Unicode general category, given with its
+/// abbreviation, e.g. `L` for letters or `Lu` for uppercase letters
+/// https://www.unicode.org/reports/tr44/#General_Category_Values
+pub fn property(name: &str) -> Result<Vec<(u32, u32)>, String> {
+    let is_category = |abbreviation: &str| match name.len() {
+        1 => abbreviation.starts_with(name),
+        _ => abbreviation == name,
+    };
+    if name.is_empty() || name.len() > 2 || !KNOWN_CATEGORIES.iter().any(|c| is_category(c)) {
+        return Err(format!("Unknown Unicode property '{}'", name));
+    }
+
+    let ranges = categories()
+        .iter()
+        .filter(|(_, _, abbreviation)| is_category(abbreviation))
+        .fold(
+            Vec::new(),
+            |mut ranges: Vec<(u32, u32)>, &(start, end, _)| {
+                match ranges.last_mut() {
+                    Some((_, last)) if *last + 1 == start => *last = end,
+                    _ => ranges.push((start, end)),
+                }
+                ranges
+            },
+        );
+    Ok(ranges)
+}
+
+/// The general category of every code point as ranges. Looking up all of the
+/// code points is slow, so the table is built only once.
+fn categories() -> &'static [(u32, u32, &'static str)] {
+    static CATEGORIES: OnceLock<Vec<(u32, u32, &'static str)>> = OnceLock::new();
+    CATEGORIES.get_or_init(|| {
+        let mut ranges: Vec<(u32, u32, &'static str)> = Vec::new();
+        for character in (0..=char::MAX as u32).filter_map(char::from_u32) {
+            let abbreviation = get_general_category(character).abbreviation();
+            let code_point = character as u32;
+            match ranges.last_mut() {
+                Some((_, end, last)) if *last == abbreviation && *end + 1 == code_point => {
+                    *end = code_point
+                }
+                _ => ranges.push((code_point, code_point, abbreviation)),
+            }
+        }
+        ranges
+    })
+}
+
+/// Complement of the code point ranges
+pub fn negate(ranges: &[(u32, u32)]) -> Vec<(u32, u32)> {
+    let mut result = Vec::new();
+    let mut next = 0;
+    for &(start, end) in ranges {
+        if next < start {
+            result.push((next, start - 1));
+        }
+        next = end + 1;
+    }
+    if next <= char::MAX as u32 {
+        result.push((next, char::MAX as u32));
+    }
+    result
+}
+
+const KNOWN_CATEGORIES: [&str; 30] = [
+    "Lu", "Ll", "Lt", "Lm", "Lo", "Mn", "Mc", "Me", "Nd", "Nl", "No", "Pc", "Pd", "Ps", "Pe", "Pi",
+    "Pf", "Po", "Sm", "Sc", "Sk", "So", "Zs", "Zl", "Zp", "Cc", "Cf", "Cs", "Co", "Cn",
+];
+
+//grcov-excl-start
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_sequence() {
+        assert_eq!(sequences(0x61, 0x7a), vec![vec![(0x61, 0x7a)]]);
+    }
+
+    #[test]
+    fn two_byte_sequence() {
+        // 'ä'..='ö'
+        assert_eq!(
+            sequences(0xe4, 0xf6),
+            vec![vec![(0xc3, 0xc3), (0xa4, 0xb6)]]
+        );
+    }
+
+    #[test]
+    fn split_by_length_and_surrogates() {
+        let result = sequences(0, char::MAX as u32);
+        assert_eq!(result[0], vec![(0x00, 0x7f)]);
+        assert_eq!(result[1], vec![(0xc2, 0xdf), (0x80, 0xbf)]);
+        assert!(result.contains(&vec![(0xed, 0xed), (0x80, 0x9f), (0x80, 0xbf)]));
+        assert!(!result
+            .iter()
+            .any(|sequence| sequence[0] == (0xed, 0xed) && sequence[1].1 > 0x9f));
+        assert_eq!(result.last().unwrap()[0], (0xf4, 0xf4));
+    }
+
+    #[test]
+    fn sequences_cover_encodings() {
+        for (start, end) in [(0x80, 0x10ff), (0x7f0, 0x10010), (0xd7f0, 0xe010)] {
+            let result = sequences(start, end);
+            for code_point in (start - 5)..(end + 5) {
+                let character = match char::from_u32(code_point) {
+                    Some(character) => character,
+                    None => continue,
+                };
+                let bytes = encode(character as u32);
+                let matched = result.iter().any(|sequence| {
+                    sequence.len() == bytes.len()
+                        && sequence
+                            .iter()
+                            .zip(bytes.iter())
+                            .all(|(&(lo, hi), &byte)| lo <= byte && byte <= hi)
+                });
+                assert_eq!(matched, start <= code_point && code_point <= end);
+            }
+        }
+    }
+
+    #[test]
+    fn letter_property() {
+        let letters = property("L").unwrap();
+        let contains = |c: char| {
+            letters
+                .iter()
+                .any(|&(start, end)| start <= c as u32 && c as u32 <= end)
+        };
+        assert!(contains('a') && contains('Ä') && contains('ö') && contains('λ'));
+        assert!(!contains('1') && !contains(' ') && !contains('-'));
+
+        let uppercase = property("Lu").unwrap();
+        assert!(uppercase.len() < letters.len());
+        assert!(property("Xx").is_err());
+        assert!(property("Letter").is_err());
+    }
+
+    #[test]
+    fn negate_ranges() {
+        let ranges = vec![(0, 10), (20, 30)];
+        assert_eq!(negate(&ranges), vec![(11, 19), (31, char::MAX as u32)]);
+    }
+}
+
+//grcov-excl-stop